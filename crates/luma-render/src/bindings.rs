@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use ash::vk;
+use bevy::prelude::*;
+use bytemuck::Pod;
+use gpu_allocator::MemoryLocation;
+
+use super::{
+    buffer::Buffer,
+    render_asset::{RenderAssets, sync_render_assets},
+    render_device::RenderDevice,
+    schedule::{Render, RenderStartup, RenderSystems},
+    texture::{GpuTexture, Texture, TextureRef},
+};
+
+pub struct RayTracingBindingsPlugin;
+
+impl Plugin for RayTracingBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RayTracingBindings>()
+            .add_systems(RenderStartup, create_gpu_ray_tracing_bindings)
+            .add_systems(
+                Render,
+                prepare_ray_tracing_bindings
+                    .in_set(RenderSystems::Prepare)
+                    .after(sync_render_assets::<GpuTexture>),
+            );
+    }
+}
+
+/// Application data for custom ray tracing shaders, such as the time, the cursor position, scene
+/// buffers or textures. Buffers are bound next to the renderer's own bindings, and the push
+/// constants are the application's alone. Changing which bindings exist or the size of the push
+/// constants rebuilds the pipeline, and until then the pipeline is given zeros for the data it was built
+/// for but that is no longer set.
+#[derive(Resource, Default)]
+pub struct RayTracingBindings {
+    buffers: BTreeMap<u32, BindingData>,
+    push_constants: Vec<u8>,
+}
+
+enum BindingData {
+    Buffer {
+        descriptor_type: vk::DescriptorType,
+        bytes: Vec<u8>,
+    },
+    /// Bound as a uniform buffer holding the texture's `TextureRef`, once it has been uploaded.
+    Texture(Handle<Texture>),
+}
+
+impl BindingData {
+    fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            Self::Buffer {
+                descriptor_type, ..
+            } => *descriptor_type,
+            Self::Texture(_) => vk::DescriptorType::UNIFORM_BUFFER,
+        }
+    }
+}
+
+impl RayTracingBindings {
+    /// Bindings below this are used by the renderer.
+    pub const FIRST_BINDING: u32 = 16;
+
+    /// Binds a value as a uniform buffer, declared as `[[vk::binding(N)]] ConstantBuffer<T>`.
+    pub fn set_uniform<T: Pod>(&mut self, binding: u32, value: &T) -> Result<()> {
+        self.set(
+            binding,
+            BindingData::Buffer {
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                bytes: bytemuck::bytes_of(value).to_vec(),
+            },
+        )
+    }
+
+    /// Binds values as a storage buffer, declared as `[[vk::binding(N)]] StructuredBuffer<T>`.
+    pub fn set_storage<T: Pod>(&mut self, binding: u32, values: &[T]) -> Result<()> {
+        self.set(
+            binding,
+            BindingData::Buffer {
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                bytes: bytemuck::cast_slice(values).to_vec(),
+            },
+        )
+    }
+
+    /// Binds a texture, declared as `[[vk::binding(N)]] ConstantBuffer<TextureRef>` and read with
+    /// `sampleTexture` from `material.slang`. It samples as white until it has been uploaded.
+    pub fn set_texture(&mut self, binding: u32, texture: Handle<Texture>) -> Result<()> {
+        self.set(binding, BindingData::Texture(texture))
+    }
+
+    pub fn remove(&mut self, binding: u32) {
+        self.buffers.remove(&binding);
+    }
+
+    /// Sets the push constants of the ray tracing shaders, which the renderer leaves entirely to
+    /// the application, so `T` can take up to `maxPushConstantsSize` bytes, at least 128 on any
+    /// device. The shaders declare its members with the `USER_PUSH_CONSTANTS` define in
+    /// `RayTracingShaders::defines`, such as `float time; float2 cursor;`, and read them from
+    /// `pc`. Vulkan pushes whole words, so the size of `T` must be a multiple of 4 bytes.
+    pub fn set_push_constants<T: Pod>(&mut self, value: &T) -> Result<()> {
+        if size_of::<T>() % 4 != 0 {
+            bail!(
+                "Push constants must be a multiple of 4 bytes, but `{}` is {} bytes",
+                std::any::type_name::<T>(),
+                size_of::<T>()
+            );
+        }
+
+        self.push_constants = bytemuck::bytes_of(value).to_vec();
+        Ok(())
+    }
+
+    pub fn layout(&self) -> BindingsLayout {
+        BindingsLayout {
+            bindings: self
+                .buffers
+                .iter()
+                .map(|(&binding, data)| (binding, data.descriptor_type()))
+                .collect(),
+            push_constant_size: self.push_constants.len(),
+        }
+    }
+
+    fn set(&mut self, binding: u32, data: BindingData) -> Result<()> {
+        if binding < Self::FIRST_BINDING {
+            bail!(
+                "Binding {binding} is reserved for the renderer, application bindings start at {}",
+                Self::FIRST_BINDING
+            );
+        }
+
+        self.buffers.insert(binding, data);
+        Ok(())
+    }
+}
+
+/// The bindings and push constant size that a pipeline was built for.
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct BindingsLayout {
+    pub bindings: Vec<(u32, vk::DescriptorType)>,
+    pub push_constant_size: usize,
+}
+
+/// GPU buffers of `RayTracingBindings`, which are written while no frame is in flight.
+#[derive(Resource)]
+pub(crate) struct GpuRayTracingBindings {
+    render_device: RenderDevice,
+    buffers: BTreeMap<u32, (vk::DescriptorType, Buffer)>,
+    push_constants: Vec<u8>,
+    /// Bound in place of buffers that the pipeline was built for but that are no longer set.
+    zeroed_buffer: Buffer,
+}
+
+impl GpuRayTracingBindings {
+    /// Size of `zeroed_buffer`, the largest uniform buffer range that every device supports.
+    const ZEROED_BUFFER_SIZE: u64 = 16384;
+
+    /// The buffer of a binding, or a buffer of zeros if the binding has been removed or changed
+    /// its type since the pipeline was built.
+    pub fn buffer(&self, binding: u32, descriptor_type: vk::DescriptorType) -> &Buffer {
+        match self.buffers.get(&binding) {
+            Some((existing_type, buffer)) if *existing_type == descriptor_type => buffer,
+            _ => &self.zeroed_buffer,
+        }
+    }
+
+    /// The push constants cut or padded with zeros to the size the pipeline was built for.
+    pub fn push_constants(&self, size: usize) -> Vec<u8> {
+        let mut push_constants = self.push_constants.clone();
+        push_constants.resize(size, 0);
+        push_constants
+    }
+}
+
+impl Drop for GpuRayTracingBindings {
+    fn drop(&mut self) {
+        for (_, (_, buffer)) in std::mem::take(&mut self.buffers) {
+            self.render_device.destroy_buffer(buffer);
+        }
+
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.zeroed_buffer));
+    }
+}
+
+fn create_gpu_ray_tracing_bindings(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+) -> Result<(), BevyError> {
+    let mut gpu_bindings = GpuRayTracingBindings {
+        render_device: render_device.clone(),
+        buffers: BTreeMap::new(),
+        push_constants: Vec::new(),
+        zeroed_buffer: render_device.create_buffer(
+            GpuRayTracingBindings::ZEROED_BUFFER_SIZE,
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            Some("Zeroed Ray Tracing Binding"),
+        )?,
+    };
+
+    // Dropping the resource destroys the buffer if mapping it fails
+    gpu_bindings.zeroed_buffer.slice_mut()?.fill(0);
+    commands.insert_resource(gpu_bindings);
+    Ok(())
+}
+
+fn prepare_ray_tracing_bindings(
+    bindings: Res<RayTracingBindings>,
+    mut gpu_bindings: ResMut<GpuRayTracingBindings>,
+    gpu_textures: Res<RenderAssets<GpuTexture>>,
+    mut texture_events: MessageReader<AssetEvent<Texture>>,
+) -> Result<(), BevyError> {
+    // Texture bindings refer to the texel buffers, which change as textures are uploaded
+    let is_texture_changed = !texture_events.is_empty();
+    texture_events.clear();
+
+    if !bindings.is_changed() && !is_texture_changed {
+        return Ok(());
+    }
+
+    let gpu_bindings = &mut *gpu_bindings;
+    let render_device = &gpu_bindings.render_device;
+    gpu_bindings
+        .push_constants
+        .clone_from(&bindings.push_constants);
+
+    // Buffers are only recreated when their type or size changes, and written in place otherwise
+    let mut buffers = std::mem::take(&mut gpu_bindings.buffers);
+
+    for (&binding, data) in &bindings.buffers {
+        let texture_ref;
+
+        let bytes = match data {
+            BindingData::Buffer { bytes, .. } => bytes.as_slice(),
+            BindingData::Texture(texture) => {
+                texture_ref = gpu_textures
+                    .get(&texture.id())
+                    .map_or(TextureRef::default(), GpuTexture::texture_ref);
+
+                bytemuck::bytes_of(&texture_ref)
+            }
+        };
+
+        // Buffers cannot be empty, so there is always at least one byte
+        let len = (bytes.len() as u64).max(1);
+
+        let (descriptor_type, mut buffer) = match buffers.remove(&binding) {
+            Some((descriptor_type, buffer))
+                if descriptor_type == data.descriptor_type() && buffer.len == len =>
+            {
+                (descriptor_type, buffer)
+            }
+            existing => {
+                if let Some((_, buffer)) = existing {
+                    render_device.destroy_buffer(buffer);
+                }
+
+                let usage = match data.descriptor_type() {
+                    vk::DescriptorType::UNIFORM_BUFFER => vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    _ => vk::BufferUsageFlags::STORAGE_BUFFER,
+                };
+
+                let buffer = render_device.create_buffer(
+                    len,
+                    usage,
+                    MemoryLocation::CpuToGpu,
+                    Some(&format!("Ray Tracing Binding {binding}")),
+                )?;
+
+                (data.descriptor_type(), buffer)
+            }
+        };
+
+        buffer.slice_mut()?[..bytes.len()].copy_from_slice(bytes);
+        gpu_bindings
+            .buffers
+            .insert(binding, (descriptor_type, buffer));
+    }
+
+    for (_, (_, buffer)) in buffers {
+        render_device.destroy_buffer(buffer);
+    }
+
+    Ok(())
+}
@@ -2,7 +2,10 @@ use anyhow::{Result, anyhow};
 use ash::vk;
 use gpu_allocator::MemoryLocation;
 
-use super::{buffer::Buffer, mesh::Vertex, render_device::RenderDevice, render_queue::RenderQueue};
+use super::{
+    buffer::Buffer, mesh::Vertex, procedural::ProceduralPrimitive, render_device::RenderDevice,
+    render_queue::RenderQueue,
+};
 
 #[derive(Default)]
 pub struct Blas {
@@ -18,26 +21,58 @@ impl RenderDevice {
         vertex_buffer: &Buffer<Vertex>,
         index_buffer: &Buffer<u32>,
     ) -> Result<Blas> {
-        unsafe {
-            let geometry = vk::AccelerationStructureGeometryKHR::default()
-                .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
-                .flags(vk::GeometryFlagsKHR::OPAQUE)
-                .geometry(vk::AccelerationStructureGeometryDataKHR {
-                    triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::default()
-                        .vertex_format(vk::Format::R32G32B32_SFLOAT)
-                        .vertex_data(vk::DeviceOrHostAddressConstKHR {
-                            device_address: vertex_buffer.address,
-                        })
-                        .vertex_stride(size_of::<Vertex>() as u64)
-                        .max_vertex(vertex_buffer.len as u32 - 1)
-                        .index_type(vk::IndexType::UINT32)
-                        .index_data(vk::DeviceOrHostAddressConstKHR {
-                            device_address: index_buffer.address,
-                        }),
-                });
+        let geometry = vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+            // Opacity is decided per instance by its material, see `AccelerationStructureInstance`.
+            // Any-hit shaders run once per triangle so that stochastic transparency stays unbiased.
+            .flags(vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION)
+            .geometry(vk::AccelerationStructureGeometryDataKHR {
+                triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::default()
+                    .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                    .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                        device_address: vertex_buffer.address,
+                    })
+                    .vertex_stride(size_of::<Vertex>() as u64)
+                    .max_vertex(vertex_buffer.len as u32 - 1)
+                    .index_type(vk::IndexType::UINT32)
+                    .index_data(vk::DeviceOrHostAddressConstKHR {
+                        device_address: index_buffer.address,
+                    }),
+            });
+
+        let primitive_count = (index_buffer.len / 3) as u32;
+        self.build_blas(render_queue, geometry, primitive_count)
+    }
 
-            let primitive_count = (index_buffer.len / 3) as u32;
+    /// Builds a BLAS of axis-aligned bounding boxes, whose hits are reported by the intersection
+    /// shader of the instance's hit group.
+    pub fn create_aabb_blas(
+        &self,
+        render_queue: &RenderQueue,
+        primitive_buffer: &Buffer<ProceduralPrimitive>,
+    ) -> Result<Blas> {
+        let geometry = vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::AABBS)
+            .flags(vk::GeometryFlagsKHR::OPAQUE)
+            .geometry(vk::AccelerationStructureGeometryDataKHR {
+                aabbs: vk::AccelerationStructureGeometryAabbsDataKHR::default()
+                    .data(vk::DeviceOrHostAddressConstKHR {
+                        device_address: primitive_buffer.address,
+                    })
+                    .stride(size_of::<ProceduralPrimitive>() as u64),
+            });
+
+        let primitive_count = primitive_buffer.len as u32;
+        self.build_blas(render_queue, geometry, primitive_count)
+    }
 
+    fn build_blas(
+        &self,
+        render_queue: &RenderQueue,
+        geometry: vk::AccelerationStructureGeometryKHR,
+        primitive_count: u32,
+    ) -> Result<Blas> {
+        unsafe {
             let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::default()
                 .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
                 .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
@@ -94,6 +94,14 @@ impl RenderDevice {
 }
 
 impl<T: Pod> Buffer<T> {
+    pub fn slice(&self) -> Result<&[T]> {
+        self.allocation
+            .mapped_slice()
+            .ok_or_else(|| anyhow!("Buffer is not host visible"))
+            .map(|slice| &slice[..self.size as usize])
+            .map(bytemuck::cast_slice)
+    }
+
     pub fn slice_mut(&mut self) -> Result<&mut [T]> {
         self.allocation
             .mapped_slice_mut()
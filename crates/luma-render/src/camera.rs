@@ -1,20 +1,344 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
 use bevy::prelude::*;
 
-#[derive(Component)]
+#[derive(Component, Clone, PartialEq)]
 pub struct Camera {
+    /// Inactive cameras are not rendered and release their images.
+    pub is_active: bool,
+    pub target: CameraTarget,
+    /// Region of the primary target the camera renders into, in normalized coordinates from the
+    /// top left corner at (0, 0) to the bottom right corner at (1, 1). Cameras rendering into an
+    /// image always fill the whole image.
+    pub viewport: Rect,
+    /// Cameras rendering into the same target are composed in ascending order of priority, so
+    /// cameras with a higher priority are drawn on top.
+    pub priority: i32,
+    /// Scale of the rendered image relative to the viewport or image size, multiplied with the
+    /// resolution scaling of the ray tracing settings.
+    pub resolution_scaling: f32,
+    pub projection: Projection,
     pub sensor: Sensor,
+    pub sensor_fit: SensorFit,
+    /// Opacity of the overlay covering the parts of the image outside of the sensor
+    pub letterbox: f32,
+    /// Focal length in millimetres
     pub focal_length: f32,
+    /// Aperture as f-number, e.g. 2.8 for f/2.8
+    pub aperture: f32,
+    /// Distance to the plane in focus in metres
+    pub focus_distance: f32,
+    /// Shutter speed in seconds
+    pub shutter_speed: f32,
+    pub iso: f32,
+    pub bokeh: Bokeh,
+    /// Traces rays through a thin lens with the aperture size instead of through a pinhole. Each
+    /// frame takes one lens sample per pixel, so the blur is noisy unless frames are accumulated
+    /// with `RayTracingSettings::accumulate`.
+    pub depth_of_field: bool,
+    /// Exposes the image from aperture, shutter speed and ISO. Otherwise only the exposure
+    /// compensation of the tonemapping pass is applied.
+    pub physical_exposure: bool,
 }
 
 impl Default for Camera {
     fn default() -> Self {
         Self {
+            is_active: true,
+            target: default(),
+            viewport: Rect::new(0.0, 0.0, 1.0, 1.0),
+            priority: 0,
+            resolution_scaling: 1.0,
+            projection: default(),
             sensor: Sensor::FULL_FRAME,
+            sensor_fit: default(),
+            letterbox: 0.75,
             focal_length: 24.0,
+            aperture: 2.8,
+            focus_distance: 10.0,
+            shutter_speed: 1.0 / 125.0,
+            iso: 100.0,
+            bokeh: default(),
+            depth_of_field: false,
+            physical_exposure: false,
+        }
+    }
+}
+
+impl Camera {
+    /// Half extents of the image plane for an image with the given aspect ratio. The image plane
+    /// is at unit distance in front of the camera for sensor based projections, and given in world
+    /// units for orthographic projections.
+    pub fn image_plane(&self, aspect_ratio: f32) -> Vec2 {
+        if let Projection::Orthographic { height } = self.projection {
+            return Vec2::new(height * 0.5 * aspect_ratio, height * 0.5);
+        }
+
+        let sensor_plane = self.sensor_plane();
+
+        let fit_horizontal = match self.sensor_fit {
+            SensorFit::Horizontal => true,
+            SensorFit::Vertical => false,
+            SensorFit::Fill => aspect_ratio > self.sensor.aspect_ratio(),
+            SensorFit::Overscan => aspect_ratio < self.sensor.aspect_ratio(),
+        };
+
+        if fit_horizontal {
+            Vec2::new(sensor_plane.x, sensor_plane.x / aspect_ratio)
+        } else {
+            Vec2::new(sensor_plane.y * aspect_ratio, sensor_plane.y)
+        }
+    }
+
+    /// Half extents of the sensor in normalized device coordinates of an image with the given
+    /// aspect ratio. Components below 1 mean that the image extends beyond the sensor.
+    pub fn sensor_frame(&self, aspect_ratio: f32) -> Vec2 {
+        match self.projection {
+            Projection::Perspective | Projection::Fisheye { .. } => {
+                (self.sensor_plane() / self.image_plane(aspect_ratio)).min(Vec2::ONE)
+            }
+            Projection::Orthographic { .. } | Projection::Equirectangular | Projection::Cubemap => {
+                Vec2::ONE
+            }
+        }
+    }
+
+    /// World space ray through `position` on an image with the given aspect ratio, where
+    /// `position` is in normalized coordinates from the top left corner. Matches the rays traced by
+    /// `raygen.slang` through the centre of the aperture, and returns `None` for positions outside
+    /// of the projection.
+    pub fn viewport_to_ray(
+        &self,
+        position: Vec2,
+        aspect_ratio: f32,
+        transform: &Transform,
+    ) -> Option<Ray3d> {
+        let ndc = Vec2::new(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0);
+        let image_plane = self.image_plane(aspect_ratio);
+
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => (Vec3::ZERO, (ndc * image_plane).extend(-1.0)),
+            Projection::Orthographic { .. } => ((ndc * image_plane).extend(0.0), Vec3::NEG_Z),
+            Projection::Equirectangular => {
+                let longitude = ndc.x * PI;
+                let latitude = ndc.y * FRAC_PI_2;
+
+                let direction = Vec3::new(
+                    longitude.sin() * latitude.cos(),
+                    latitude.sin(),
+                    -longitude.cos() * latitude.cos(),
+                );
+
+                (Vec3::ZERO, direction)
+            }
+            Projection::Cubemap => {
+                const FORWARD: [Vec3; 6] = [
+                    Vec3::X,
+                    Vec3::NEG_X,
+                    Vec3::Y,
+                    Vec3::NEG_Y,
+                    Vec3::Z,
+                    Vec3::NEG_Z,
+                ];
+
+                const UP: [Vec3; 6] = [Vec3::Y, Vec3::Y, Vec3::Z, Vec3::NEG_Z, Vec3::Y, Vec3::Y];
+
+                let uv = Vec2::new(position.x, 1.0 - position.y);
+                let column = ((uv.x * 3.0) as usize).min(2);
+                let row = ((position.y * 2.0) as usize).min(1);
+                let face = row * 3 + column;
+                let st = (uv * Vec2::new(3.0, 2.0)).fract() * 2.0 - 1.0;
+
+                let forward = FORWARD[face];
+                let up = UP[face];
+                (Vec3::ZERO, forward + st.x * forward.cross(up) + st.y * up)
+            }
+            Projection::Fisheye { model, fov } => {
+                let sensor = ndc * image_plane;
+                let r = sensor.length();
+
+                let theta = match model {
+                    FisheyeModel::Equidistant => r,
+                    FisheyeModel::Equisolid if r > 2.0 => return None,
+                    FisheyeModel::Equisolid => 2.0 * (r * 0.5).asin(),
+                    FisheyeModel::Stereographic => 2.0 * (r * 0.5).atan(),
+                };
+
+                if theta > fov * 0.5 || theta > PI {
+                    return None;
+                }
+
+                let radial = if r > 0.0 { sensor / r } else { Vec2::ZERO };
+                (Vec3::ZERO, (radial * theta.sin()).extend(-theta.cos()))
+            }
+        };
+
+        let direction = Dir3::new(transform.rotation * direction).ok()?;
+        let origin = transform.translation + transform.rotation * origin;
+        Some(Ray3d::new(origin, direction))
+    }
+
+    /// Normalized position from the top left corner at which `point` in world space appears on an
+    /// image with the given aspect ratio, which is the inverse of `viewport_to_ray`. Returns `None`
+    /// for points behind a perspective camera, and for panoramic and fisheye projections.
+    pub fn world_to_viewport(
+        &self,
+        point: Vec3,
+        aspect_ratio: f32,
+        transform: &Transform,
+    ) -> Option<Vec2> {
+        let local = transform.rotation.inverse() * (point - transform.translation);
+        let image_plane = self.image_plane(aspect_ratio);
+
+        let ndc = match self.projection {
+            Projection::Perspective if local.z < 0.0 => local.truncate() / -local.z / image_plane,
+            Projection::Orthographic { .. } => local.truncate() / image_plane,
+            _ => return None,
+        };
+
+        Some(Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) * 0.5)
+    }
+
+    /// Half extents of the sensor projected to unit distance in front of the camera.
+    fn sensor_plane(&self) -> Vec2 {
+        Vec2::new(self.sensor.width, self.sensor.height) / (2.0 * self.focal_length)
+    }
+
+    /// Radius of the aperture in metres.
+    pub fn aperture_radius(&self) -> f32 {
+        self.focal_length / (2.0 * self.aperture) / 1000.0
+    }
+
+    /// Exposure value at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.aperture * self.aperture / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    /// Scale from scene luminance in cd/m² to normalized pixel values, based on the saturation
+    /// based sensitivity of the sensor.
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * self.ev100().exp2())
+    }
+}
+
+/// Image a camera renders into.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum CameraTarget {
+    /// The primary window, or the offscreen image when running headless.
+    #[default]
+    Primary,
+    /// An offscreen image of the given size, which can be captured with screenshots.
+    Image { width: u32, height: u32 },
+    /// An image of the given size that egui can draw through the `EguiTexture` component, which
+    /// is added to the camera once the image exists.
+    Egui { width: u32, height: u32 },
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum Projection {
+    /// Pinhole projection through the sensor, or thin lens projection with depth of field.
+    #[default]
+    Perspective,
+    /// Parallel projection of a view volume with the given height in world units.
+    Orthographic { height: f32 },
+    /// Full 360° by 180° panorama with longitude along the horizontal axis and latitude along the
+    /// vertical axis.
+    Equirectangular,
+    /// Six 90° faces laid out in a 3x2 grid, with +X, -X and +Y in the top row and -Y, +Z and -Z
+    /// in the bottom row.
+    Cubemap,
+    /// Fisheye lens mapping points on the sensor to angles from the optical axis according to
+    /// `model`, limited to a circular image of `fov` radians.
+    Fisheye { model: FisheyeModel, fov: f32 },
+}
+
+impl Projection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Perspective => "Perspective",
+            Self::Orthographic { .. } => "Orthographic",
+            Self::Equirectangular => "Equirectangular",
+            Self::Cubemap => "Cubemap",
+            Self::Fisheye { model, .. } => match model {
+                FisheyeModel::Equidistant => "Fisheye (equidistant)",
+                FisheyeModel::Equisolid => "Fisheye (equisolid)",
+                FisheyeModel::Stereographic => "Fisheye (stereographic)",
+            },
+        }
+    }
+
+    pub(crate) fn index(&self) -> u32 {
+        match self {
+            Self::Perspective => 0,
+            Self::Orthographic { .. } => 1,
+            Self::Equirectangular => 2,
+            Self::Cubemap => 3,
+            Self::Fisheye { model, .. } => 4 + model.index(),
+        }
+    }
+}
+
+/// Mapping from the angle θ between a ray and the optical axis to the distance r of its image
+/// from the sensor centre, for a lens with focal length f.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum FisheyeModel {
+    /// r = f·θ
+    #[default]
+    Equidistant,
+    /// r = 2f·sin(θ/2), preserving relative areas
+    Equisolid,
+    /// r = 2f·tan(θ/2), preserving angles
+    Stereographic,
+}
+
+impl FisheyeModel {
+    fn index(&self) -> u32 {
+        match self {
+            Self::Equidistant => 0,
+            Self::Equisolid => 1,
+            Self::Stereographic => 2,
+        }
+    }
+}
+
+/// How the sensor is fitted to images whose aspect ratio differs from the sensor.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SensorFit {
+    /// Fits the sensor width to the image width.
+    Horizontal,
+    /// Fits the sensor height to the image height.
+    Vertical,
+    /// Fills the image with the sensor, cropping the sensor where the aspect ratios differ.
+    Fill,
+    /// Fits the whole sensor inside the image, extending the image beyond the sensor where the
+    /// aspect ratios differ.
+    #[default]
+    Overscan,
+}
+
+impl SensorFit {
+    pub const ALL: [Self; 4] = [Self::Horizontal, Self::Vertical, Self::Fill, Self::Overscan];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Horizontal => "Horizontal",
+            Self::Vertical => "Vertical",
+            Self::Fill => "Fill",
+            Self::Overscan => "Overscan",
         }
     }
 }
 
+/// Shape of the aperture, which determines the shape of out of focus highlights.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Bokeh {
+    /// Number of aperture blades, or 0 for a perfectly circular aperture.
+    pub blades: u32,
+    /// Rotation of the aperture polygon in radians.
+    pub rotation: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub struct Sensor {
     pub width: f32,
     pub height: f32,
@@ -27,6 +351,10 @@ impl Sensor {
         height: 24.0,
     };
 
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width / self.height
+    }
+
     pub fn horizontal_fov(&self, focal_length: f32) -> f32 {
         2.0 * (self.width / (2.0 * focal_length)).atan()
     }
@@ -1,4 +1,6 @@
-use anyhow::Result;
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
 use ash::vk;
 use bevy::{
     ecs::schedule::ScheduleLabel,
@@ -8,13 +10,18 @@ use bevy::{
 };
 use egui::ViewportId;
 
+use crate::camera::CameraTarget;
+
 use super::{
+    ray_tracing::RayTracingPipeline,
     render_context::RenderContext,
     render_device::RenderDevice,
     render_queue::RenderQueue,
+    render_target::RenderTarget,
     resource_state_tracker::{ImageState, ResourceStateTracker},
     schedule::{Render, RenderStartup, RenderSystems},
-    swapchain::Swapchain,
+    storage_image::is_srgb,
+    view::{Views, prepare_views},
 };
 
 pub struct EguiPlugin;
@@ -22,8 +29,17 @@ pub struct EguiPlugin;
 impl Plugin for EguiPlugin {
     fn build(&self, app: &mut App) {
         app.add_schedule(EguiPass::schedule())
+            .init_resource::<EguiWantsInput>()
             .add_systems(RenderStartup, setup)
-            .add_systems(Render, run_egui_pass.in_set(RenderSystems::QueueUi))
+            .add_systems(
+                Render,
+                (
+                    update_user_textures
+                        .in_set(RenderSystems::Prepare)
+                        .after(prepare_views),
+                    run_egui_pass.in_set(RenderSystems::QueueUi),
+                ),
+            )
             .add_systems(
                 EguiPass,
                 (
@@ -61,6 +77,33 @@ pub enum EguiPassSystems {
     End,
 }
 
+/// Texture of a camera with an egui target, which can be drawn with e.g. `egui::Image`.
+#[derive(Component, Clone, Copy, Deref)]
+pub struct EguiTexture(pub egui::TextureId);
+
+/// Whether egui used the input of the last frame, in which case other input consumers should
+/// ignore it.
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct EguiWantsInput {
+    /// A widget has keyboard focus, e.g. a text field.
+    pub keyboard: bool,
+    /// The pointer is over an egui window or a widget is being dragged. Unlike
+    /// `egui::Context::wants_pointer_input`, panels in the background layer do not count, so that
+    /// a viewport panel still passes pointer input through.
+    pub pointer: bool,
+}
+
+/// Run condition that is true if egui wants keyboard input.
+pub fn egui_wants_keyboard_input(wants_input: Option<Res<EguiWantsInput>>) -> bool {
+    wants_input.is_some_and(|wants_input| wants_input.keyboard)
+}
+
+/// Run condition that is true if egui wants pointer input.
+pub fn egui_wants_pointer_input(wants_input: Option<Res<EguiWantsInput>>) -> bool {
+    wants_input.is_some_and(|wants_input| wants_input.pointer)
+}
+
+/// Translates window events into egui input, which only exists when rendering to a window.
 #[derive(Resource, Deref, DerefMut)]
 pub struct EguiState(egui_winit::State);
 
@@ -69,23 +112,32 @@ pub struct EguiContext(egui::Context);
 
 fn setup(
     mut commands: Commands,
-    display_handle: Res<DisplayHandleWrapper>,
+    display_handle: Option<Res<DisplayHandleWrapper>>,
     render_device: Res<RenderDevice>,
+    render_target: Res<RenderTarget>,
 ) -> Result<(), BevyError> {
     let context = egui::Context::default();
 
-    let state = egui_winit::State::new(
+    // Headless apps still draw the UI into the offscreen target, but without any input
+    if let Some(display_handle) = display_handle {
+        let state = egui_winit::State::new(
+            context.clone(),
+            ViewportId::ROOT,
+            &**display_handle,
+            None,
+            None,
+            None,
+        );
+
+        commands.insert_resource(EguiState(state));
+    }
+
+    let egui_renderer = EguiRenderer::new(
+        render_device.clone(),
         context.clone(),
-        ViewportId::ROOT,
-        &**display_handle,
-        None,
-        None,
-        None,
-    );
-
-    let egui_renderer = EguiRenderer::new(render_device.clone(), context.clone())?;
+        render_target.format(),
+    )?;
     commands.insert_resource(EguiContext(context));
-    commands.insert_resource(EguiState(state));
     commands.insert_resource(egui_renderer);
     Ok(())
 }
@@ -96,11 +148,24 @@ fn run_egui_pass(world: &mut World) {
 
 fn begin(
     mut egui_renderer: ResMut<EguiRenderer>,
-    mut state: ResMut<EguiState>,
+    state: Option<ResMut<EguiState>>,
+    render_target: Res<RenderTarget>,
     windows: Query<Entity, With<PrimaryWindow>>,
     mut winit_events: MessageReader<RawWinitWindowEvent>,
 ) -> Result<(), BevyError> {
-    let window = windows.single()?;
+    let (Some(mut state), Ok(window)) = (state, windows.single()) else {
+        let vk::Extent2D { width, height } = render_target.extent();
+
+        egui_renderer.begin(egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            ..default()
+        })?;
+
+        return Ok(());
+    };
 
     let raw_input = WINIT_WINDOWS.with_borrow(|windows| {
         let window = windows.get_window(window).unwrap();
@@ -121,11 +186,68 @@ fn begin(
 fn end(
     render_queue: Res<RenderQueue>,
     render_context: Res<RenderContext>,
-    swapchain: Res<Swapchain>,
+    render_target: Res<RenderTarget>,
     mut tracker: ResMut<ResourceStateTracker>,
     mut egui_renderer: ResMut<EguiRenderer>,
+    mut wants_input: ResMut<EguiWantsInput>,
+    views: Option<Res<Views>>,
 ) -> Result<(), BevyError> {
-    egui_renderer.end(&render_queue, &render_context, &swapchain, &mut tracker)?;
+    // User textures are sampled even if nothing has been rendered into them yet
+    for view in views.iter().flat_map(|views| views.values()) {
+        if matches!(view.target, CameraTarget::Egui { .. }) {
+            tracker.transition_image(view.output_image.image, ImageState::shader_read_only());
+        }
+    }
+
+    egui_renderer.end(&render_queue, &render_context, &render_target, &mut tracker)?;
+
+    *wants_input = EguiWantsInput {
+        keyboard: egui_renderer.context.wants_keyboard_input(),
+        pointer: egui_renderer.wants_pointer_input(),
+    };
+
+    Ok(())
+}
+
+/// Registers the output images of cameras with an egui target as user textures, and adds the
+/// `EguiTexture` component to those cameras.
+fn update_user_textures(
+    mut commands: Commands,
+    egui_renderer: Option<ResMut<EguiRenderer>>,
+    views: Option<Res<Views>>,
+) -> Result<(), BevyError> {
+    let Some(mut egui_renderer) = egui_renderer else {
+        return Ok(());
+    };
+
+    let egui_views = views
+        .iter()
+        .flat_map(|views| views.iter())
+        .filter(|(_, view)| matches!(view.target, CameraTarget::Egui { .. }))
+        .map(|(&entity, view)| (entity, view.output_image_view))
+        .collect::<HashMap<_, _>>();
+
+    let removed = egui_renderer
+        .user_textures
+        .keys()
+        .filter(|entity| !egui_views.contains_key(entity))
+        .copied()
+        .collect::<Vec<_>>();
+
+    for entity in removed {
+        egui_renderer.remove_user_texture(entity)?;
+
+        if let Ok(mut entity) = commands.get_entity(entity) {
+            entity.remove::<EguiTexture>();
+        }
+    }
+
+    for (entity, image_view) in egui_views {
+        if let Some(texture_id) = egui_renderer.update_user_texture(entity, image_view)? {
+            commands.entity(entity).insert(EguiTexture(texture_id));
+        }
+    }
+
     Ok(())
 }
 
@@ -135,12 +257,25 @@ pub struct EguiRenderer {
     pub context: egui::Context,
     pub renderer: egui_ash_renderer::Renderer,
     pub textures_to_free: Vec<egui::TextureId>,
+    pub user_texture_set_layout: vk::DescriptorSetLayout,
+    pub user_texture_pool: vk::DescriptorPool,
+    pub user_texture_sampler: vk::Sampler,
+    pub user_textures: HashMap<Entity, UserTexture>,
+}
+
+pub struct UserTexture {
+    pub texture_id: egui::TextureId,
+    pub descriptor_set: vk::DescriptorSet,
 }
 
 impl EguiRenderer {
-    pub fn new(render_device: RenderDevice, context: egui::Context) -> Result<Self> {
+    pub fn new(
+        render_device: RenderDevice,
+        context: egui::Context,
+        color_attachment_format: vk::Format,
+    ) -> Result<Self> {
         let dynamic_rendering = egui_ash_renderer::DynamicRendering {
-            color_attachment_format: vk::Format::B8G8R8A8_UNORM,
+            color_attachment_format,
             depth_attachment_format: None,
         };
 
@@ -148,19 +283,155 @@ impl EguiRenderer {
             render_device.allocator.clone(),
             render_device.device.clone(),
             dynamic_rendering,
-            default(),
+            egui_ash_renderer::Options {
+                srgb_framebuffer: is_srgb(color_attachment_format),
+                ..default()
+            },
         )?;
 
         let textures_to_free = Vec::new();
 
+        let (user_texture_set_layout, user_texture_pool, user_texture_sampler) = unsafe {
+            // Must match the descriptor set layout of the egui pipeline
+            let binding = vk::DescriptorSetLayoutBinding::default()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+                .bindings(std::slice::from_ref(&binding));
+
+            let user_texture_set_layout = render_device
+                .device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)?;
+
+            let pool_size = vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(RayTracingPipeline::MAX_VIEWS);
+
+            let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::default()
+                .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+                .max_sets(RayTracingPipeline::MAX_VIEWS)
+                .pool_sizes(std::slice::from_ref(&pool_size));
+
+            let user_texture_pool = render_device
+                .device
+                .create_descriptor_pool(&descriptor_pool_create_info, None)?;
+
+            let sampler_create_info = vk::SamplerCreateInfo::default()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+
+            let user_texture_sampler = render_device
+                .device
+                .create_sampler(&sampler_create_info, None)?;
+
+            (
+                user_texture_set_layout,
+                user_texture_pool,
+                user_texture_sampler,
+            )
+        };
+
         Ok(Self {
             render_device,
             context,
             renderer,
             textures_to_free,
+            user_texture_set_layout,
+            user_texture_pool,
+            user_texture_sampler,
+            user_textures: HashMap::new(),
         })
     }
 
+    /// Points the user texture of `entity` at `image_view`, which must be in the shader read only
+    /// layout when drawn. Returns the texture id if the texture has been newly registered.
+    pub fn update_user_texture(
+        &mut self,
+        entity: Entity,
+        image_view: vk::ImageView,
+    ) -> Result<Option<egui::TextureId>> {
+        let mut texture_id = None;
+
+        let descriptor_set = match self.user_textures.get(&entity) {
+            Some(user_texture) => user_texture.descriptor_set,
+            None => unsafe {
+                let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(self.user_texture_pool)
+                    .set_layouts(std::slice::from_ref(&self.user_texture_set_layout));
+
+                let [descriptor_set] = self
+                    .render_device
+                    .device
+                    .allocate_descriptor_sets(&descriptor_set_allocate_info)?
+                    .try_into()
+                    .map_err(|_| anyhow!("Failed to allocate exactly one descriptor set"))?;
+
+                let id = self.renderer.add_user_texture(descriptor_set);
+                texture_id = Some(id);
+
+                self.user_textures.insert(
+                    entity,
+                    UserTexture {
+                        texture_id: id,
+                        descriptor_set,
+                    },
+                );
+
+                descriptor_set
+            },
+        };
+
+        let image_info = vk::DescriptorImageInfo::default()
+            .sampler(self.user_texture_sampler)
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        unsafe {
+            self.render_device.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&image_info))],
+                &[],
+            );
+        }
+
+        Ok(texture_id)
+    }
+
+    pub fn remove_user_texture(&mut self, entity: Entity) -> Result<()> {
+        let Some(user_texture) = self.user_textures.remove(&entity) else {
+            return Ok(());
+        };
+
+        self.renderer.remove_user_texture(user_texture.texture_id);
+
+        unsafe {
+            self.render_device
+                .device
+                .free_descriptor_sets(self.user_texture_pool, &[user_texture.descriptor_set])?;
+        }
+
+        Ok(())
+    }
+
+    fn wants_pointer_input(&self) -> bool {
+        let is_over_window = self
+            .context
+            .pointer_hover_pos()
+            .and_then(|pos| self.context.layer_id_at(pos))
+            .is_some_and(|layer_id| layer_id.order != egui::Order::Background);
+
+        is_over_window || self.context.is_using_pointer()
+    }
+
     pub fn begin(&mut self, raw_input: egui::RawInput) -> Result<()> {
         if !self.textures_to_free.is_empty() {
             self.renderer.free_textures(&self.textures_to_free)?;
@@ -175,7 +446,7 @@ impl EguiRenderer {
         &mut self,
         render_queue: &RenderQueue,
         render_context: &RenderContext,
-        swapchain: &Swapchain,
+        render_target: &RenderTarget,
         tracker: &mut ResourceStateTracker,
     ) -> Result<()> {
         let egui::FullOutput {
@@ -198,14 +469,13 @@ impl EguiRenderer {
         }
 
         let clipped_primitives = self.context.tessellate(shapes, pixels_per_point);
-        let swapchain_image = swapchain.current_image();
 
         tracker
-            .transition_image(swapchain_image.image, ImageState::color_attachment())
+            .transition_image(render_target.image(), ImageState::color_attachment())
             .flush(&self.render_device, render_context.command_buffer);
 
         let rendering_attachment_info = vk::RenderingAttachmentInfo::default()
-            .image_view(swapchain_image.image_view)
+            .image_view(render_target.image_view())
             .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
             .load_op(vk::AttachmentLoadOp::LOAD)
             .store_op(vk::AttachmentStoreOp::STORE);
@@ -213,7 +483,7 @@ impl EguiRenderer {
         let rendering_info = vk::RenderingInfo::default()
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
-                extent: swapchain.surface_extent,
+                extent: render_target.extent(),
             })
             .layer_count(1)
             .color_attachments(std::slice::from_ref(&rendering_attachment_info));
@@ -225,7 +495,7 @@ impl EguiRenderer {
 
             self.renderer.cmd_draw(
                 render_context.command_buffer,
-                swapchain.surface_extent,
+                render_target.extent(),
                 pixels_per_point,
                 &clipped_primitives,
             )?;
@@ -238,3 +508,19 @@ impl EguiRenderer {
         Ok(())
     }
 }
+
+impl Drop for EguiRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            self.render_device
+                .device
+                .destroy_sampler(self.user_texture_sampler, None);
+            self.render_device
+                .device
+                .destroy_descriptor_pool(self.user_texture_pool, None);
+            self.render_device
+                .device
+                .destroy_descriptor_set_layout(self.user_texture_set_layout, None);
+        }
+    }
+}
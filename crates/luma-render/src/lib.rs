@@ -1,21 +1,34 @@
+pub mod bindings;
 mod blas;
 mod buffer;
 pub mod camera;
 pub mod egui_renderer;
+pub mod light;
+pub mod material;
 mod mesh;
+pub mod picking;
+pub mod procedural;
 pub mod ray_tracing;
+mod readback;
+mod reflection;
 mod render_asset;
 mod render_context;
 mod render_device;
 mod render_queue;
+pub mod render_target;
 mod resource_state_tracker;
 mod schedule;
-mod shader;
+pub mod screenshot;
+pub mod shader;
 mod storage_image;
 mod swapchain;
+pub mod texture;
 mod tlas;
+pub mod tonemapping;
+mod view;
 
 use anyhow::Result;
+use ash::vk;
 use bevy::{
     ecs::system::SystemId,
     prelude::*,
@@ -24,15 +37,28 @@ use bevy::{
 use render_context::RenderContext;
 use render_device::RenderDevice;
 use render_queue::RenderQueue;
+use render_target::{Headless, OffscreenTarget, RenderTarget};
 use resource_state_tracker::{ImageState, ResourceStateTracker};
 use schedule::{Render, RenderStartup, run_render_startup_schedule};
 use shader::ShaderPlugin;
 use swapchain::Swapchain;
 
-pub struct RendererPlugin;
+/// Directory for compiled shaders and pipelines that speed up later runs, relative to the working
+/// directory.
+const CACHE_DIR: &str = "cache";
+
+#[derive(Default)]
+pub struct RendererPlugin {
+    /// Renders into an offscreen image instead of the primary window when set.
+    pub headless: Option<Headless>,
+}
 
 impl Plugin for RendererPlugin {
     fn build(&self, app: &mut App) {
+        if let Some(headless) = &self.headless {
+            app.insert_resource(headless.clone());
+        }
+
         app.add_plugins(ShaderPlugin)
             .add_schedule(RenderStartup::schedule())
             .add_schedule(Render::schedule())
@@ -47,26 +73,48 @@ impl Plugin for RendererPlugin {
 
 fn setup_renderer(
     mut commands: Commands,
+    headless: Option<Res<Headless>>,
     windows: Query<(&Window, &RawHandleWrapper), With<PrimaryWindow>>,
 ) -> Result<(), BevyError> {
-    let (window, handle) = windows.single()?;
-    let (render_device, render_queue) = RenderDevice::new(handle.get_display_handle())?;
-    let render_context = RenderContext::new(render_device.clone(), &render_queue)?;
+    let (render_device, render_queue, render_target) = match headless {
+        Some(headless) => {
+            let (render_device, render_queue) = RenderDevice::new(None)?;
+
+            let extent = vk::Extent2D {
+                width: headless.width,
+                height: headless.height,
+            };
+
+            let offscreen_target = OffscreenTarget::new(render_device.clone(), extent)?;
+            let render_target = RenderTarget::Offscreen(Box::new(offscreen_target));
+            (render_device, render_queue, render_target)
+        }
+        None => {
+            let (window, handle) = windows.single()?;
+            let (render_device, render_queue) =
+                RenderDevice::new(Some(handle.get_display_handle()))?;
+
+            let swapchain = Swapchain::new(
+                render_device.clone(),
+                &render_queue,
+                handle.get_display_handle(),
+                handle.get_window_handle(),
+                window.physical_width(),
+                window.physical_height(),
+                None,
+            )?;
+
+            let render_target = RenderTarget::Window(swapchain);
+            (render_device, render_queue, render_target)
+        }
+    };
 
-    let swapchain = Swapchain::new(
-        render_device.clone(),
-        &render_queue,
-        handle.get_display_handle(),
-        handle.get_window_handle(),
-        window.physical_width(),
-        window.physical_height(),
-        None,
-    )?;
+    let render_context = RenderContext::new(render_device.clone(), &render_queue)?;
 
     commands.insert_resource(render_device);
     commands.insert_resource(render_queue);
     commands.insert_resource(render_context);
-    commands.insert_resource(swapchain);
+    commands.insert_resource(render_target);
     commands.init_resource::<ResourceStateTracker>();
 
     Ok(())
@@ -75,10 +123,14 @@ fn setup_renderer(
 fn recreate_swapchain(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
-    mut swapchain: ResMut<Swapchain>,
+    mut render_target: ResMut<RenderTarget>,
     mut resource_state_tracker: ResMut<ResourceStateTracker>,
     windows: Query<(&Window, &RawHandleWrapper), With<PrimaryWindow>>,
 ) -> Result<(), BevyError> {
+    let RenderTarget::Window(swapchain) = &mut *render_target else {
+        return Ok(());
+    };
+
     if !swapchain.out_of_date {
         return Ok(());
     }
@@ -98,7 +150,7 @@ fn recreate_swapchain(
         handle.get_window_handle(),
         window.physical_width(),
         window.physical_height(),
-        Some(&mut swapchain),
+        Some(&mut *swapchain),
     )?;
 
     Ok(())
@@ -131,10 +183,15 @@ fn render(
 fn begin(
     render_device: Res<RenderDevice>,
     render_context: Res<RenderContext>,
-    mut swapchain: ResMut<Swapchain>,
+    mut render_target: ResMut<RenderTarget>,
     mut resource_state_tracker: ResMut<ResourceStateTracker>,
 ) -> Result<bool> {
     render_context.begin_frame(&render_device)?;
+
+    let RenderTarget::Window(swapchain) = &mut *render_target else {
+        return Ok(false);
+    };
+
     swapchain.acquire_next(render_context.semaphore)?;
 
     if swapchain.out_of_date {
@@ -150,17 +207,39 @@ fn end(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     render_context: Res<RenderContext>,
-    mut swapchain: ResMut<Swapchain>,
+    mut render_target: ResMut<RenderTarget>,
     mut resource_state_tracker: ResMut<ResourceStateTracker>,
 ) -> Result<()> {
-    let swapchain_image = swapchain.current_image();
-
-    resource_state_tracker
-        .transition_image(swapchain_image.image, ImageState::present())
-        .flush(&render_device, render_context.command_buffer);
+    match &mut *render_target {
+        RenderTarget::Window(swapchain) => {
+            let swapchain_image = swapchain.current_image();
+
+            resource_state_tracker
+                .transition_image(swapchain_image.image, ImageState::present())
+                .flush(&render_device, render_context.command_buffer);
+
+            render_context.end_frame(
+                &render_device,
+                &render_queue,
+                Some(render_context.semaphore),
+                Some(swapchain_image.semaphore),
+            )?;
+
+            swapchain.present(&render_queue)?;
+        }
+        RenderTarget::Offscreen(offscreen_target) => {
+            offscreen_target.copy_to_readback_buffer(
+                render_context.command_buffer,
+                &mut resource_state_tracker,
+            );
+
+            render_context.end_frame(&render_device, &render_queue, None, None)?;
+
+            // Block until the frame is done so it can be read back right away
+            render_context.wait_frame(&render_device)?;
+        }
+    }
 
-    render_context.end_frame(&render_device, &render_queue, swapchain_image.semaphore)?;
-    swapchain.present(&render_queue)?;
     Ok(())
 }
 
@@ -168,5 +247,6 @@ fn on_app_exit(mut app_exit_events: MessageReader<AppExit>, render_device: Res<R
     if !app_exit_events.is_empty() {
         app_exit_events.clear();
         render_device.wait_idle();
+        render_device.save_pipeline_cache();
     }
 }
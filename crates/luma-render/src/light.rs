@@ -0,0 +1,22 @@
+use bevy::prelude::*;
+
+/// Light from an infinitely distant source, like the sun, that shines along the forward direction
+/// of its transform. Only one directional light is rendered, and scenes without one are shaded
+/// without lighting or shadows.
+#[derive(Component, Clone, PartialEq, Debug)]
+#[require(Transform)]
+pub struct DirectionalLight {
+    /// Color that lit surfaces are multiplied with.
+    pub color: Color,
+    /// Traces a shadow ray towards the light for every shaded surface.
+    pub shadows: bool,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            shadows: false,
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use bevy::prelude::*;
+
+use crate::texture::Texture;
+
+/// Surface properties of a mesh. Meshes without a material are shaded with the default material.
+#[derive(Component, Clone, PartialEq, Debug)]
+pub struct Material {
+    pub base_color: Color,
+    /// Multiplies `base_color` at the surface's UV, including its alpha. Instances are shaded
+    /// without it until it has loaded.
+    pub base_color_texture: Option<Handle<Texture>>,
+    /// How the alpha of `base_color` and `base_color_texture` covers the surface.
+    pub alpha_mode: AlphaMode,
+    /// Index into [`RayTracingShaders::hit_groups`](crate::ray_tracing::RayTracingShaders::hit_groups)
+    /// of the shaders that the surface is shaded with.
+    pub hit_group: u32,
+    /// Index into [`RayTracingShaders::callables`](crate::ray_tracing::RayTracingShaders::callables)
+    /// of the callable shader that evaluates the surface, or `None` for the hit group's own shading.
+    pub callable: Option<u32>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Color::WHITE,
+            base_color_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            hit_group: 0,
+            callable: None,
+        }
+    }
+}
+
+/// Non-opaque surfaces run the any-hit shader of their hit group, which decides whether a hit
+/// counts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AlphaMode {
+    /// Alpha is ignored, which traces fastest.
+    Opaque,
+    /// Surfaces with an alpha below the cutoff are cut out.
+    Mask { cutoff: f32 },
+    /// Surfaces are hit with a probability of their alpha, which converges to blended
+    /// transparency as samples accumulate.
+    Blend,
+}
+
+impl AlphaMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Opaque => "Opaque",
+            Self::Mask { .. } => "Mask",
+            Self::Blend => "Blend",
+        }
+    }
+
+    /// Value of the mode in shaders, see `ALPHA_MODE_*` in `material.slang`.
+    pub(crate) fn index(&self) -> u32 {
+        match self {
+            Self::Opaque => 0,
+            Self::Mask { .. } => 1,
+            Self::Blend => 2,
+        }
+    }
+}
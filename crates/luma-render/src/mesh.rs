@@ -72,6 +72,7 @@ impl RenderAsset for GpuMesh {
         let mesh_index = mesh_info_buffer.insert(MeshInfo {
             vertex_buffer_address: vertex_buffer.address,
             index_buffer_address: index_buffer.address,
+            primitive_buffer_address: 0,
         })?;
 
         let blas = render_device.create_blas(render_queue, &vertex_buffer, &index_buffer)?;
@@ -105,6 +106,8 @@ impl Drop for GpuMesh {
 pub struct MeshInfo {
     pub vertex_buffer_address: vk::DeviceAddress,
     pub index_buffer_address: vk::DeviceAddress,
+    /// Primitives of procedural geometry, which has no vertices or indices.
+    pub primitive_buffer_address: vk::DeviceAddress,
 }
 
 #[derive(Resource)]
@@ -0,0 +1,641 @@
+use anyhow::{Result, anyhow};
+use ash::vk;
+use bevy::prelude::*;
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::MemoryLocation;
+
+use crate::camera::Camera;
+
+use super::{
+    RenderDevice,
+    buffer::Buffer,
+    mesh::MeshInfoBuffer,
+    procedural::ProceduralGeometry,
+    reflection::PipelineReflection,
+    render_context::RenderContext,
+    schedule::{Render, RenderSystems},
+    shader::{Shader, ShaderDiagnostics},
+    tlas::{InstanceGeometry, Tlas, TlasInstance},
+    view::Views,
+};
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<Pick>()
+            .add_message::<PickResult>()
+            .init_resource::<PendingPicks>()
+            .add_systems(Startup, load_shader)
+            .add_systems(
+                Render,
+                (
+                    (read_pick_results, create_or_update_picking_pipeline)
+                        .in_set(RenderSystems::Prepare),
+                    execute_picking_pipeline.in_set(RenderSystems::QueueRayTracing),
+                ),
+            );
+    }
+}
+
+/// Requests the scene to be picked at `position` of the image of `camera`, where `position` is in
+/// normalized coordinates from the top left corner. The answer arrives as a `PickResult` once the
+/// frame the request was traced in has finished.
+#[derive(Message, Clone, Copy, Debug)]
+pub struct Pick {
+    pub camera: Entity,
+    pub position: Vec2,
+}
+
+#[derive(Message, Clone, Copy, Debug)]
+pub struct PickResult {
+    pub pick: Pick,
+    /// The closest surface along the ray, if any.
+    pub hit: Option<PickHit>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PickHit {
+    pub entity: Entity,
+    pub geometry: PickedGeometry,
+    /// Position of the hit in world space.
+    pub position: Vec3,
+    /// Distance from the ray origin to the hit.
+    pub distance: f32,
+}
+
+/// The part of an instance's geometry that was hit.
+#[derive(Clone, Copy, Debug)]
+pub enum PickedGeometry {
+    Triangle {
+        mesh: AssetId<Mesh>,
+        /// Index of the triangle within the mesh.
+        triangle: u32,
+        /// Weights of the three vertices of the triangle at the hit.
+        barycentrics: Vec3,
+    },
+    Procedural {
+        geometry: AssetId<ProceduralGeometry>,
+        /// Index of the primitive within the geometry.
+        primitive: u32,
+    },
+}
+
+#[derive(Resource)]
+struct PickingShaderHandle(Handle<Shader>);
+
+fn load_shader(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(PickingShaderHandle(
+        asset_server.load("shaders/picking.slang"),
+    ));
+}
+
+fn create_or_update_picking_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    shader_handle: Res<PickingShaderHandle>,
+    assets: Res<Assets<Shader>>,
+    mut asset_events: MessageReader<AssetEvent<Shader>>,
+    mut shader_diagnostics: ResMut<ShaderDiagnostics>,
+    mut is_built: Local<bool>,
+) {
+    let Some(shader) = assets.get(&shader_handle.0) else {
+        return;
+    };
+
+    let is_shader_modified = asset_events.read().any(|asset_event| {
+        matches!(asset_event, AssetEvent::Modified { id } if *id == shader_handle.0.id())
+    });
+
+    // A pipeline that failed to build is only retried once the shader changes
+    if *is_built && !is_shader_modified {
+        return;
+    }
+
+    *is_built = true;
+
+    // The last good pipeline stays in use until the error is fixed
+    match PickingPipeline::new(render_device.clone(), shader) {
+        Ok(pipeline) => {
+            shader_diagnostics.remove(PickingPipeline::NAME);
+            commands.insert_resource(pipeline);
+        }
+        Err(err) => {
+            error!("Failed to build picking pipeline: {err}");
+            shader_diagnostics.insert(PickingPipeline::NAME, &err.to_string());
+        }
+    }
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+struct PendingPicks(Vec<PendingPicking>);
+
+/// Picks that have been traced into a readback buffer, which is ready to be read once the frame
+/// it was recorded in has finished.
+struct PendingPicking {
+    render_device: RenderDevice,
+    picks: Vec<(Pick, Ray3d)>,
+    /// Instances of the TLAS at the time of tracing, which may have been rebuilt since.
+    instances: Vec<TlasInstance>,
+    ray_buffer: Buffer<GpuPickRay>,
+    buffer: Buffer<GpuPickResult>,
+}
+
+impl Drop for PendingPicking {
+    fn drop(&mut self) {
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.ray_buffer));
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.buffer));
+    }
+}
+
+fn read_pick_results(
+    mut pending_picks: ResMut<PendingPicks>,
+    mut pick_results: MessageWriter<PickResult>,
+) -> Result<(), BevyError> {
+    for pending_picking in pending_picks.drain(..) {
+        let results = pending_picking.buffer.slice()?;
+
+        for (&(pick, ray), result) in pending_picking.picks.iter().zip(results) {
+            let hit = (result.hit != GpuPickResult::HIT_NONE)
+                .then(|| pending_picking.instances.get(result.instance as usize))
+                .flatten()
+                .map(|instance| PickHit {
+                    entity: instance.entity,
+                    geometry: match instance.geometry {
+                        InstanceGeometry::Mesh(mesh) => PickedGeometry::Triangle {
+                            mesh,
+                            triangle: result.primitive,
+                            barycentrics: Vec3::new(
+                                1.0 - result.barycentrics.x - result.barycentrics.y,
+                                result.barycentrics.x,
+                                result.barycentrics.y,
+                            ),
+                        },
+                        InstanceGeometry::Procedural(geometry) => PickedGeometry::Procedural {
+                            geometry,
+                            primitive: result.primitive,
+                        },
+                    },
+                    position: ray.get_point(result.distance),
+                    distance: result.distance,
+                });
+
+            pick_results.write(PickResult { pick, hit });
+        }
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute_picking_pipeline(
+    render_device: Res<RenderDevice>,
+    render_context: Res<RenderContext>,
+    picking_pipeline: Option<Res<PickingPipeline>>,
+    tlas: Option<Res<Tlas>>,
+    mesh_info_buffer: Res<MeshInfoBuffer>,
+    views: Res<Views>,
+    cameras: Query<(&Camera, &Transform)>,
+    mut pending_picks: ResMut<PendingPicks>,
+    mut picks: MessageReader<Pick>,
+    mut pick_results: MessageWriter<PickResult>,
+) -> Result<(), BevyError> {
+    let (Some(picking_pipeline), Some(tlas)) = (picking_pipeline, tlas) else {
+        picks.clear();
+        return Ok(());
+    };
+
+    // All picks are read, so that the dropped ones are not traced in a later frame instead
+    let mut frame_picks = picks.read().copied().collect::<Vec<_>>();
+
+    if frame_picks.len() > PickingPipeline::MAX_PICKS as usize {
+        warn!(
+            "Dropping {} picks, at most {} are traced per frame",
+            frame_picks.len() - PickingPipeline::MAX_PICKS as usize,
+            PickingPipeline::MAX_PICKS
+        );
+
+        frame_picks.truncate(PickingPipeline::MAX_PICKS as usize);
+    }
+
+    let mut rays = Vec::new();
+
+    for pick in frame_picks {
+        let Some(view) = views.get(&pick.camera) else {
+            warn!(
+                "Cannot pick through camera {} without an image",
+                pick.camera
+            );
+            continue;
+        };
+
+        let Ok((camera, transform)) = cameras.get(pick.camera) else {
+            continue;
+        };
+
+        let vk::Extent2D { width, height } = view.extent();
+        let aspect_ratio = width as f32 / height as f32;
+
+        // Positions outside of the projection cannot hit anything
+        match camera.viewport_to_ray(pick.position, aspect_ratio, transform) {
+            Some(ray) => rays.push((pick, ray)),
+            None => {
+                pick_results.write(PickResult { pick, hit: None });
+            }
+        }
+    }
+
+    if rays.is_empty() {
+        return Ok(());
+    }
+
+    // Pushed before anything can fail, so that dropping it destroys the buffers
+    let mut pending_picking = PendingPicking {
+        render_device: render_device.clone(),
+        instances: tlas.instances().to_vec(),
+        ray_buffer: render_device.create_buffer(
+            rays.len() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryLocation::CpuToGpu,
+            Some("Pick Ray Buffer"),
+        )?,
+        buffer: default(),
+        picks: rays,
+    };
+
+    pending_picking.buffer = render_device.create_buffer(
+        pending_picking.picks.len() as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        MemoryLocation::GpuToCpu,
+        Some("Pick Result Buffer"),
+    )?;
+
+    for (slot, (_, ray)) in pending_picking
+        .ray_buffer
+        .slice_mut()?
+        .iter_mut()
+        .zip(&pending_picking.picks)
+    {
+        *slot = GpuPickRay {
+            origin: ray.origin,
+            direction: *ray.direction,
+        };
+    }
+
+    picking_pipeline.trace(
+        render_context.command_buffer,
+        &tlas,
+        &mesh_info_buffer,
+        &pending_picking.ray_buffer,
+        &pending_picking.buffer,
+    )?;
+
+    pending_picks.push(pending_picking);
+
+    Ok(())
+}
+
+/// Compute pass that traces single rays with ray queries and writes the closest hits into a
+/// host visible buffer.
+#[derive(Resource)]
+pub struct PickingPipeline {
+    pub render_device: RenderDevice,
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Holds the descriptor set of the current frame.
+    pub descriptor_pool: vk::DescriptorPool,
+}
+
+impl PickingPipeline {
+    /// Origin of build errors in `ShaderDiagnostics`.
+    pub const NAME: &str = "Picking pipeline";
+
+    /// Maximum number of picks traced per frame, further picks of the same frame are dropped
+    /// with a warning.
+    pub const MAX_PICKS: u32 = 64;
+
+    /// Number of rays traced by a workgroup, `numthreads` in `picking.slang`.
+    const WORKGROUP_SIZE: u32 = 64;
+
+    /// Bindings written by `trace`, which the shader may use a subset of.
+    const BINDINGS: [(u32, vk::DescriptorType); 5] = [
+        (0, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+        (1, vk::DescriptorType::STORAGE_BUFFER),
+        (2, vk::DescriptorType::STORAGE_BUFFER),
+        (3, vk::DescriptorType::STORAGE_BUFFER),
+        (4, vk::DescriptorType::STORAGE_BUFFER),
+    ];
+
+    pub fn new(render_device: RenderDevice, shader: &Shader) -> Result<Self> {
+        // Catch layout mismatches with a readable error before they reach the driver
+        let mut reflection = PipelineReflection::default();
+        reflection.add_stage(&shader.reflection, vk::ShaderStageFlags::COMPUTE)?;
+        reflection.expect_bindings(&Self::BINDINGS)?;
+        reflection.expect_push_constants::<PushConstants>(&PushConstants::OFFSETS, 0)?;
+
+        // Dropping this if a later step fails destroys the objects created so far
+        let mut picking_pipeline = Self {
+            render_device: render_device.clone(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+        };
+
+        unsafe {
+            let descriptor_set_layout_bindings = [
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(2)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(3)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(4)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            ];
+
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+                .bindings(&descriptor_set_layout_bindings);
+
+            picking_pipeline.descriptor_set_layout = render_device
+                .device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)?;
+
+            let push_constant_range = vk::PushConstantRange::default()
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .offset(0)
+                .size(size_of::<PushConstants>() as u32);
+
+            let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(
+                    &picking_pipeline.descriptor_set_layout,
+                ))
+                .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+
+            picking_pipeline.pipeline_layout = render_device
+                .device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)?;
+
+            let shader_module = render_device.device.create_shader_module(
+                &vk::ShaderModuleCreateInfo::default().code(&shader.code),
+                None,
+            )?;
+
+            let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(shader_module)
+                .name(&shader.entry_point);
+
+            let pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+                .stage(shader_stage)
+                .layout(picking_pipeline.pipeline_layout);
+
+            let pipelines = render_device.device.create_compute_pipelines(
+                render_device.pipeline_cache,
+                &[pipeline_create_info],
+                None,
+            );
+
+            render_device
+                .device
+                .destroy_shader_module(shader_module, None);
+
+            picking_pipeline.pipeline = pipelines
+                .map_err(|(_, result)| anyhow!("Failed to create picking pipeline: {result:?}"))?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Failed to create picking pipeline"))?;
+
+            let pool_sizes = descriptor_set_layout_bindings
+                .iter()
+                .map(|binding| {
+                    vk::DescriptorPoolSize::default()
+                        .ty(binding.descriptor_type)
+                        .descriptor_count(binding.descriptor_count)
+                })
+                .collect::<Vec<_>>();
+
+            let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::default()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes);
+
+            picking_pipeline.descriptor_pool = render_device
+                .device
+                .create_descriptor_pool(&descriptor_pool_create_info, None)?;
+        }
+
+        Ok(picking_pipeline)
+    }
+
+    /// Traces the rays of `ray_buffer` in a single dispatch and writes the closest hits into
+    /// `buffer`, in the same order.
+    pub fn trace(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        tlas: &Tlas,
+        mesh_info_buffer: &MeshInfoBuffer,
+        ray_buffer: &Buffer<GpuPickRay>,
+        buffer: &Buffer<GpuPickResult>,
+    ) -> Result<()> {
+        unsafe {
+            // The descriptor set of the previous frame is no longer in use once it has finished
+            self.render_device.device.reset_descriptor_pool(
+                self.descriptor_pool,
+                vk::DescriptorPoolResetFlags::empty(),
+            )?;
+
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(std::slice::from_ref(&self.descriptor_set_layout));
+
+            let [descriptor_set] = self
+                .render_device
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)?
+                .try_into()
+                .map_err(|_| anyhow!("Failed to allocate exactly one descriptor set"))?;
+
+            let mut acceleration_structure_info =
+                vk::WriteDescriptorSetAccelerationStructureKHR::default()
+                    .acceleration_structures(std::slice::from_ref(tlas.acceleration_structure()));
+
+            let descriptor_buffer_info = vk::DescriptorBufferInfo::default()
+                .buffer(buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE);
+
+            // Procedural primitives are intersected with the data of their geometry
+            let mesh_info_buffer_info = vk::DescriptorBufferInfo::default()
+                .buffer(mesh_info_buffer.buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE);
+
+            // Non-opaque triangles are alpha tested with the material of their instance
+            let instance_info_buffer_info = vk::DescriptorBufferInfo::default()
+                .buffer(tlas.instance_info_buffer().buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE);
+
+            let ray_buffer_info = vk::DescriptorBufferInfo::default()
+                .buffer(ray_buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE);
+
+            self.render_device.device.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                        .descriptor_count(1)
+                        .push_next(&mut acceleration_structure_info),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .buffer_info(std::slice::from_ref(&descriptor_buffer_info)),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(2)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .buffer_info(std::slice::from_ref(&mesh_info_buffer_info)),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(3)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .buffer_info(std::slice::from_ref(&instance_info_buffer_info)),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(4)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .buffer_info(std::slice::from_ref(&ray_buffer_info)),
+                ],
+                &[],
+            );
+
+            self.render_device.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+
+            self.render_device.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+
+            let push_constants = PushConstants {
+                ray_count: ray_buffer.len as u32,
+            };
+
+            self.render_device.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+
+            self.render_device.device.cmd_dispatch(
+                command_buffer,
+                push_constants.ray_count.div_ceil(Self::WORKGROUP_SIZE),
+                1,
+                1,
+            );
+
+            let memory_barrier = vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ);
+
+            self.render_device.device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PickingPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.render_device
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.render_device
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.render_device
+                .device
+                .destroy_pipeline(self.pipeline, None);
+            self.render_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Zeroable, Pod)]
+pub struct GpuPickResult {
+    hit: u32,
+    instance: u32,
+    primitive: u32,
+    distance: f32,
+    barycentrics: Vec2,
+}
+
+impl GpuPickResult {
+    /// Value of `hit` for rays that hit nothing, `HIT_NONE` in `picking.slang`.
+    const HIT_NONE: u32 = 0;
+}
+
+/// A ray to pick along, `PickRay` in `picking.slang`.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Zeroable, Pod)]
+pub struct GpuPickRay {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod)]
+struct PushConstants {
+    ray_count: u32,
+}
+
+impl PushConstants {
+    const OFFSETS: [usize; 1] = [offset_of!(Self, ray_count)];
+}
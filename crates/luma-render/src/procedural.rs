@@ -0,0 +1,231 @@
+use anyhow::{Result, anyhow};
+use ash::vk;
+use bevy::{
+    ecs::system::{
+        SystemParamItem,
+        lifetimeless::{SRes, SResMut},
+    },
+    prelude::*,
+};
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::MemoryLocation;
+
+use super::{
+    blas::Blas,
+    buffer::Buffer,
+    mesh::{MeshInfo, MeshInfoBuffer},
+    render_asset::{RenderAsset, RenderAssets, sync_render_assets},
+    render_device::RenderDevice,
+    render_queue::RenderQueue,
+    schedule::{Render, RenderSystems},
+};
+
+pub struct ProceduralPlugin;
+
+impl Plugin for ProceduralPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ProceduralGeometry>()
+            .init_resource::<RenderAssets<GpuProceduralGeometry>>()
+            .add_systems(
+                Render,
+                sync_render_assets::<GpuProceduralGeometry>.in_set(RenderSystems::Prepare),
+            );
+    }
+}
+
+/// Geometry made of bounding boxes, whose surfaces are found by the intersection shader of the
+/// hit group that the instance's material selects.
+#[derive(Asset, TypePath, Clone, Default, Debug)]
+pub struct ProceduralGeometry {
+    pub primitives: Vec<ProceduralPrimitive>,
+}
+
+impl ProceduralGeometry {
+    /// Analytic spheres from their centers and radii, such as a point cloud.
+    pub fn spheres(spheres: impl IntoIterator<Item = (Vec3, f32)>) -> Self {
+        Self {
+            primitives: spheres
+                .into_iter()
+                .map(|(center, radius)| ProceduralPrimitive::sphere(center, radius))
+                .collect(),
+        }
+    }
+
+    /// A tube of constant radius through the points, made of a capsule per segment.
+    pub fn curve(points: impl IntoIterator<Item = Vec3>, radius: f32) -> Self {
+        let points = points.into_iter().collect::<Vec<_>>();
+
+        Self {
+            primitives: points
+                .windows(2)
+                .map(|segment| ProceduralPrimitive::capsule(segment[0], segment[1], radius))
+                .collect(),
+        }
+    }
+}
+
+/// Shapes that `shaders/shape-intersection.slang` and picking intersect, selected by
+/// `ProceduralPrimitive::shape`. Custom intersection shaders may define their own.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProceduralShape {
+    /// Center in `data[0..3]` and radius in `data[3]`.
+    Sphere = 0,
+    /// Segment from `data[0..3]` to `data[3..6]` with radius `data[6]`.
+    Capsule = 1,
+    /// Signed distance field of a torus around the Y axis, with center in `data[0..3]`, the
+    /// radius to the center of the tube in `data[3]` and the radius of the tube in `data[4]`.
+    Torus = 2,
+    /// Signed distance field of a box, with center in `data[0..3]`, half size in `data[3..6]` and
+    /// the radius of the rounded edges in `data[6]`.
+    RoundedBox = 3,
+}
+
+/// Laid out as `VkAabbPositionsKHR` followed by data for the intersection shader, which reads the
+/// primitive with `meshInfoBuffer[InstanceID()].primitives[PrimitiveIndex()]`.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+pub struct ProceduralPrimitive {
+    pub min: Vec3,
+    pub max: Vec3,
+    /// A `ProceduralShape`, or a value of a custom intersection shader.
+    pub shape: u32,
+    /// Parameters of the shape, such as a sphere's center and radius.
+    pub data: [f32; 7],
+}
+
+impl ProceduralPrimitive {
+    pub fn sphere(center: Vec3, radius: f32) -> Self {
+        Self {
+            min: center - radius,
+            max: center + radius,
+            shape: ProceduralShape::Sphere as u32,
+            data: [center.x, center.y, center.z, radius, 0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn capsule(start: Vec3, end: Vec3, radius: f32) -> Self {
+        Self {
+            min: start.min(end) - radius,
+            max: start.max(end) + radius,
+            shape: ProceduralShape::Capsule as u32,
+            data: [start.x, start.y, start.z, end.x, end.y, end.z, radius],
+        }
+    }
+
+    pub fn torus(center: Vec3, major_radius: f32, minor_radius: f32) -> Self {
+        let half_size = Vec3::new(
+            major_radius + minor_radius,
+            minor_radius,
+            major_radius + minor_radius,
+        );
+
+        Self {
+            min: center - half_size,
+            max: center + half_size,
+            shape: ProceduralShape::Torus as u32,
+            data: [
+                center.x,
+                center.y,
+                center.z,
+                major_radius,
+                minor_radius,
+                0.0,
+                0.0,
+            ],
+        }
+    }
+
+    /// `radius` is clamped to half of the smallest side, which rounds that side completely.
+    pub fn rounded_box(center: Vec3, half_size: Vec3, radius: f32) -> Self {
+        let radius = radius.clamp(0.0, half_size.min_element());
+
+        Self {
+            min: center - half_size,
+            max: center + half_size,
+            shape: ProceduralShape::RoundedBox as u32,
+            data: [
+                center.x,
+                center.y,
+                center.z,
+                half_size.x,
+                half_size.y,
+                half_size.z,
+                radius,
+            ],
+        }
+    }
+}
+
+/// Renders procedural geometry, like `Mesh3d` does for meshes.
+#[derive(Component, Clone, Default, Debug, Deref, DerefMut, PartialEq)]
+#[require(Transform)]
+pub struct Procedural3d(pub Handle<ProceduralGeometry>);
+
+pub struct GpuProceduralGeometry {
+    pub render_device: RenderDevice,
+    pub primitive_buffer: Buffer<ProceduralPrimitive>,
+    pub mesh_index: u32,
+    pub blas: Blas,
+}
+
+impl RenderAsset for GpuProceduralGeometry {
+    type SourceAsset = ProceduralGeometry;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<RenderQueue>,
+        SResMut<MeshInfoBuffer>,
+    );
+
+    fn prepare(
+        source_asset: &Self::SourceAsset,
+        (render_device, render_queue, mesh_info_buffer): &mut SystemParamItem<Self::Param>,
+        previous_asset: Option<&Self>,
+    ) -> Result<Self> {
+        if let Some(previous_asset) = previous_asset {
+            mesh_info_buffer.remove(previous_asset.mesh_index)?;
+        }
+
+        if source_asset.primitives.is_empty() {
+            return Err(anyhow!("Procedural geometry has no primitives"));
+        }
+
+        let mut primitive_buffer = render_device.create_buffer(
+            source_asset.primitives.len() as u64,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            MemoryLocation::CpuToGpu,
+            Some("Procedural Primitive Buffer"),
+        )?;
+
+        primitive_buffer
+            .slice_mut()?
+            .copy_from_slice(&source_asset.primitives);
+
+        // Shares the mesh info buffer so that `InstanceID()` indexes both kinds of geometry
+        let mesh_index = mesh_info_buffer.insert(MeshInfo {
+            primitive_buffer_address: primitive_buffer.address,
+            ..default()
+        })?;
+
+        let blas = render_device.create_aabb_blas(render_queue, &primitive_buffer)?;
+        let render_device = render_device.clone();
+
+        Ok(Self {
+            render_device,
+            primitive_buffer,
+            mesh_index,
+            blas,
+        })
+    }
+}
+
+impl Drop for GpuProceduralGeometry {
+    fn drop(&mut self) {
+        self.render_device
+            .destroy_blas(std::mem::take(&mut self.blas));
+
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.primitive_buffer));
+    }
+}
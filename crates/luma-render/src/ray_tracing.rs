@@ -1,24 +1,32 @@
 use std::borrow::Cow;
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use ash::vk;
-use bevy::{asset::AssetPath, prelude::*};
+use bevy::{diagnostic::FrameCount, prelude::*};
 use bytemuck::{Pod, Zeroable};
 use gpu_allocator::MemoryLocation;
 
-use crate::camera::Camera;
+use crate::camera::{Camera, Projection};
 
 use super::{
     RenderDevice,
+    bindings::{
+        BindingsLayout, GpuRayTracingBindings, RayTracingBindings, RayTracingBindingsPlugin,
+    },
     buffer::Buffer,
+    light::DirectionalLight,
     mesh::{MeshInfoBuffer, MeshPlugin},
+    picking::PickingPlugin,
+    procedural::ProceduralPlugin,
+    reflection::{PipelineReflection, ShaderReflection},
     render_context::RenderContext,
     resource_state_tracker::{ImageState, ResourceStateTracker},
     schedule::{Render, RenderSystems},
-    shader::Shader,
-    storage_image::StorageImage,
-    swapchain::Swapchain,
+    shader::{Shader, ShaderDiagnostics, ShaderSettings},
+    texture::TexturePlugin,
     tlas::{Tlas, TlasPlugin},
+    tonemapping::TonemappingPlugin,
+    view::{View, ViewPlugin, Views},
 };
 
 pub struct RayTracingPlugin {
@@ -30,7 +38,16 @@ impl Plugin for RayTracingPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(self.shaders.clone())
             .insert_resource(self.settings.clone())
-            .add_plugins((TlasPlugin, MeshPlugin))
+            .add_plugins((
+                TlasPlugin,
+                MeshPlugin,
+                ProceduralPlugin,
+                TexturePlugin,
+                PickingPlugin,
+                RayTracingBindingsPlugin,
+                TonemappingPlugin,
+                ViewPlugin,
+            ))
             .add_systems(Startup, load_shaders)
             .add_systems(
                 Render,
@@ -42,15 +59,95 @@ impl Plugin for RayTracingPlugin {
     }
 }
 
-#[derive(Resource, Clone)]
+#[derive(Resource, Clone, PartialEq)]
 pub struct RayTracingSettings {
+    /// Scale of the rendered images relative to their viewports, applied to all cameras.
     pub resolution_scaling: f32,
+    /// Averages the samples of consecutive frames while nothing changes, which converges
+    /// anti-aliasing and depth of field.
+    pub accumulate: bool,
+    pub debug_view: DebugView,
 }
 
 impl Default for RayTracingSettings {
     fn default() -> Self {
         Self {
             resolution_scaling: 1.0,
+            accumulate: true,
+            debug_view: DebugView::None,
+        }
+    }
+}
+
+/// Replaces the shaded image with a visualisation of the geometry, for diagnosing bad imports.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum DebugView {
+    #[default]
+    None,
+    /// Interpolated vertex normals in world space.
+    ShadingNormal,
+    /// Face normals in world space.
+    GeometricNormal,
+    Uv,
+    Barycentrics,
+    /// A random colour per TLAS instance.
+    InstanceId,
+    /// A random colour per mesh, shared by all instances of it.
+    MeshId,
+    /// A random colour per triangle.
+    PrimitiveId,
+    /// Hit distance on a logarithmic scale from black at the camera to white at 1000 units.
+    Depth,
+    /// Number of candidate triangles the traversal intersected, from blue to red.
+    TraversalHeatmap,
+    /// Base colour of the material without shading.
+    Albedo,
+}
+
+impl DebugView {
+    pub const ALL: [Self; 11] = [
+        Self::None,
+        Self::ShadingNormal,
+        Self::GeometricNormal,
+        Self::Uv,
+        Self::Barycentrics,
+        Self::InstanceId,
+        Self::MeshId,
+        Self::PrimitiveId,
+        Self::Depth,
+        Self::TraversalHeatmap,
+        Self::Albedo,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::ShadingNormal => "Shading Normal",
+            Self::GeometricNormal => "Geometric Normal",
+            Self::Uv => "UV",
+            Self::Barycentrics => "Barycentrics",
+            Self::InstanceId => "Instance ID",
+            Self::MeshId => "Mesh ID",
+            Self::PrimitiveId => "Primitive ID",
+            Self::Depth => "Depth",
+            Self::TraversalHeatmap => "Traversal Heatmap",
+            Self::Albedo => "Albedo",
+        }
+    }
+
+    fn index(&self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::ShadingNormal => 1,
+            Self::GeometricNormal => 2,
+            Self::Uv => 3,
+            Self::Barycentrics => 4,
+            Self::InstanceId => 5,
+            Self::MeshId => 6,
+            Self::PrimitiveId => 7,
+            Self::Depth => 8,
+            Self::TraversalHeatmap => 9,
+            Self::Albedo => 10,
         }
     }
 }
@@ -59,14 +156,70 @@ impl Default for RayTracingSettings {
 pub struct RayTracingShaders {
     pub raygen: Cow<'static, str>,
     pub miss: Cow<'static, str>,
+    /// Miss shader of shadow rays, which mark their payload as unoccluded.
+    pub shadow_miss: Cow<'static, str>,
+    /// Shading models that materials select by index with `Material::hit_group`. Must not be empty.
+    pub hit_groups: Vec<HitGroupShaders>,
+    /// Material evaluations that materials select by index with `Material::callable`, which the
+    /// closest-hit shaders call with a `MaterialCall` instead of shading the surface themselves.
+    pub callables: Vec<Cow<'static, str>>,
+    /// Preprocessor macros that all ray tracing shaders are compiled with, such as
+    /// `USER_PUSH_CONSTANTS` for the members of `RayTracingBindings::set_push_constants`. Shaders
+    /// are identified by path, so these files must not be loaded elsewhere with other settings.
+    pub defines: Vec<(String, String)>,
+    /// Specialization constants of all ray tracing shaders as constant IDs and 32-bit values,
+    /// see `RayTracingPipelineBuilder::with_specialization_constant`.
+    pub specialization_constants: Vec<(u32, u32)>,
+}
+
+#[derive(Clone)]
+pub struct HitGroupShaders {
     pub closest_hit: Cow<'static, str>,
+    /// Decides whether hits on non-opaque materials count, such as alpha-tested ones.
+    pub any_hit: Option<Cow<'static, str>>,
+    /// Any-hit shader of shadow rays, which carry a `ShadowPayload` instead.
+    pub shadow_any_hit: Option<Cow<'static, str>>,
+    /// Finds the surfaces of `Procedural3d` geometry, which hit groups without one cannot shade.
+    pub intersection: Option<Cow<'static, str>>,
 }
 
 #[derive(Resource)]
 struct RayTracingShaderHandles {
     raygen: Handle<Shader>,
     miss: Handle<Shader>,
+    shadow_miss: Handle<Shader>,
+    hit_groups: Vec<HitGroupShaderHandles>,
+    callables: Vec<Handle<Shader>>,
+    specialization_constants: Vec<(u32, u32)>,
+}
+
+struct HitGroupShaderHandles {
     closest_hit: Handle<Shader>,
+    any_hit: Option<Handle<Shader>>,
+    shadow_any_hit: Option<Handle<Shader>>,
+    intersection: Option<Handle<Shader>>,
+}
+
+impl RayTracingShaderHandles {
+    /// Whether the pipeline is built from the shader with the given ID.
+    fn contains(&self, id: AssetId<Shader>) -> bool {
+        let hit_group_handles = self.hit_groups.iter().flat_map(|hit_group| {
+            [
+                Some(&hit_group.closest_hit),
+                hit_group.any_hit.as_ref(),
+                hit_group.shadow_any_hit.as_ref(),
+                hit_group.intersection.as_ref(),
+            ]
+            .into_iter()
+            .flatten()
+        });
+
+        [&self.raygen, &self.miss, &self.shadow_miss]
+            .into_iter()
+            .chain(hit_group_handles)
+            .chain(&self.callables)
+            .any(|handle| handle.id() == id)
+    }
 }
 
 fn load_shaders(
@@ -74,10 +227,34 @@ fn load_shaders(
     asset_server: Res<AssetServer>,
     shaders: Res<RayTracingShaders>,
 ) {
+    let settings = ShaderSettings {
+        defines: shaders.defines.clone(),
+        ..default()
+    };
+
+    let load = |path: &str| -> Handle<Shader> {
+        let settings = settings.clone();
+        asset_server.load_with_settings(path.to_owned(), move |s: &mut ShaderSettings| {
+            *s = settings.clone();
+        })
+    };
+
     commands.insert_resource(RayTracingShaderHandles {
-        raygen: asset_server.load(AssetPath::parse(&shaders.raygen)),
-        miss: asset_server.load(AssetPath::parse(&shaders.miss)),
-        closest_hit: asset_server.load(AssetPath::parse(&shaders.closest_hit)),
+        raygen: load(&shaders.raygen),
+        miss: load(&shaders.miss),
+        shadow_miss: load(&shaders.shadow_miss),
+        hit_groups: shaders
+            .hit_groups
+            .iter()
+            .map(|hit_group| HitGroupShaderHandles {
+                closest_hit: load(&hit_group.closest_hit),
+                any_hit: hit_group.any_hit.as_deref().map(load),
+                shadow_any_hit: hit_group.shadow_any_hit.as_deref().map(load),
+                intersection: hit_group.intersection.as_deref().map(load),
+            })
+            .collect(),
+        callables: shaders.callables.iter().map(|path| load(path)).collect(),
+        specialization_constants: shaders.specialization_constants.clone(),
     });
 }
 
@@ -85,91 +262,191 @@ fn load_shaders(
 fn create_or_update_ray_tracing_pipeline(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
-    swapchain: Res<Swapchain>,
-    mut resource_state_tracker: ResMut<ResourceStateTracker>,
-    ray_tracing_pipeline: Option<ResMut<RayTracingPipeline>>,
-    settings: Res<RayTracingSettings>,
     ray_tracing_shaders: Res<RayTracingShaderHandles>,
+    ray_tracing_bindings: Res<RayTracingBindings>,
     assets: Res<Assets<Shader>>,
     mut asset_events: MessageReader<AssetEvent<Shader>>,
-) -> Result<(), BevyError> {
+    mut shader_diagnostics: ResMut<ShaderDiagnostics>,
+    mut built_layout: Local<Option<BindingsLayout>>,
+) {
     let Some(raygen_shader) = assets.get(&ray_tracing_shaders.raygen) else {
-        return Ok(());
+        return;
     };
 
     let Some(miss_shader) = assets.get(&ray_tracing_shaders.miss) else {
-        return Ok(());
+        return;
     };
 
-    let Some(closest_hit_shader) = assets.get(&ray_tracing_shaders.closest_hit) else {
-        return Ok(());
+    let Some(shadow_miss_shader) = assets.get(&ray_tracing_shaders.shadow_miss) else {
+        return;
     };
 
-    let is_shader_modified = asset_events
-        .read()
-        .any(|asset_event| matches!(asset_event, AssetEvent::Modified { .. },));
+    let Some(hit_group_shaders) = ray_tracing_shaders
+        .hit_groups
+        .iter()
+        .map(|hit_group| {
+            // Outer `None` while a shader is loading, inner `None` if the hit group has none
+            let get_optional = |handle: &Option<Handle<Shader>>| match handle {
+                Some(handle) => assets.get(handle).map(Some),
+                None => Some(None),
+            };
+
+            Some((
+                assets.get(&hit_group.closest_hit)?,
+                get_optional(&hit_group.any_hit)?,
+                get_optional(&hit_group.shadow_any_hit)?,
+                get_optional(&hit_group.intersection)?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+
+    let Some(callable_shaders) = ray_tracing_shaders
+        .callables
+        .iter()
+        .map(|callable| assets.get(callable))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
 
-    let vk::Extent2D { width, height } = swapchain.surface_extent;
+    // Shaders of other pipelines, like picking and tonemapping, don't concern this one
+    let is_shader_modified = asset_events.read().any(|asset_event| {
+        matches!(asset_event, AssetEvent::Modified { id } if ray_tracing_shaders.contains(*id))
+    });
+
+    let bindings_layout = ray_tracing_bindings.layout();
+
+    // A pipeline that failed to build is only retried once the shaders or bindings change
+    if !is_shader_modified && built_layout.as_ref() == Some(&bindings_layout) {
+        return;
+    }
 
-    let extent = vk::Extent2D {
-        width: (width as f32 * settings.resolution_scaling) as u32,
-        height: (height as f32 * settings.resolution_scaling) as u32,
+    *built_layout = Some(bindings_layout.clone());
+
+    // Miss shaders and hit groups are indexed by ray type, see `RayTracingPipeline::RAY_TYPE_COUNT`
+    let build = || -> Result<RayTracingPipeline> {
+        let mut builder = RayTracingPipeline::builder(render_device.clone())
+            .with_bindings_layout(bindings_layout)
+            .with_raygen_shader_group(raygen_shader)?
+            .with_miss_shader_group(miss_shader)?
+            .with_miss_shader_group(shadow_miss_shader)?;
+
+        // Shadow rays skip the closest-hit shader, so their hit groups only find and filter hits
+        for &(closest_hit, any_hit, shadow_any_hit, intersection) in &hit_group_shaders {
+            builder = builder
+                .with_hit_shader_group(Some(closest_hit), any_hit, intersection)?
+                .with_hit_shader_group(None, shadow_any_hit, intersection)?;
+        }
+
+        for &callable_shader in &callable_shaders {
+            builder = builder.with_callable_shader_group(callable_shader)?;
+        }
+
+        for &(constant_id, value) in &ray_tracing_shaders.specialization_constants {
+            builder = builder.with_specialization_constant(constant_id, value);
+        }
+
+        builder.build()
     };
 
-    match (ray_tracing_pipeline, is_shader_modified) {
-        (None, false) | (_, true) => {
-            commands.insert_resource(
-                RayTracingPipeline::builder(render_device.clone())
-                    .with_raygen_shader_group(raygen_shader)?
-                    .with_miss_shader_group(miss_shader)?
-                    .with_hit_shader_group(closest_hit_shader, None, None)?
-                    .build(extent)?,
-            );
+    // The last good pipeline stays in use until the error is fixed
+    match build() {
+        Ok(pipeline) => {
+            shader_diagnostics.remove(RayTracingPipeline::NAME);
+            commands.insert_resource(pipeline);
         }
-        (Some(mut ray_tracing_pipeline), false) => {
-            if ray_tracing_pipeline.storage_image.extent != extent {
-                ray_tracing_pipeline.recreate_storage_image(extent, &mut resource_state_tracker)?;
-            }
+        Err(err) => {
+            error!("Failed to build ray tracing pipeline: {err}");
+            shader_diagnostics.insert(RayTracingPipeline::NAME, &err.to_string());
         }
     }
-
-    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn execute_ray_tracing_pipeline(
-    swapchain: Res<Swapchain>,
     render_context: Res<RenderContext>,
     mut resource_state_tracker: ResMut<ResourceStateTracker>,
     ray_tracing_pipeline: Option<Res<RayTracingPipeline>>,
     tlas: Option<Res<Tlas>>,
     mesh_info_buffer: Res<MeshInfoBuffer>,
-    camera: Query<(&Camera, &Transform), With<Camera>>,
+    ray_tracing_bindings: Res<GpuRayTracingBindings>,
+    settings: Res<RayTracingSettings>,
+    frame_count: Res<FrameCount>,
+    mut views: ResMut<Views>,
+    cameras: Query<(Ref<Camera>, Ref<Transform>)>,
+    lights: Query<(Entity, Ref<DirectionalLight>, Ref<GlobalTransform>)>,
+    mut removed_lights: RemovedComponents<DirectionalLight>,
 ) -> Result<(), BevyError> {
     let Some(ray_tracing_pipeline) = ray_tracing_pipeline else {
         return Ok(());
     };
 
+    // Without any instances there is nothing to trace, so the views are cleared to black instead
     let Some(tlas) = tlas else {
+        for view in views.values_mut() {
+            render_context.render_device.clear_image(
+                render_context.command_buffer,
+                &mut resource_state_tracker,
+                view.radiance_image.image,
+            );
+
+            view.sample_count = 0;
+        }
+
         return Ok(());
     };
 
-    let (camera, camera_transform) = camera.single()?;
+    // The descriptor sets of the previous frame are no longer in use once it has finished
+    ray_tracing_pipeline.reset_descriptor_sets()?;
+
+    // The light with the lowest entity is used, so the choice stays stable between frames
+    let light = lights.iter().min_by_key(|(entity, ..)| *entity);
 
-    ray_tracing_pipeline.trace_rays(
-        render_context.command_buffer,
-        &mut resource_state_tracker,
-        &tlas,
-        &mesh_info_buffer,
-        camera_transform,
-        camera.sensor.vertical_fov(camera.focal_length),
-    );
+    let is_light_changed = removed_lights.read().count() > 0
+        || light
+            .as_ref()
+            .is_some_and(|(_, light, transform)| light.is_changed() || transform.is_changed());
 
-    ray_tracing_pipeline.blit(
-        render_context.command_buffer,
-        &mut resource_state_tracker,
-        swapchain.current_image().image,
-        swapchain.surface_extent,
-    );
+    // The TLAS is rebuilt whenever meshes, their transforms or materials change
+    let is_scene_changed = tlas.is_changed()
+        || settings.is_changed()
+        || ray_tracing_pipeline.is_changed()
+        || is_light_changed;
+
+    for (entity, view) in views.iter_mut() {
+        let Ok((camera, camera_transform)) = cameras.get(*entity) else {
+            continue;
+        };
+
+        if is_scene_changed
+            || camera.is_changed()
+            || camera_transform.is_changed()
+            || !settings.accumulate
+        {
+            view.sample_count = 0;
+        }
+
+        ray_tracing_pipeline.trace_rays(
+            render_context.command_buffer,
+            &mut resource_state_tracker,
+            view,
+            &tlas,
+            &mesh_info_buffer,
+            &ray_tracing_bindings,
+            &camera,
+            &camera_transform,
+            light
+                .as_ref()
+                .map(|(_, light, transform)| (&**light, &**transform)),
+            frame_count.0,
+            settings.debug_view,
+        )?;
+
+        view.sample_count = view.sample_count.saturating_add(1);
+    }
 
     Ok(())
 }
@@ -180,71 +457,246 @@ pub struct RayTracingPipeline {
     pub pipeline: vk::Pipeline,
     pub pipeline_layout: vk::PipelineLayout,
     pub shader_binding_table: ShaderBindingTable,
-    pub storage_image: StorageImage,
-    pub descriptor_set: vk::DescriptorSet,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Holds one descriptor set per view traced in the current frame.
+    pub descriptor_pool: vk::DescriptorPool,
+    /// Stages that access the push constants according to the shaders' reflection.
+    pub push_constant_stages: vk::ShaderStageFlags,
+    /// Application bindings written by `trace_rays` after the renderer's own, and push constants,
+    /// which are the application's alone.
+    pub bindings_layout: BindingsLayout,
 }
 
 impl RayTracingPipeline {
+    /// Format of the images traced into, which hold linear HDR radiance.
+    pub const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+    /// Maximum number of views that can be traced per frame.
+    pub const MAX_VIEWS: u32 = 16;
+
+    /// Origin of build errors in `ShaderDiagnostics`.
+    pub const NAME: &str = "Ray tracing pipeline";
+
+    /// Number of ray types, i.e. primary and shadow rays, which is the stride between the hit
+    /// groups in the shader binding table. Must match `RAY_TYPE_COUNT` in `common.slang`.
+    pub const RAY_TYPE_COUNT: u32 = 2;
+
+    /// Depth of rays traced from within hit shaders, which is 2 since closest-hit shaders trace
+    /// shadow rays.
+    pub const RAY_RECURSION_DEPTH: u32 = 2;
+
+    /// Size of a view's uniform buffer, which holds its camera and frame data.
+    pub const VIEW_UNIFORMS_SIZE: u64 = size_of::<ViewUniforms>() as u64;
+
+    /// Binding of the view's uniform buffer, `view` in `common.slang`.
+    const VIEW_UNIFORMS_BINDING: u32 = 4;
+
+    /// Bindings written by `trace_rays`, which the shaders may use a subset of.
+    const BINDINGS: [(u32, vk::DescriptorType); 5] = [
+        (0, vk::DescriptorType::STORAGE_IMAGE),
+        (1, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+        (2, vk::DescriptorType::STORAGE_BUFFER),
+        (3, vk::DescriptorType::STORAGE_BUFFER),
+        (
+            Self::VIEW_UNIFORMS_BINDING,
+            vk::DescriptorType::UNIFORM_BUFFER,
+        ),
+    ];
+
     pub fn builder<'a>(render_device: RenderDevice) -> RayTracingPipelineBuilder<'a> {
         RayTracingPipelineBuilder::new(render_device)
     }
 
+    /// Frees the descriptor sets allocated by `trace_rays`, which must no longer be in use.
+    pub fn reset_descriptor_sets(&self) -> Result<()> {
+        unsafe {
+            self.render_device.device.reset_descriptor_pool(
+                self.descriptor_pool,
+                vk::DescriptorPoolResetFlags::empty(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn trace_rays(
         &self,
         command_buffer: vk::CommandBuffer,
         tracker: &mut ResourceStateTracker,
+        view: &mut View,
         tlas: &Tlas,
         mesh_info_buffer: &MeshInfoBuffer,
+        bindings: &GpuRayTracingBindings,
+        camera: &Camera,
         camera_transform: &Transform,
-        camera_fov: f32,
-    ) {
-        unsafe {
+        light: Option<(&DirectionalLight, &GlobalTransform)>,
+        frame: u32,
+        debug_view: DebugView,
+    ) -> Result<()> {
+        let vk::Extent2D { width, height } = view.extent();
+        let aspect_ratio = width as f32 / height as f32;
+
+        // A black light is the same as no light, which the shaders skip lighting and shadows for
+        let (light_direction, light_color, light_shadows) =
+            light.map_or((Vec3::ZERO, Vec3::ZERO, false), |(light, transform)| {
+                (
+                    transform.back().as_vec3(),
+                    light.color.to_linear().to_vec3(),
+                    light.shadows,
+                )
+            });
+
+        let view_uniforms = ViewUniforms {
+            camera_translation: camera_transform.translation,
+            camera_rotation: Mat3::from_quat(camera_transform.rotation),
+            projection: camera.projection.index(),
+            image_plane: camera.image_plane(aspect_ratio),
+            fisheye_fov: match camera.projection {
+                Projection::Fisheye { fov, .. } => fov,
+                _ => 0.0,
+            },
+            focus_distance: camera.focus_distance,
+            aperture_radius: if camera.depth_of_field {
+                camera.aperture_radius()
+            } else {
+                0.0
+            },
+            aperture_blades: camera.bokeh.blades,
+            aperture_rotation: camera.bokeh.rotation,
+            frame,
+            sample_count: view.sample_count,
+            debug_view: debug_view.index(),
+            light_direction,
+            light_color,
+            light_shadows: light_shadows.into(),
+        };
+
+        // The previous frame, which read the buffer, has finished by now
+        view.uniform_buffer
+            .slice_mut()?
+            .copy_from_slice(bytemuck::bytes_of(&view_uniforms));
+
+        let descriptor_set = unsafe {
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(std::slice::from_ref(&self.descriptor_set_layout));
+
+            let [descriptor_set] = self
+                .render_device
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                .map_err(|err| {
+                    anyhow!(
+                        "Failed to allocate descriptor set, at most {} views are supported: {err}",
+                        Self::MAX_VIEWS
+                    )
+                })?
+                .try_into()
+                .map_err(|_| anyhow!("Failed to allocate exactly one descriptor set"))?;
+
+            let image_info = vk::DescriptorImageInfo::default()
+                .image_view(view.radiance_image.image_view)
+                .image_layout(vk::ImageLayout::GENERAL);
+
             let mut acceleration_structure_info =
                 vk::WriteDescriptorSetAccelerationStructureKHR::default()
                     .acceleration_structures(std::slice::from_ref(tlas.acceleration_structure()));
 
-            self.render_device.device.update_descriptor_sets(
-                &[vk::WriteDescriptorSet::default()
-                    .dst_set(self.descriptor_set)
-                    .dst_binding(1)
-                    .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                    .descriptor_count(1)
-                    .push_next(&mut acceleration_structure_info)],
-                &[],
-            );
-
             let descriptor_buffer_info = vk::DescriptorBufferInfo::default()
                 .buffer(mesh_info_buffer.buffer.buffer)
                 .offset(0)
                 .range(vk::WHOLE_SIZE);
 
-            self.render_device.device.update_descriptor_sets(
-                &[vk::WriteDescriptorSet::default()
-                    .dst_set(self.descriptor_set)
+            let instance_info_buffer_info = vk::DescriptorBufferInfo::default()
+                .buffer(tlas.instance_info_buffer().buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE);
+
+            let view_uniforms_buffer_info = vk::DescriptorBufferInfo::default()
+                .buffer(view.uniform_buffer.buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE);
+
+            // Bound for the layout the pipeline was built with, which lags behind changes to the
+            // bindings until the pipeline has been rebuilt
+            let user_buffer_infos = self
+                .bindings_layout
+                .bindings
+                .iter()
+                .map(|&(binding, descriptor_type)| {
+                    let buffer_info = vk::DescriptorBufferInfo::default()
+                        .buffer(bindings.buffer(binding, descriptor_type).buffer)
+                        .offset(0)
+                        .range(vk::WHOLE_SIZE);
+
+                    (binding, descriptor_type, buffer_info)
+                })
+                .collect::<Vec<_>>();
+
+            let mut writes = vec![
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(std::slice::from_ref(&image_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+                    .descriptor_count(1)
+                    .push_next(&mut acceleration_structure_info),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
                     .dst_binding(2)
                     .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
                     .descriptor_count(1)
-                    .buffer_info(std::slice::from_ref(&descriptor_buffer_info))],
-                &[],
-            );
-        }
+                    .buffer_info(std::slice::from_ref(&descriptor_buffer_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(3)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .buffer_info(std::slice::from_ref(&instance_info_buffer_info)),
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(Self::VIEW_UNIFORMS_BINDING)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .descriptor_count(1)
+                    .buffer_info(std::slice::from_ref(&view_uniforms_buffer_info)),
+            ];
+
+            writes.extend(user_buffer_infos.iter().map(
+                |(binding, descriptor_type, buffer_info)| {
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(*binding)
+                        .descriptor_type(*descriptor_type)
+                        .descriptor_count(1)
+                        .buffer_info(std::slice::from_ref(buffer_info))
+                },
+            ));
+
+            self.render_device
+                .device
+                .update_descriptor_sets(&writes, &[]);
+
+            descriptor_set
+        };
 
         tracker
             .transition_image(
-                self.storage_image.image,
+                view.radiance_image.image,
                 ImageState {
                     layout: vk::ImageLayout::GENERAL,
-                    access: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    access: vk::AccessFlags2::SHADER_STORAGE_READ
+                        | vk::AccessFlags2::SHADER_STORAGE_WRITE,
                     stages: vk::PipelineStageFlags2::RAY_TRACING_SHADER_KHR,
                 },
             )
             .flush(&self.render_device, command_buffer);
 
-        let push_constants = PushConstants {
-            camera_translation: camera_transform.translation,
-            camera_rotation: Mat3::from_quat(camera_transform.rotation),
-            camera_fov,
-        };
+        let push_constants = bindings.push_constants(self.bindings_layout.push_constant_size);
 
         unsafe {
             self.render_device.device.cmd_bind_pipeline(
@@ -258,17 +710,19 @@ impl RayTracingPipeline {
                 vk::PipelineBindPoint::RAY_TRACING_KHR,
                 self.pipeline_layout,
                 0,
-                &[self.descriptor_set],
+                &[descriptor_set],
                 &[],
             );
 
-            self.render_device.device.cmd_push_constants(
-                command_buffer,
-                self.pipeline_layout,
-                vk::ShaderStageFlags::RAYGEN_KHR,
-                0,
-                bytemuck::bytes_of(&push_constants),
-            );
+            if !push_constants.is_empty() {
+                self.render_device.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    self.push_constant_stages,
+                    0,
+                    &push_constants,
+                );
+            }
 
             self.render_device
                 .ray_tracing_pipeline_device
@@ -278,99 +732,12 @@ impl RayTracingPipeline {
                     &self.shader_binding_table.miss_region,
                     &self.shader_binding_table.hit_region,
                     &self.shader_binding_table.callable_region,
-                    self.storage_image.extent.width,
-                    self.storage_image.extent.height,
+                    width,
+                    height,
                     1,
                 );
         }
-    }
-
-    pub fn blit(
-        &self,
-        command_buffer: vk::CommandBuffer,
-        tracker: &mut ResourceStateTracker,
-        swapchain_image: vk::Image,
-        swapchain_image_extent: vk::Extent2D,
-    ) {
-        tracker
-            .transition_image(self.storage_image.image, ImageState::transfer_src())
-            .transition_image(swapchain_image, ImageState::transfer_dst())
-            .flush(&self.render_device, command_buffer);
 
-        let subresource = vk::ImageSubresourceLayers {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            mip_level: 0,
-            base_array_layer: 0,
-            layer_count: 1,
-        };
-
-        let x = self.storage_image.extent.width as i32;
-        let y = self.storage_image.extent.height as i32;
-
-        let src_offsets = [
-            vk::Offset3D { x: 0, y: 0, z: 0 },
-            vk::Offset3D { x, y, z: 1 },
-        ];
-
-        let x = swapchain_image_extent.width as i32;
-        let y = swapchain_image_extent.height as i32;
-
-        let dst_offsets = [
-            vk::Offset3D { x: 0, y: 0, z: 0 },
-            vk::Offset3D { x, y, z: 1 },
-        ];
-
-        let image_blit = vk::ImageBlit::default()
-            .src_subresource(subresource)
-            .src_offsets(src_offsets)
-            .dst_subresource(subresource)
-            .dst_offsets(dst_offsets);
-
-        unsafe {
-            self.render_device.device.cmd_blit_image(
-                command_buffer,
-                self.storage_image.image,
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                swapchain_image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[image_blit],
-                vk::Filter::LINEAR,
-            );
-        }
-    }
-
-    pub fn recreate_storage_image(
-        &mut self,
-        extent: vk::Extent2D,
-        tracker: &mut ResourceStateTracker,
-    ) -> Result<()> {
-        self.render_device.wait_idle();
-
-        let new_storage_image = self.render_device.create_storage_image(
-            extent,
-            self.storage_image.format,
-            Some(&self.storage_image.name),
-        )?;
-
-        let image_info = vk::DescriptorImageInfo::default()
-            .image_view(new_storage_image.image_view)
-            .image_layout(vk::ImageLayout::GENERAL);
-
-        let write_descriptor_set = vk::WriteDescriptorSet::default()
-            .dst_set(self.descriptor_set)
-            .dst_binding(0)
-            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-            .image_info(std::slice::from_ref(&image_info));
-
-        unsafe {
-            self.render_device
-                .device
-                .update_descriptor_sets(&[write_descriptor_set], &[]);
-        }
-
-        let old_storage_image = std::mem::replace(&mut self.storage_image, new_storage_image);
-        tracker.untrack_image(old_storage_image.image);
-        self.render_device.destroy_storage_image(old_storage_image);
         Ok(())
     }
 }
@@ -380,7 +747,11 @@ impl Drop for RayTracingPipeline {
         unsafe {
             self.shader_binding_table.destroy(&self.render_device);
             self.render_device
-                .destroy_storage_image(std::mem::take(&mut self.storage_image));
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.render_device
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
             self.render_device
                 .device
                 .destroy_pipeline(self.pipeline, None);
@@ -391,6 +762,7 @@ impl Drop for RayTracingPipeline {
     }
 }
 
+#[derive(Default)]
 pub struct ShaderBindingTable {
     buffer: Buffer,
     raygen_region: vk::StridedDeviceAddressRegionKHR,
@@ -410,9 +782,14 @@ pub struct RayTracingPipelineBuilder<'a> {
     shader_modules: Vec<vk::ShaderModule>,
     shader_stages: Vec<vk::PipelineShaderStageCreateInfo<'a>>,
     shader_groups: Vec<vk::RayTracingShaderGroupCreateInfoKHR<'a>>,
+    shader_reflections: Vec<(&'a ShaderReflection, vk::ShaderStageFlags)>,
+    specialization_entries: Vec<vk::SpecializationMapEntry>,
+    specialization_data: Vec<u8>,
     raygen_group_indices: Vec<usize>,
     miss_group_indices: Vec<usize>,
     hit_group_indices: Vec<usize>,
+    callable_group_indices: Vec<usize>,
+    bindings_layout: BindingsLayout,
 }
 
 impl<'a> RayTracingPipelineBuilder<'a> {
@@ -422,12 +799,44 @@ impl<'a> RayTracingPipelineBuilder<'a> {
             shader_modules: Vec::new(),
             shader_stages: Vec::new(),
             shader_groups: Vec::new(),
+            shader_reflections: Vec::new(),
+            specialization_entries: Vec::new(),
+            specialization_data: Vec::new(),
             raygen_group_indices: Vec::new(),
             miss_group_indices: Vec::new(),
             hit_group_indices: Vec::new(),
+            callable_group_indices: Vec::new(),
+            bindings_layout: BindingsLayout::default(),
         }
     }
 
+    /// Adds application bindings and push constants to the pipeline layout, which the shaders are
+    /// checked against like the renderer's own.
+    pub fn with_bindings_layout(mut self, bindings_layout: BindingsLayout) -> Self {
+        self.bindings_layout = bindings_layout;
+        self
+    }
+
+    /// Sets a specialization constant of all stages, which shaders declare as
+    /// `[vk::constant_id(ID)] const uint NAME = DEFAULT;`. Booleans have to be passed as `u32`.
+    pub fn with_specialization_constant<T: Pod>(mut self, constant_id: u32, value: T) -> Self {
+        let data = bytemuck::bytes_of(&value);
+
+        // Setting a constant again replaces it, leaving its previous value unused in the data
+        self.specialization_entries
+            .retain(|entry| entry.constant_id != constant_id);
+
+        self.specialization_entries.push(
+            vk::SpecializationMapEntry::default()
+                .constant_id(constant_id)
+                .offset(self.specialization_data.len() as u32)
+                .size(data.len()),
+        );
+
+        self.specialization_data.extend_from_slice(data);
+        self
+    }
+
     pub fn with_raygen_shader_group(mut self, shader: &'a Shader) -> Result<Self, vk::Result> {
         let raygen_stage_index =
             self.push_shader_stage(shader, vk::ShaderStageFlags::RAYGEN_KHR)?;
@@ -461,12 +870,15 @@ impl<'a> RayTracingPipelineBuilder<'a> {
 
     pub fn with_hit_shader_group(
         mut self,
-        closest_hit_shader: &'a Shader,
+        closest_hit_shader: Option<&'a Shader>,
         any_hit_shader: Option<&'a Shader>,
         intersection_shader: Option<&'a Shader>,
     ) -> Result<Self, vk::Result> {
-        let closest_hit_stage_index =
-            self.push_shader_stage(closest_hit_shader, vk::ShaderStageFlags::CLOSEST_HIT_KHR)?;
+        let closest_hit_stage_index = closest_hit_shader
+            .map(|closest_hit_shader| {
+                self.push_shader_stage(closest_hit_shader, vk::ShaderStageFlags::CLOSEST_HIT_KHR)
+            })
+            .transpose()?;
 
         let any_hit_stage_index = any_hit_shader
             .map(|any_hit_shader| {
@@ -489,7 +901,7 @@ impl<'a> RayTracingPipelineBuilder<'a> {
         let group_index = self.push_shader_group(
             shader_group_type,
             None,
-            Some(closest_hit_stage_index),
+            closest_hit_stage_index,
             any_hit_stage_index,
             intersection_stage_index,
         );
@@ -498,62 +910,124 @@ impl<'a> RayTracingPipelineBuilder<'a> {
         Ok(self)
     }
 
-    pub fn build(self, extent: vk::Extent2D) -> Result<RayTracingPipeline> {
-        unsafe {
-            let descriptor_set_layout_bindings = [
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(0)
-                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR),
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(1)
-                    .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR),
-                vk::DescriptorSetLayoutBinding::default()
-                    .binding(2)
-                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(
-                        vk::ShaderStageFlags::RAYGEN_KHR | vk::ShaderStageFlags::CLOSEST_HIT_KHR,
-                    ),
-            ];
+    /// Adds a callable shader, which shaders invoke with `CallShader(index, data)` where `index`
+    /// counts the callable shaders in the order they were added.
+    pub fn with_callable_shader_group(mut self, shader: &'a Shader) -> Result<Self, vk::Result> {
+        let callable_stage_index =
+            self.push_shader_stage(shader, vk::ShaderStageFlags::CALLABLE_KHR)?;
+
+        let group_index = self.push_shader_group(
+            vk::RayTracingShaderGroupTypeKHR::GENERAL,
+            Some(callable_stage_index),
+            None,
+            None,
+            None,
+        );
+
+        self.callable_group_indices.push(group_index);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<RayTracingPipeline> {
+        let reflection = self.reflect()?;
+
+        let descriptor_set_layout_bindings = reflection.descriptor_set_layout_bindings();
+        let push_constant_stages = reflection.push_constant_stages();
+        let push_constant_size = self.bindings_layout.push_constant_size;
+
+        let max_push_constants_size = unsafe {
+            self.render_device
+                .instance
+                .get_physical_device_properties(self.render_device.physical_device)
+                .limits
+                .max_push_constants_size
+        };
+
+        if push_constant_size > max_push_constants_size as usize {
+            bail!(
+                "Push constants are {push_constant_size} bytes, but the device supports at most {max_push_constants_size}"
+            );
+        }
+
+        let max_ray_recursion_depth = self
+            .render_device
+            .get_physical_device_ray_tracing_pipeline_properties()
+            .max_ray_recursion_depth;
+
+        if max_ray_recursion_depth < RayTracingPipeline::RAY_RECURSION_DEPTH {
+            bail!(
+                "Closest-hit shaders trace shadow rays, which needs a ray recursion depth of {}, but the device supports at most {max_ray_recursion_depth}",
+                RayTracingPipeline::RAY_RECURSION_DEPTH
+            );
+        }
+
+        // Objects are created into the pipeline, so that dropping it on an error destroys the
+        // ones created so far. Destroying null handles does nothing.
+        let mut ray_tracing_pipeline = RayTracingPipeline {
+            render_device: self.render_device.clone(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            shader_binding_table: ShaderBindingTable::default(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            push_constant_stages,
+            bindings_layout: self.bindings_layout.clone(),
+        };
 
+        unsafe {
             let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
                 .bindings(&descriptor_set_layout_bindings);
 
-            let descriptor_set_layout = self
+            ray_tracing_pipeline.descriptor_set_layout = self
                 .render_device
                 .device
                 .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)?;
 
-            let push_constant_range = vk::PushConstantRange::default()
-                .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
-                .offset(0)
-                .size(size_of::<PushConstants>() as u32);
+            // Only the application pushes constants, and Vulkan doesn't allow empty ranges
+            let push_constant_ranges = if push_constant_size > 0 {
+                vec![
+                    vk::PushConstantRange::default()
+                        .stage_flags(push_constant_stages)
+                        .offset(0)
+                        .size(push_constant_size as u32),
+                ]
+            } else {
+                Vec::new()
+            };
 
             let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
-                .set_layouts(std::slice::from_ref(&descriptor_set_layout))
-                .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+                .set_layouts(std::slice::from_ref(
+                    &ray_tracing_pipeline.descriptor_set_layout,
+                ))
+                .push_constant_ranges(&push_constant_ranges);
 
-            let pipeline_layout = self
+            ray_tracing_pipeline.pipeline_layout = self
                 .render_device
                 .device
                 .create_pipeline_layout(&pipeline_layout_create_info, None)?;
 
+            let specialization_info = vk::SpecializationInfo::default()
+                .map_entries(&self.specialization_entries)
+                .data(&self.specialization_data);
+
+            let shader_stages = self
+                .shader_stages
+                .iter()
+                .map(|stage| stage.specialization_info(&specialization_info))
+                .collect::<Vec<_>>();
+
             let pipeline_create_info = vk::RayTracingPipelineCreateInfoKHR::default()
-                .stages(&self.shader_stages)
+                .stages(&shader_stages)
                 .groups(&self.shader_groups)
-                .max_pipeline_ray_recursion_depth(1)
-                .layout(pipeline_layout);
+                .max_pipeline_ray_recursion_depth(RayTracingPipeline::RAY_RECURSION_DEPTH)
+                .layout(ray_tracing_pipeline.pipeline_layout);
 
             let ray_tracing_pipeline_device = &self.render_device.ray_tracing_pipeline_device;
 
             let pipelines = ray_tracing_pipeline_device
                 .create_ray_tracing_pipelines(
                     vk::DeferredOperationKHR::null(),
-                    vk::PipelineCache::null(),
+                    self.render_device.pipeline_cache,
                     &[pipeline_create_info],
                     None,
                 )
@@ -561,76 +1035,57 @@ impl<'a> RayTracingPipelineBuilder<'a> {
                     anyhow!("Failed to create ray tracing pipeline: {:?}", result)
                 })?;
 
-            let pipeline = pipelines
+            ray_tracing_pipeline.pipeline = pipelines
                 .into_iter()
                 .next()
                 .ok_or_else(|| anyhow!("Failed to create ray tracing pipeline"))?;
 
-            let shader_binding_table = self.build_sbt(pipeline)?;
-
-            for shader_module in self.shader_modules {
-                self.render_device
-                    .device
-                    .destroy_shader_module(shader_module, None);
-            }
-
-            let storage_image = self.render_device.create_storage_image(
-                extent,
-                vk::Format::R8G8B8A8_UNORM,
-                Some("Ray Tracing Pipeline Storage Image"),
-            )?;
+            ray_tracing_pipeline.shader_binding_table =
+                self.build_sbt(ray_tracing_pipeline.pipeline)?;
 
             let pool_sizes = descriptor_set_layout_bindings
                 .iter()
                 .map(|binding| {
                     vk::DescriptorPoolSize::default()
                         .ty(binding.descriptor_type)
-                        .descriptor_count(binding.descriptor_count)
+                        .descriptor_count(binding.descriptor_count * RayTracingPipeline::MAX_VIEWS)
                 })
                 .collect::<Vec<_>>();
 
             let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::default()
-                .max_sets(1)
+                .max_sets(RayTracingPipeline::MAX_VIEWS)
                 .pool_sizes(&pool_sizes);
 
-            let descriptor_pool = self
+            ray_tracing_pipeline.descriptor_pool = self
                 .render_device
                 .device
                 .create_descriptor_pool(&descriptor_pool_create_info, None)?;
+        }
 
-            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
-                .descriptor_pool(descriptor_pool)
-                .set_layouts(std::slice::from_ref(&descriptor_set_layout));
+        Ok(ray_tracing_pipeline)
+    }
 
-            let [descriptor_set] = self
-                .render_device
-                .device
-                .allocate_descriptor_sets(&descriptor_set_allocate_info)?
-                .try_into()
-                .map_err(|_| anyhow!("Failed to allocate exactly one descriptor set"))?;
+    /// Derives the pipeline layout from the shaders, so that drift between them and the
+    /// renderer fails here instead of rendering garbage.
+    fn reflect(&self) -> Result<PipelineReflection> {
+        let mut reflection = PipelineReflection::default();
 
-            let image_info = vk::DescriptorImageInfo::default()
-                .image_view(storage_image.image_view)
-                .image_layout(vk::ImageLayout::GENERAL);
+        for (shader_reflection, stage) in &self.shader_reflections {
+            reflection.add_stage(shader_reflection, *stage)?;
+        }
 
-            self.render_device.device.update_descriptor_sets(
-                &[vk::WriteDescriptorSet::default()
-                    .dst_set(descriptor_set)
-                    .dst_binding(0)
-                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                    .image_info(std::slice::from_ref(&image_info))],
-                &[],
-            );
+        let bindings = RayTracingPipeline::BINDINGS
+            .into_iter()
+            .chain(self.bindings_layout.bindings.iter().copied())
+            .collect::<Vec<_>>();
 
-            Ok(RayTracingPipeline {
-                render_device: self.render_device,
-                pipeline,
-                pipeline_layout,
-                shader_binding_table,
-                storage_image,
-                descriptor_set,
-            })
-        }
+        reflection.expect_bindings(&bindings)?;
+        reflection.expect_uniform_buffer::<ViewUniforms>(
+            RayTracingPipeline::VIEW_UNIFORMS_BINDING,
+            &ViewUniforms::OFFSETS,
+        )?;
+        reflection.expect_push_constants::<()>(&[], self.bindings_layout.push_constant_size)?;
+        Ok(reflection)
     }
 
     fn push_shader_stage(
@@ -646,6 +1101,8 @@ impl<'a> RayTracingPipelineBuilder<'a> {
         };
 
         self.shader_modules.push(shader_module);
+        self.shader_reflections
+            .push((&shader.reflection, stage_flag));
 
         let shader_stage = vk::PipelineShaderStageCreateInfo::default()
             .stage(stage_flag)
@@ -695,20 +1152,15 @@ impl<'a> RayTracingPipelineBuilder<'a> {
             (self.miss_group_indices.len() * region_stride).next_multiple_of(base_alignment);
         let hit_region_size =
             (self.hit_group_indices.len() * region_stride).next_multiple_of(base_alignment);
+        let callable_region_size =
+            (self.callable_group_indices.len() * region_stride).next_multiple_of(base_alignment);
 
         let raygen_region_offset = 0usize;
         let miss_region_offset = raygen_region_offset + raygen_region_size;
         let hit_region_offset = miss_region_offset + miss_region_size;
+        let callable_region_offset = hit_region_offset + hit_region_size;
 
-        let sbt_size = hit_region_offset + hit_region_size;
-
-        let mut buffer = self.render_device.create_buffer(
-            sbt_size as u64,
-            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
-                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-            MemoryLocation::CpuToGpu,
-            Some("Shader Binding Table Buffer"),
-        )?;
+        let sbt_size = callable_region_offset + callable_region_size;
 
         let group_count = self.shader_groups.len();
         let shader_handles = unsafe {
@@ -723,7 +1175,21 @@ impl<'a> RayTracingPipelineBuilder<'a> {
                 .map_err(|_| anyhow!("Failed to get shader group handles"))?
         };
 
-        let sbt_data = buffer.slice_mut()?;
+        let mut buffer = self.render_device.create_buffer(
+            sbt_size as u64,
+            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            MemoryLocation::CpuToGpu,
+            Some("Shader Binding Table Buffer"),
+        )?;
+
+        let sbt_data = match buffer.slice_mut() {
+            Ok(sbt_data) => sbt_data,
+            Err(err) => {
+                self.render_device.destroy_buffer(buffer);
+                return Err(err);
+            }
+        };
         sbt_data.fill(0);
 
         for (slot, &group_index) in self.raygen_group_indices.iter().enumerate() {
@@ -744,6 +1210,12 @@ impl<'a> RayTracingPipelineBuilder<'a> {
             sbt_data[dst..][..handle_size].copy_from_slice(&shader_handles[src..][..handle_size]);
         }
 
+        for (slot, &group_index) in self.callable_group_indices.iter().enumerate() {
+            let dst = callable_region_offset + slot * region_stride;
+            let src = group_index * handle_size;
+            sbt_data[dst..][..handle_size].copy_from_slice(&shader_handles[src..][..handle_size]);
+        }
+
         let sbt_address = buffer.address;
 
         let raygen_region = vk::StridedDeviceAddressRegionKHR::default()
@@ -761,7 +1233,10 @@ impl<'a> RayTracingPipelineBuilder<'a> {
             .stride(region_stride as u64)
             .size(hit_region_size as u64);
 
-        let callable_region = vk::StridedDeviceAddressRegionKHR::default();
+        let callable_region = vk::StridedDeviceAddressRegionKHR::default()
+            .device_address(sbt_address + callable_region_offset as u64)
+            .stride(region_stride as u64)
+            .size(callable_region_size as u64);
 
         Ok(ShaderBindingTable {
             buffer,
@@ -773,10 +1248,56 @@ impl<'a> RayTracingPipelineBuilder<'a> {
     }
 }
 
+impl Drop for RayTracingPipelineBuilder<'_> {
+    fn drop(&mut self) {
+        // Built pipelines no longer need the shader modules, and failed builds must not leak them
+        for &shader_module in &self.shader_modules {
+            unsafe {
+                self.render_device
+                    .device
+                    .destroy_shader_module(shader_module, None);
+            }
+        }
+    }
+}
+
+/// Camera and frame data of a view, `ViewUniforms` in `common.slang`.
 #[repr(C)]
 #[derive(Clone, Copy, Zeroable, Pod)]
-struct PushConstants {
+struct ViewUniforms {
     camera_translation: Vec3,
     camera_rotation: Mat3,
-    camera_fov: f32,
+    projection: u32,
+    image_plane: Vec2,
+    fisheye_fov: f32,
+    focus_distance: f32,
+    aperture_radius: f32,
+    aperture_blades: u32,
+    aperture_rotation: f32,
+    frame: u32,
+    sample_count: u32,
+    debug_view: u32,
+    light_direction: Vec3,
+    light_color: Vec3,
+    light_shadows: u32,
+}
+
+impl ViewUniforms {
+    const OFFSETS: [usize; 15] = [
+        offset_of!(Self, camera_translation),
+        offset_of!(Self, camera_rotation),
+        offset_of!(Self, projection),
+        offset_of!(Self, image_plane),
+        offset_of!(Self, fisheye_fov),
+        offset_of!(Self, focus_distance),
+        offset_of!(Self, aperture_radius),
+        offset_of!(Self, aperture_blades),
+        offset_of!(Self, aperture_rotation),
+        offset_of!(Self, frame),
+        offset_of!(Self, sample_count),
+        offset_of!(Self, debug_view),
+        offset_of!(Self, light_direction),
+        offset_of!(Self, light_color),
+        offset_of!(Self, light_shadows),
+    ];
 }
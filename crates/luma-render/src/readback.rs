@@ -0,0 +1,66 @@
+use ash::vk;
+
+use super::{
+    buffer::Buffer,
+    render_device::RenderDevice,
+    resource_state_tracker::{ImageState, ResourceStateTracker},
+};
+
+impl RenderDevice {
+    /// Records a copy of `image` into a host visible `buffer` and makes it available for reading
+    /// on the host once the command buffer has finished executing.
+    pub fn cmd_copy_image_to_readback_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        tracker: &mut ResourceStateTracker,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        buffer: &Buffer,
+    ) {
+        tracker
+            .transition_image(image, ImageState::transfer_src())
+            .flush(self, command_buffer);
+
+        let buffer_image_copy = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(extent.into());
+
+        let memory_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ);
+
+        unsafe {
+            self.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[buffer_image_copy],
+            );
+
+            self.device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            );
+        }
+    }
+}
+
+pub fn bytes_per_pixel(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => Some(4),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
@@ -0,0 +1,1067 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow, bail};
+use ash::vk;
+
+const MAGIC_NUMBER: u32 = 0x0723_0203;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+// Decorations
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ROW_MAJOR: u32 = 4;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+const STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+const DIM_BUFFER: u32 = 5;
+
+/// Resources that a compiled shader's entry point accesses, read from its SPIR-V.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<BlockLayout>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DescriptorBinding {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub descriptor_count: u32,
+    /// Layout of a uniform buffer's contents.
+    pub block: Option<BlockLayout>,
+}
+
+/// Layout of push constants or the contents of a uniform buffer.
+#[derive(Clone, Debug)]
+pub struct BlockLayout {
+    pub name: String,
+    pub size: u32,
+    /// Names and byte offsets of the block's members in declaration order.
+    pub members: Vec<(String, u32)>,
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    Bool,
+    Scalar { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, columns: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct,
+    Pointer { storage_class: u32, pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct Decorations {
+    binding: Option<u32>,
+    set: Option<u32>,
+    array_stride: Option<u32>,
+    buffer_block: bool,
+}
+
+#[derive(Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+}
+
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32, u32)>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+    /// Entry point names and the IDs of the global variables they reference.
+    entry_points: Vec<(String, Vec<u32>)>,
+}
+
+impl ShaderReflection {
+    /// Reflects the resources of `entry_point`. SPIR-V before version 1.4 only lists the inputs
+    /// and outputs of an entry point, in which case all global resources are reported.
+    pub fn new(code: &[u32], entry_point: &str) -> Result<Self> {
+        let module = Module::parse(code)?;
+        let version = code[1];
+
+        let interface = module
+            .entry_points
+            .iter()
+            .find(|(name, _)| name == entry_point)
+            .map(|(_, interface)| interface)
+            .ok_or_else(|| anyhow!("Entry point `{entry_point}` not found"))?;
+
+        let mut reflection = ShaderReflection::default();
+
+        for &(result_type, id, storage_class) in &module.variables {
+            if version >= 0x0001_0400 && !interface.contains(&id) {
+                continue;
+            }
+
+            let name = module.name(id);
+
+            let Some(Type::Pointer { pointee, .. }) = module.types.get(&result_type).copied()
+            else {
+                bail!("Variable `{name}` does not have a pointer type");
+            };
+
+            match storage_class {
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    reflection.push_constants = Some(module.block_layout(pointee, name)?);
+                }
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let decorations = module.decorations.get(&id);
+                    let set = decorations.and_then(|decorations| decorations.set);
+                    let binding = decorations.and_then(|decorations| decorations.binding);
+
+                    let (Some(set), Some(binding)) = (set, binding) else {
+                        bail!("Resource `{name}` has no descriptor set or binding");
+                    };
+
+                    let (descriptor_type, descriptor_count) =
+                        module.descriptor_type(pointee, storage_class, &name)?;
+
+                    let block = if descriptor_type == vk::DescriptorType::UNIFORM_BUFFER
+                        && descriptor_count == 1
+                    {
+                        Some(module.block_layout(pointee, name.clone())?)
+                    } else {
+                        None
+                    };
+
+                    reflection.bindings.push(DescriptorBinding {
+                        name,
+                        set,
+                        binding,
+                        descriptor_type,
+                        descriptor_count,
+                        block,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        reflection
+            .bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+        Ok(reflection)
+    }
+}
+
+impl Module {
+    fn parse(code: &[u32]) -> Result<Self> {
+        if code.len() < 5 || code[0] != MAGIC_NUMBER {
+            bail!("Not a SPIR-V module");
+        }
+
+        let mut module = Module::default();
+        let mut words = &code[5..];
+
+        while let Some(&first) = words.first() {
+            let word_count = (first >> 16) as usize;
+            let opcode = first & 0xFFFF;
+
+            if word_count == 0 || word_count > words.len() {
+                bail!("Malformed SPIR-V instruction");
+            }
+
+            let operands = &words[1..word_count];
+            words = &words[word_count..];
+            module.parse_instruction(opcode, operands)?;
+        }
+
+        Ok(module)
+    }
+
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) -> Result<()> {
+        let operand = |index: usize| {
+            operands
+                .get(index)
+                .copied()
+                .ok_or_else(|| anyhow!("Missing operand of SPIR-V instruction {opcode}"))
+        };
+
+        match opcode {
+            OP_NAME => {
+                let (name, _) = parse_string(operands.get(1..).unwrap_or_default());
+                self.names.insert(operand(0)?, name);
+            }
+            OP_MEMBER_NAME => {
+                let (name, _) = parse_string(operands.get(2..).unwrap_or_default());
+                self.member_names.insert((operand(0)?, operand(1)?), name);
+            }
+            OP_ENTRY_POINT => {
+                let (name, name_words) = parse_string(operands.get(2..).unwrap_or_default());
+                let interface = operands.get(2 + name_words..).unwrap_or_default().to_vec();
+                self.entry_points.push((name, interface));
+            }
+            OP_TYPE_BOOL => {
+                self.types.insert(operand(0)?, Type::Bool);
+            }
+            OP_TYPE_INT | OP_TYPE_FLOAT => {
+                let width = operand(1)?;
+                self.types.insert(operand(0)?, Type::Scalar { width });
+            }
+            OP_TYPE_VECTOR => {
+                let (component, count) = (operand(1)?, operand(2)?);
+                self.types
+                    .insert(operand(0)?, Type::Vector { component, count });
+            }
+            OP_TYPE_MATRIX => {
+                let (column, columns) = (operand(1)?, operand(2)?);
+                self.types
+                    .insert(operand(0)?, Type::Matrix { column, columns });
+            }
+            OP_TYPE_IMAGE => {
+                let (dim, sampled) = (operand(2)?, operand(6)?);
+                self.types.insert(operand(0)?, Type::Image { dim, sampled });
+            }
+            OP_TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0)?, Type::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                let element = operand(1)?;
+
+                // Lengths given by specialization constants are only known once the pipeline is
+                // created, so such arrays are treated like runtime arrays
+                let array = match self.constants.get(&operand(2)?) {
+                    Some(&length) => Type::Array { element, length },
+                    None => Type::RuntimeArray,
+                };
+
+                self.types.insert(operand(0)?, array);
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                self.types.insert(operand(0)?, Type::RuntimeArray);
+            }
+            OP_TYPE_STRUCT => {
+                self.types.insert(operand(0)?, Type::Struct);
+                self.struct_members
+                    .insert(operand(0)?, operands.get(1..).unwrap_or_default().to_vec());
+            }
+            OP_TYPE_POINTER => {
+                let (storage_class, pointee) = (operand(1)?, operand(2)?);
+
+                self.types.insert(
+                    operand(0)?,
+                    Type::Pointer {
+                        storage_class,
+                        pointee,
+                    },
+                );
+            }
+            OP_TYPE_ACCELERATION_STRUCTURE => {
+                self.types.insert(operand(0)?, Type::AccelerationStructure);
+            }
+            OP_CONSTANT => {
+                // Only the low word matters for array lengths
+                self.constants.insert(operand(1)?, operand(2)?);
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(0)?, operand(1)?, operand(2)?));
+            }
+            OP_DECORATE => {
+                let decorations = self.decorations.entry(operand(0)?).or_default();
+
+                match operand(1)? {
+                    DECORATION_BINDING => decorations.binding = Some(operand(2)?),
+                    DECORATION_DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                    DECORATION_ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                    DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                    _ => {}
+                }
+            }
+            OP_MEMBER_DECORATE => {
+                let decorations = self
+                    .member_decorations
+                    .entry((operand(0)?, operand(1)?))
+                    .or_default();
+
+                match operand(2)? {
+                    DECORATION_OFFSET => decorations.offset = Some(operand(3)?),
+                    DECORATION_MATRIX_STRIDE => decorations.matrix_stride = Some(operand(3)?),
+                    DECORATION_ROW_MAJOR => decorations.row_major = true,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names
+            .get(&id)
+            .filter(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("%{id}"))
+    }
+
+    fn get_type(&self, id: u32) -> Result<Type> {
+        self.types
+            .get(&id)
+            .copied()
+            .ok_or_else(|| anyhow!("Unsupported SPIR-V type %{id}"))
+    }
+
+    fn descriptor_type(
+        &self,
+        id: u32,
+        storage_class: u32,
+        name: &str,
+    ) -> Result<(vk::DescriptorType, u32)> {
+        let descriptor_type = match (self.get_type(id)?, storage_class) {
+            (Type::Array { element, length }, _) => {
+                let (descriptor_type, count) =
+                    self.descriptor_type(element, storage_class, name)?;
+                return Ok((descriptor_type, count * length));
+            }
+            (Type::RuntimeArray, _) => {
+                bail!(
+                    "Resource `{name}` is an unbounded array or sized by a specialization constant, which is not supported"
+                )
+            }
+            (_, STORAGE_CLASS_STORAGE_BUFFER) => vk::DescriptorType::STORAGE_BUFFER,
+            (_, STORAGE_CLASS_UNIFORM) => {
+                if self
+                    .decorations
+                    .get(&id)
+                    .is_some_and(|decorations| decorations.buffer_block)
+                {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            (Type::Image { dim, sampled }, _) => match (dim == DIM_BUFFER, sampled == 2) {
+                (false, true) => vk::DescriptorType::STORAGE_IMAGE,
+                (false, false) => vk::DescriptorType::SAMPLED_IMAGE,
+                (true, true) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (true, false) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            },
+            (Type::Sampler, _) => vk::DescriptorType::SAMPLER,
+            (Type::SampledImage, _) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (Type::AccelerationStructure, _) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            _ => bail!("Resource `{name}` has an unsupported type"),
+        };
+
+        Ok((descriptor_type, 1))
+    }
+
+    /// Members and size of a struct in push constants or a uniform buffer.
+    fn block_layout(&self, id: u32, name: String) -> Result<BlockLayout> {
+        let members = self
+            .struct_members
+            .get(&id)
+            .ok_or_else(|| anyhow!("Block `{name}` is not a struct"))?
+            .iter()
+            .enumerate()
+            .map(|(index, _)| {
+                let member = (id, index as u32);
+
+                let offset = self
+                    .member_decorations
+                    .get(&member)
+                    .and_then(|decorations| decorations.offset)
+                    .ok_or_else(|| anyhow!("Member of block `{name}` has no offset"))?;
+
+                let member_name = self
+                    .member_names
+                    .get(&member)
+                    .cloned()
+                    .unwrap_or_else(|| format!("#{index}"));
+
+                Ok((member_name, offset))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(BlockLayout {
+            size: self.size_of(id, None)?,
+            name,
+            members,
+        })
+    }
+
+    /// Size in bytes of a type in push constants or a uniform buffer. Matrices take their stride and
+    /// majorness from the decorations of the struct member they are declared as.
+    fn size_of(&self, id: u32, member: Option<&MemberDecorations>) -> Result<u32> {
+        let size = match self.get_type(id)? {
+            Type::Bool => 4,
+            Type::Scalar { width } => width / 8,
+            Type::Vector { component, count } => self.size_of(component, None)? * count,
+            Type::Matrix { column, columns } => {
+                let Type::Vector { count: rows, .. } = self.get_type(column)? else {
+                    bail!("Matrix columns must be vectors");
+                };
+
+                let column_size = self.size_of(column, None)?;
+
+                match member {
+                    Some(MemberDecorations {
+                        matrix_stride: Some(stride),
+                        row_major: true,
+                        ..
+                    }) => stride * rows,
+                    Some(MemberDecorations {
+                        matrix_stride: Some(stride),
+                        ..
+                    }) => stride * columns,
+                    _ => column_size * columns,
+                }
+            }
+            Type::Array { element, length } => {
+                let stride = match self
+                    .decorations
+                    .get(&id)
+                    .and_then(|decorations| decorations.array_stride)
+                {
+                    Some(stride) => stride,
+                    None => self.size_of(element, member)?,
+                };
+
+                stride * length
+            }
+            Type::Struct => {
+                let mut size = 0;
+
+                for (index, &member_type) in self.struct_members[&id].iter().enumerate() {
+                    let decorations = self.member_decorations.get(&(id, index as u32));
+
+                    let offset = decorations
+                        .and_then(|decorations| decorations.offset)
+                        .ok_or_else(|| anyhow!("Struct member has no offset"))?;
+
+                    size = size.max(offset + self.size_of(member_type, decorations)?);
+                }
+
+                size
+            }
+            Type::Pointer {
+                storage_class: STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER,
+                ..
+            } => 8,
+            _ => bail!("Type %{id} cannot be used in push constants or uniform buffers"),
+        };
+
+        Ok(size)
+    }
+}
+
+/// Decodes a null-terminated literal string, returning it and the number of words it spans.
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let bytes = bytemuck::cast_slice::<u32, u8>(words);
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    let string = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    (string, (len / 4 + 1).min(words.len()))
+}
+
+impl BlockLayout {
+    fn expect<T>(&self, kind: &str, offsets: &[usize], extra_size: usize) -> Result<()> {
+        let type_name = std::any::type_name::<T>();
+
+        if self.size as usize != size_of::<T>() + extra_size {
+            bail!(
+                "{kind} `{}` is {} bytes in the shaders, but `{type_name}` is {} bytes followed by {extra_size} more",
+                self.name,
+                self.size,
+                size_of::<T>()
+            );
+        }
+
+        if self.members.len() < offsets.len() {
+            bail!(
+                "{kind} `{}` has {} members in the shaders, but `{type_name}` has {}",
+                self.name,
+                self.members.len(),
+                offsets.len()
+            );
+        }
+
+        for ((name, offset), &expected) in self.members.iter().zip(offsets) {
+            if *offset as usize != expected {
+                bail!(
+                    "{kind} member `{name}` of `{}` is at offset {offset} in the shaders, but at {expected} in `{type_name}`",
+                    self.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Descriptor bindings and push constants of all stages of a pipeline, merged into the layout
+/// that the pipeline is created with.
+#[derive(Default)]
+pub struct PipelineReflection {
+    bindings: Vec<(DescriptorBinding, vk::ShaderStageFlags)>,
+    push_constants: Option<(BlockLayout, vk::ShaderStageFlags)>,
+    stages: vk::ShaderStageFlags,
+}
+
+impl PipelineReflection {
+    /// Adds the resources of a stage, failing if they disagree with those of earlier stages.
+    pub fn add_stage(
+        &mut self,
+        reflection: &ShaderReflection,
+        stage: vk::ShaderStageFlags,
+    ) -> Result<()> {
+        self.stages |= stage;
+
+        for binding in &reflection.bindings {
+            if binding.set != 0 {
+                bail!(
+                    "Resource `{}` is in descriptor set {}, but only set 0 is supported",
+                    binding.name,
+                    binding.set
+                );
+            }
+
+            let existing = self
+                .bindings
+                .iter_mut()
+                .find(|(existing, _)| existing.binding == binding.binding);
+
+            match existing {
+                Some((existing, stages)) => {
+                    let is_block_different = match (&existing.block, &binding.block) {
+                        (Some(existing), Some(block)) => {
+                            existing.size != block.size || existing.members != block.members
+                        }
+                        _ => false,
+                    };
+
+                    if existing.descriptor_type != binding.descriptor_type
+                        || existing.descriptor_count != binding.descriptor_count
+                        || is_block_different
+                    {
+                        bail!(
+                            "Binding {} is declared as both `{}` and `{}` with different types",
+                            binding.binding,
+                            existing.name,
+                            binding.name
+                        );
+                    }
+
+                    *stages |= stage;
+                }
+                None => self.bindings.push((binding.clone(), stage)),
+            }
+        }
+
+        if let Some(block) = &reflection.push_constants {
+            match &mut self.push_constants {
+                Some((existing, stages)) => {
+                    if existing.size != block.size || existing.members != block.members {
+                        bail!(
+                            "Push constants `{}` and `{}` differ between shader stages",
+                            existing.name,
+                            block.name
+                        );
+                    }
+
+                    *stages |= stage;
+                }
+                None => self.push_constants = Some((block.clone(), stage)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the shaders only use the given bindings with the given descriptor types, and
+    /// adds the ones they do not use so that the renderer can still write all of them.
+    pub fn expect_bindings(&mut self, expected: &[(u32, vk::DescriptorType)]) -> Result<()> {
+        for (binding, _) in &self.bindings {
+            let Some(&(_, descriptor_type)) = expected
+                .iter()
+                .find(|(expected, _)| *expected == binding.binding)
+            else {
+                bail!(
+                    "Binding {} `{}` is not provided by the renderer",
+                    binding.binding,
+                    binding.name
+                );
+            };
+
+            if binding.descriptor_type != descriptor_type || binding.descriptor_count != 1 {
+                bail!(
+                    "Binding {} `{}` is {} {:?} in the shaders, but the renderer binds one {:?}",
+                    binding.binding,
+                    binding.name,
+                    binding.descriptor_count,
+                    binding.descriptor_type,
+                    descriptor_type
+                );
+            }
+        }
+
+        for &(binding, descriptor_type) in expected {
+            if !self
+                .bindings
+                .iter()
+                .any(|(existing, _)| existing.binding == binding)
+            {
+                let unused = DescriptorBinding {
+                    name: String::new(),
+                    set: 0,
+                    binding,
+                    descriptor_type,
+                    descriptor_count: 1,
+                    block: None,
+                };
+
+                self.bindings.push((unused, self.stages));
+            }
+        }
+
+        self.bindings.sort_by_key(|(binding, _)| binding.binding);
+        Ok(())
+    }
+
+    /// Checks that the push constants of the shaders start with members at the given byte offsets
+    /// of `T`, which are usually taken with `offset_of!`, followed by `extra_size` bytes of other
+    /// members.
+    pub fn expect_push_constants<T>(&self, offsets: &[usize], extra_size: usize) -> Result<()> {
+        let Some((block, _)) = &self.push_constants else {
+            return Ok(());
+        };
+
+        block.expect::<T>("Push constant block", offsets, extra_size)
+    }
+
+    /// Checks that the uniform buffer at `binding` holds a `T` with members at the given byte
+    /// offsets, if the shaders use it.
+    pub fn expect_uniform_buffer<T>(&self, binding: u32, offsets: &[usize]) -> Result<()> {
+        let block = self
+            .bindings
+            .iter()
+            .find(|(existing, _)| existing.binding == binding)
+            .and_then(|(existing, _)| existing.block.as_ref());
+
+        let Some(block) = block else {
+            return Ok(());
+        };
+
+        block.expect::<T>("Uniform buffer", offsets, 0)
+    }
+
+    pub fn descriptor_set_layout_bindings(&self) -> Vec<vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings
+            .iter()
+            .map(|(binding, stages)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.descriptor_count)
+                    .stage_flags(*stages)
+            })
+            .collect()
+    }
+
+    /// Stages that access the push constants, all stages if none do since the renderer pushes
+    /// them regardless.
+    pub fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        self.push_constants
+            .as_ref()
+            .map_or(self.stages, |(_, stages)| *stages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_TYPE_VOID: u32 = 19;
+    const OP_SPEC_CONSTANT: u32 = 50;
+    const EXECUTION_MODEL_GL_COMPUTE: u32 = 5;
+    const DIM_2D: u32 = 1;
+    const IMAGE_FORMAT_RGBA32F: u32 = 1;
+    const VERSION_1_0: u32 = 0x0001_0000;
+    const VERSION_1_4: u32 = 0x0001_0400;
+
+    /// Assembles SPIR-V modules instruction by instruction, for fixtures that would otherwise
+    /// need a shader compiler.
+    struct Assembler {
+        words: Vec<u32>,
+    }
+
+    impl Assembler {
+        fn new(version: u32) -> Self {
+            Self {
+                words: vec![MAGIC_NUMBER, version, 0, 100, 0],
+            }
+        }
+
+        fn op(mut self, opcode: u32, operands: &[u32]) -> Self {
+            self.words
+                .push(((operands.len() as u32 + 1) << 16) | opcode);
+            self.words.extend_from_slice(operands);
+            self
+        }
+
+        fn named(self, opcode: u32, operands: &[u32], name: &str, rest: &[u32]) -> Self {
+            let mut bytes = name.as_bytes().to_vec();
+            bytes.resize(name.len() / 4 * 4 + 4, 0);
+
+            let operands = operands
+                .iter()
+                .copied()
+                .chain(
+                    bytes
+                        .chunks(4)
+                        .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap())),
+                )
+                .chain(rest.iter().copied())
+                .collect::<Vec<_>>();
+
+            self.op(opcode, &operands)
+        }
+
+        fn entry_point(self, interface: &[u32]) -> Self {
+            self.named(
+                OP_ENTRY_POINT,
+                &[EXECUTION_MODEL_GL_COMPUTE, 99],
+                "main",
+                interface,
+            )
+        }
+
+        /// `struct { float3 origin; uint index; }` as push constants `pc` with ID 6.
+        fn push_constants(self) -> Self {
+            self.op(OP_TYPE_FLOAT, &[1, 32])
+                .op(OP_TYPE_VECTOR, &[2, 1, 3])
+                .op(OP_TYPE_INT, &[3, 32, 0])
+                .op(OP_TYPE_STRUCT, &[4, 2, 3])
+                .op(OP_TYPE_POINTER, &[5, STORAGE_CLASS_PUSH_CONSTANT, 4])
+                .op(OP_VARIABLE, &[5, 6, STORAGE_CLASS_PUSH_CONSTANT])
+                .named(OP_NAME, &[6], "pc", &[])
+                .named(OP_MEMBER_NAME, &[4, 0], "origin", &[])
+                .named(OP_MEMBER_NAME, &[4, 1], "index", &[])
+                .op(OP_MEMBER_DECORATE, &[4, 0, DECORATION_OFFSET, 0])
+                .op(OP_MEMBER_DECORATE, &[4, 1, DECORATION_OFFSET, 12])
+        }
+
+        /// `struct { float3 origin; uint index; }` as uniform buffer `view` with ID 46 at binding
+        /// 4 in set 0.
+        fn uniform_buffer(self) -> Self {
+            self.op(OP_TYPE_FLOAT, &[41, 32])
+                .op(OP_TYPE_VECTOR, &[42, 41, 3])
+                .op(OP_TYPE_INT, &[43, 32, 0])
+                .op(OP_TYPE_STRUCT, &[44, 42, 43])
+                .op(OP_TYPE_POINTER, &[45, STORAGE_CLASS_UNIFORM, 44])
+                .op(OP_VARIABLE, &[45, 46, STORAGE_CLASS_UNIFORM])
+                .named(OP_NAME, &[46], "view", &[])
+                .named(OP_MEMBER_NAME, &[44, 0], "origin", &[])
+                .named(OP_MEMBER_NAME, &[44, 1], "index", &[])
+                .op(OP_MEMBER_DECORATE, &[44, 0, DECORATION_OFFSET, 0])
+                .op(OP_MEMBER_DECORATE, &[44, 1, DECORATION_OFFSET, 12])
+                .op(OP_DECORATE, &[46, DECORATION_DESCRIPTOR_SET, 0])
+                .op(OP_DECORATE, &[46, DECORATION_BINDING, 4])
+        }
+
+        /// A storage image with ID 12 at binding 0 and an acceleration structure with ID 15 at
+        /// binding 1, both in set 0.
+        fn bindings(self) -> Self {
+            self.op(OP_TYPE_FLOAT, &[10, 32])
+                .op(
+                    OP_TYPE_IMAGE,
+                    &[11, 10, DIM_2D, 0, 0, 0, 2, IMAGE_FORMAT_RGBA32F],
+                )
+                .op(OP_TYPE_POINTER, &[13, STORAGE_CLASS_UNIFORM_CONSTANT, 11])
+                .op(OP_VARIABLE, &[13, 12, STORAGE_CLASS_UNIFORM_CONSTANT])
+                .op(OP_TYPE_ACCELERATION_STRUCTURE, &[14])
+                .op(OP_TYPE_POINTER, &[16, STORAGE_CLASS_UNIFORM_CONSTANT, 14])
+                .op(OP_VARIABLE, &[16, 15, STORAGE_CLASS_UNIFORM_CONSTANT])
+                .named(OP_NAME, &[12], "image", &[])
+                .op(OP_DECORATE, &[12, DECORATION_DESCRIPTOR_SET, 0])
+                .op(OP_DECORATE, &[12, DECORATION_BINDING, 0])
+                .op(OP_DECORATE, &[15, DECORATION_DESCRIPTOR_SET, 0])
+                .op(OP_DECORATE, &[15, DECORATION_BINDING, 1])
+        }
+
+        fn reflect(self) -> Result<ShaderReflection> {
+            ShaderReflection::new(&self.words, "main")
+        }
+    }
+
+    #[test]
+    fn reflects_push_constants() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[6])
+            .push_constants()
+            .reflect()
+            .unwrap();
+
+        let block = reflection.push_constants.unwrap();
+        assert_eq!(block.name, "pc");
+        assert_eq!(block.size, 16);
+        assert_eq!(
+            block.members,
+            [("origin".to_owned(), 0), ("index".to_owned(), 12)]
+        );
+    }
+
+    #[test]
+    fn reflects_bindings() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[15, 12])
+            .bindings()
+            .reflect()
+            .unwrap();
+
+        let bindings = reflection
+            .bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.name.as_str(),
+                    binding.binding,
+                    binding.descriptor_type,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            bindings,
+            [
+                ("image", 0, vk::DescriptorType::STORAGE_IMAGE),
+                ("%15", 1, vk::DescriptorType::ACCELERATION_STRUCTURE_KHR),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_resources_outside_of_the_entry_point_interface() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[12])
+            .bindings()
+            .reflect()
+            .unwrap();
+
+        assert_eq!(reflection.bindings.len(), 1);
+        assert_eq!(reflection.bindings[0].binding, 0);
+
+        // Older versions only list inputs and outputs, so every resource counts
+        let reflection = Assembler::new(VERSION_1_0)
+            .entry_point(&[])
+            .bindings()
+            .reflect()
+            .unwrap();
+
+        assert_eq!(reflection.bindings.len(), 2);
+    }
+
+    #[test]
+    fn counts_descriptor_arrays() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[24])
+            .op(OP_TYPE_INT, &[20, 32, 0])
+            .op(OP_CONSTANT, &[20, 21, 3])
+            .op(OP_TYPE_ACCELERATION_STRUCTURE, &[22])
+            .op(OP_TYPE_ARRAY, &[23, 22, 21])
+            .op(OP_TYPE_POINTER, &[25, STORAGE_CLASS_UNIFORM_CONSTANT, 23])
+            .op(OP_VARIABLE, &[25, 24, STORAGE_CLASS_UNIFORM_CONSTANT])
+            .op(OP_DECORATE, &[24, DECORATION_DESCRIPTOR_SET, 0])
+            .op(OP_DECORATE, &[24, DECORATION_BINDING, 2])
+            .reflect()
+            .unwrap();
+
+        assert_eq!(reflection.bindings[0].descriptor_count, 3);
+    }
+
+    #[test]
+    fn accepts_arrays_sized_by_specialization_constants() {
+        // E.g. a local array whose length is a specialization constant
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[6])
+            .push_constants()
+            .op(OP_TYPE_INT, &[30, 32, 0])
+            .op(OP_SPEC_CONSTANT, &[30, 31, 4])
+            .op(OP_TYPE_ARRAY, &[32, 30, 31])
+            .reflect()
+            .unwrap();
+
+        assert_eq!(reflection.push_constants.unwrap().size, 16);
+    }
+
+    #[test]
+    fn rejects_invalid_modules() {
+        assert!(ShaderReflection::new(&[0; 5], "main").is_err());
+
+        // Truncated instruction
+        let mut assembler = Assembler::new(VERSION_1_4).entry_point(&[]);
+        assembler.words.pop();
+        assert!(assembler.reflect().is_err());
+
+        let missing_entry_point = Assembler::new(VERSION_1_4).op(OP_TYPE_VOID, &[1]);
+        assert!(missing_entry_point.reflect().is_err());
+    }
+
+    #[test]
+    fn checks_push_constants_against_the_renderer() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[6])
+            .push_constants()
+            .reflect()
+            .unwrap();
+
+        let mut pipeline = PipelineReflection::default();
+        pipeline
+            .add_stage(&reflection, vk::ShaderStageFlags::COMPUTE)
+            .unwrap();
+
+        // Laid out like the fixture's `float3` followed by a `uint`
+        type PushConstants = [u32; 4];
+
+        assert!(
+            pipeline
+                .expect_push_constants::<PushConstants>(&[0, 12], 0)
+                .is_ok()
+        );
+        assert!(
+            pipeline
+                .expect_push_constants::<PushConstants>(&[0, 12], 4)
+                .is_err()
+        );
+        assert!(
+            pipeline
+                .expect_push_constants::<PushConstants>(&[0, 16], 0)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn checks_uniform_buffers_against_the_renderer() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[46])
+            .uniform_buffer()
+            .reflect()
+            .unwrap();
+
+        let binding = &reflection.bindings[0];
+        assert_eq!(binding.descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+        assert_eq!(binding.block.as_ref().unwrap().size, 16);
+
+        let mut pipeline = PipelineReflection::default();
+        pipeline
+            .add_stage(&reflection, vk::ShaderStageFlags::COMPUTE)
+            .unwrap();
+
+        type ViewUniforms = [u32; 4];
+
+        assert!(
+            pipeline
+                .expect_uniform_buffer::<ViewUniforms>(4, &[0, 12])
+                .is_ok()
+        );
+        assert!(
+            pipeline
+                .expect_uniform_buffer::<[u32; 5]>(4, &[0, 12])
+                .is_err()
+        );
+        assert!(
+            pipeline
+                .expect_uniform_buffer::<ViewUniforms>(4, &[0, 16])
+                .is_err()
+        );
+
+        // Unused by the shaders
+        assert!(
+            pipeline
+                .expect_uniform_buffer::<ViewUniforms>(5, &[0, 16])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn adds_bindings_the_shaders_do_not_use() {
+        let reflection = Assembler::new(VERSION_1_4)
+            .entry_point(&[12])
+            .bindings()
+            .reflect()
+            .unwrap();
+
+        let mut pipeline = PipelineReflection::default();
+        pipeline
+            .add_stage(&reflection, vk::ShaderStageFlags::COMPUTE)
+            .unwrap();
+
+        pipeline
+            .expect_bindings(&[
+                (0, vk::DescriptorType::STORAGE_IMAGE),
+                (1, vk::DescriptorType::STORAGE_BUFFER),
+            ])
+            .unwrap();
+
+        let bindings = pipeline
+            .descriptor_set_layout_bindings()
+            .iter()
+            .map(|binding| (binding.binding, binding.descriptor_type))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            bindings,
+            [
+                (0, vk::DescriptorType::STORAGE_IMAGE),
+                (1, vk::DescriptorType::STORAGE_BUFFER),
+            ]
+        );
+
+        assert!(
+            pipeline
+                .expect_bindings(&[(0, vk::DescriptorType::SAMPLED_IMAGE)])
+                .is_err()
+        );
+    }
+}
@@ -92,7 +92,8 @@ impl RenderContext {
         &self,
         render_device: &RenderDevice,
         render_queue: &RenderQueue,
-        signal_semaphore: vk::Semaphore,
+        wait_semaphore: Option<vk::Semaphore>,
+        signal_semaphore: Option<vk::Semaphore>,
     ) -> Result<()> {
         unsafe {
             render_device
@@ -101,7 +102,7 @@ impl RenderContext {
 
             render_queue.submit(
                 self.command_buffer,
-                self.semaphore,
+                wait_semaphore,
                 signal_semaphore,
                 self.fence,
             )?;
@@ -109,6 +110,16 @@ impl RenderContext {
 
         Ok(())
     }
+
+    pub fn wait_frame(&self, render_device: &RenderDevice) -> Result<()> {
+        unsafe {
+            render_device
+                .device
+                .wait_for_fences(&[self.fence], true, u64::MAX)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for RenderContext {
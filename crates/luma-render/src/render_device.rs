@@ -1,6 +1,8 @@
 use std::{
     collections::HashSet,
     ffi::{CStr, c_char},
+    fs,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
@@ -18,24 +20,35 @@ pub struct RenderDevice(Arc<RenderDeviceInner>);
 pub struct RenderDeviceInner {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
-    pub surface_instance: khr::surface::Instance,
+    /// Only loaded when presenting to windows.
+    pub surface_instance: Option<khr::surface::Instance>,
     pub physical_device: vk::PhysicalDevice,
     pub device_memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub device: ash::Device,
-    pub swapchain_device: khr::swapchain::Device,
+    /// Only loaded when presenting to windows.
+    pub swapchain_device: Option<khr::swapchain::Device>,
     pub ray_tracing_pipeline_device: khr::ray_tracing_pipeline::Device,
     pub acceleration_structure_device: khr::acceleration_structure::Device,
     pub deferred_host_operations_device: khr::deferred_host_operations::Device,
     pub allocator: Arc<Mutex<Allocator>>,
+    /// Persisted across runs by `save_pipeline_cache`.
+    pub pipeline_cache: vk::PipelineCache,
+    pipeline_cache_path: PathBuf,
 }
 
 impl RenderDevice {
-    pub fn new(display_handle: RawDisplayHandle) -> Result<(Self, RenderQueue)> {
+    /// Creates a device for presenting to windows on `display_handle`, or a headless device
+    /// without surface and swapchain support when no display handle is given.
+    pub fn new(display_handle: Option<RawDisplayHandle>) -> Result<(Self, RenderQueue)> {
         unsafe {
             let entry = ash::Entry::load()?;
             let application_info = vk::ApplicationInfo::default().api_version(Self::api_version());
             let instance_layers = Self::instance_layers();
-            let window_extensions = ash_window::enumerate_required_extensions(display_handle)?;
+
+            let window_extensions = match display_handle {
+                Some(display_handle) => ash_window::enumerate_required_extensions(display_handle)?,
+                None => &[],
+            };
 
             let instance_create_info = vk::InstanceCreateInfo::default()
                 .application_info(&application_info)
@@ -43,7 +56,11 @@ impl RenderDevice {
                 .enabled_extension_names(window_extensions);
 
             let instance = entry.create_instance(&instance_create_info, None)?;
-            let surface_instance = khr::surface::Instance::new(&entry, &instance);
+
+            // Surface and swapchain functions are only loaded if their extensions are enabled
+            let surface_instance = display_handle
+                .is_some()
+                .then(|| khr::surface::Instance::new(&entry, &instance));
 
             let (physical_device, queue_family_index) = instance
                 .enumerate_physical_devices()?
@@ -68,7 +85,7 @@ impl RenderDevice {
                 .queue_family_index(queue_family_index)
                 .queue_priorities(&[1.0]);
 
-            let device_extensions = Self::device_extensions();
+            let device_extensions = Self::device_extensions(display_handle.is_some());
 
             let device_extensions_properties =
                 instance.enumerate_device_extension_properties(physical_device)?;
@@ -104,6 +121,9 @@ impl RenderDevice {
                 vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
                     .acceleration_structure(true);
 
+            let mut ray_query_features =
+                vk::PhysicalDeviceRayQueryFeaturesKHR::default().ray_query(true);
+
             let mut buffer_device_address_features =
                 vk::PhysicalDeviceBufferDeviceAddressFeatures::default()
                     .buffer_device_address(true);
@@ -123,11 +143,14 @@ impl RenderDevice {
                 .push_next(&mut scalar_block_layout_features)
                 .push_next(&mut ray_tracing_pipeline_features)
                 .push_next(&mut acceleration_structure_features)
+                .push_next(&mut ray_query_features)
                 .push_next(&mut buffer_device_address_features)
                 .push_next(&mut synchronization2_features);
 
             let device = instance.create_device(physical_device, &device_create_info, None)?;
-            let swapchain_device = khr::swapchain::Device::new(&instance, &device);
+            let swapchain_device = display_handle
+                .is_some()
+                .then(|| khr::swapchain::Device::new(&instance, &device));
             let ray_tracing_pipeline_device =
                 khr::ray_tracing_pipeline::Device::new(&instance, &device);
             let acceleration_structure_device =
@@ -146,6 +169,9 @@ impl RenderDevice {
 
             let allocator = Arc::new(Mutex::new(Allocator::new(&allocater_create_desc)?));
 
+            let pipeline_cache_path = Self::pipeline_cache_path(&instance, physical_device);
+            let pipeline_cache = Self::create_pipeline_cache(&device, &pipeline_cache_path)?;
+
             let render_device = Self(Arc::new(RenderDeviceInner {
                 entry,
                 instance,
@@ -158,6 +184,8 @@ impl RenderDevice {
                 acceleration_structure_device,
                 deferred_host_operations_device,
                 allocator,
+                pipeline_cache,
+                pipeline_cache_path,
             }));
 
             let render_queue = RenderQueue::new(render_device.clone(), queue_family_index, 0);
@@ -183,6 +211,25 @@ impl RenderDevice {
         }
     }
 
+    /// Writes the pipeline cache to disk, so that the next run can skip compiling the pipelines
+    /// created so far.
+    pub fn save_pipeline_cache(&self) {
+        let result = unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache) }
+            .map_err(anyhow::Error::from)
+            .and_then(|data| {
+                if let Some(parent) = self.pipeline_cache_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                fs::write(&self.pipeline_cache_path, data)?;
+                Ok(())
+            });
+
+        if let Err(err) = result {
+            warn!("Failed to save pipeline cache: {err}");
+        }
+    }
+
     pub fn get_physical_device_ray_tracing_pipeline_properties(
         &self,
     ) -> vk::PhysicalDeviceRayTracingPipelinePropertiesKHR<'_> {
@@ -195,6 +242,49 @@ impl RenderDevice {
         }
     }
 
+    /// Path of the pipeline cache, which is only valid for the same device and driver.
+    fn pipeline_cache_path(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> PathBuf {
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut id_properties);
+
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+
+        let hex = |uuid: [u8; vk::UUID_SIZE]| {
+            uuid.iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        };
+
+        let file_name = format!(
+            "{}-{}.bin",
+            hex(id_properties.device_uuid),
+            hex(id_properties.driver_uuid)
+        );
+
+        Path::new(crate::CACHE_DIR)
+            .join("pipelines")
+            .join(file_name)
+    }
+
+    /// Creates the pipeline cache from the data saved by a previous run if there is any.
+    fn create_pipeline_cache(device: &ash::Device, path: &Path) -> Result<vk::PipelineCache> {
+        let initial_data = fs::read(path).unwrap_or_default();
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(&initial_data);
+
+        match unsafe { device.create_pipeline_cache(&create_info, None) } {
+            Ok(pipeline_cache) => Ok(pipeline_cache),
+            Err(err) => {
+                // Drivers should ignore incompatible data, but start over in case one does not
+                warn!("Failed to load pipeline cache, starting with an empty one: {err}");
+                let create_info = vk::PipelineCacheCreateInfo::default();
+                Ok(unsafe { device.create_pipeline_cache(&create_info, None)? })
+            }
+        }
+    }
+
     fn api_version() -> u32 {
         vk::API_VERSION_1_3
     }
@@ -210,13 +300,19 @@ impl RenderDevice {
         instance_layers
     }
 
-    fn device_extensions() -> Vec<&'static CStr> {
-        vec![
-            khr::swapchain::NAME,
+    fn device_extensions(presentation: bool) -> Vec<&'static CStr> {
+        let mut device_extensions = vec![
             khr::dynamic_rendering::NAME,
             khr::ray_tracing_pipeline::NAME,
             khr::acceleration_structure::NAME,
             khr::deferred_host_operations::NAME,
-        ]
+            khr::ray_query::NAME,
+        ];
+
+        if presentation {
+            device_extensions.push(khr::swapchain::NAME);
+        }
+
+        device_extensions
     }
 }
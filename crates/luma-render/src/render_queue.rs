@@ -30,16 +30,20 @@ impl RenderQueue {
     pub unsafe fn submit(
         &self,
         command_buffer: vk::CommandBuffer,
-        wait_semaphore: vk::Semaphore,
-        signal_semaphore: vk::Semaphore,
+        wait_semaphore: Option<vk::Semaphore>,
+        signal_semaphore: Option<vk::Semaphore>,
         fence: vk::Fence,
     ) -> Result<(), vk::Result> {
         unsafe {
+            let wait_semaphores = wait_semaphore.as_slice();
+            let wait_dst_stage_mask =
+                &[vk::PipelineStageFlags::ALL_COMMANDS][..wait_semaphores.len()];
+
             let submit_info = vk::SubmitInfo::default()
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::ALL_COMMANDS])
+                .wait_dst_stage_mask(wait_dst_stage_mask)
                 .command_buffers(std::slice::from_ref(&command_buffer))
-                .wait_semaphores(std::slice::from_ref(&wait_semaphore))
-                .signal_semaphores(std::slice::from_ref(&signal_semaphore));
+                .wait_semaphores(wait_semaphores)
+                .signal_semaphores(signal_semaphore.as_slice());
 
             self.render_device
                 .device
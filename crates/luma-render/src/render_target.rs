@@ -0,0 +1,125 @@
+use anyhow::Result;
+use ash::vk;
+use bevy::prelude::*;
+use gpu_allocator::MemoryLocation;
+
+use super::{
+    buffer::Buffer, render_device::RenderDevice, resource_state_tracker::ResourceStateTracker,
+    storage_image::StorageImage, swapchain::Swapchain,
+};
+
+/// Size of the offscreen image rendered into when running without a window.
+#[derive(Resource, Clone)]
+pub struct Headless {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub enum RenderTarget {
+    Window(Swapchain),
+    Offscreen(Box<OffscreenTarget>),
+}
+
+impl RenderTarget {
+    pub fn extent(&self) -> vk::Extent2D {
+        match self {
+            Self::Window(swapchain) => swapchain.surface_extent,
+            Self::Offscreen(offscreen_target) => offscreen_target.image.extent,
+        }
+    }
+
+    pub fn format(&self) -> vk::Format {
+        match self {
+            Self::Window(swapchain) => swapchain.surface_format,
+            Self::Offscreen(offscreen_target) => offscreen_target.image.format,
+        }
+    }
+
+    pub fn image(&self) -> vk::Image {
+        match self {
+            Self::Window(swapchain) => swapchain.current_image().image,
+            Self::Offscreen(offscreen_target) => offscreen_target.image.image,
+        }
+    }
+
+    pub fn image_view(&self) -> vk::ImageView {
+        match self {
+            Self::Window(swapchain) => swapchain.current_image().image_view,
+            Self::Offscreen(offscreen_target) => offscreen_target.image.image_view,
+        }
+    }
+}
+
+pub struct OffscreenTarget {
+    pub render_device: RenderDevice,
+    pub image: StorageImage,
+    pub readback_buffer: Buffer,
+}
+
+impl OffscreenTarget {
+    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+    pub fn new(render_device: RenderDevice, extent: vk::Extent2D) -> Result<Self> {
+        let image = render_device.create_image(
+            extent,
+            Self::FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            Some("Offscreen Target Image"),
+        )?;
+
+        let readback_buffer = render_device.create_buffer(
+            extent.width as u64 * extent.height as u64 * 4,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            Some("Offscreen Target Readback Buffer"),
+        )?;
+
+        Ok(Self {
+            render_device,
+            image,
+            readback_buffer,
+        })
+    }
+
+    /// Records a copy of the target image into the readback buffer.
+    pub fn copy_to_readback_buffer(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        tracker: &mut ResourceStateTracker,
+    ) {
+        self.render_device.cmd_copy_image_to_readback_buffer(
+            command_buffer,
+            tracker,
+            self.image.image,
+            self.image.extent,
+            &self.readback_buffer,
+        );
+    }
+
+    /// Tightly packed sRGB encoded RGBA8 pixels of the last completed frame.
+    pub fn read_pixels(&self) -> Result<&[u8]> {
+        self.readback_buffer.slice()
+    }
+}
+
+impl Drop for OffscreenTarget {
+    fn drop(&mut self) {
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.readback_buffer));
+
+        self.render_device
+            .destroy_storage_image(std::mem::take(&mut self.image));
+    }
+}
@@ -123,4 +123,12 @@ impl ImageState {
             stages: vk::PipelineStageFlags2::TRANSFER,
         }
     }
+
+    pub fn shader_read_only() -> Self {
+        Self {
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+            stages: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        }
+    }
 }
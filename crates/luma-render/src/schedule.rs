@@ -8,7 +8,9 @@ pub enum RenderSystems {
     Prepare,
     Queue,
     QueueRayTracing,
+    QueuePostProcess,
     QueueUi,
+    Readback,
     Submit,
 }
 
@@ -32,13 +34,18 @@ impl Render {
             (
                 RenderSystems::Prepare,
                 RenderSystems::Queue,
+                RenderSystems::Readback,
                 RenderSystems::Submit,
             )
                 .chain(),
         );
 
         schedule.configure_sets(
-            (RenderSystems::QueueRayTracing, RenderSystems::QueueUi)
+            (
+                RenderSystems::QueueRayTracing,
+                RenderSystems::QueuePostProcess,
+                RenderSystems::QueueUi,
+            )
                 .chain()
                 .in_set(RenderSystems::Queue),
         );
@@ -0,0 +1,400 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use ash::vk;
+use bevy::{
+    prelude::*,
+    tasks::{IoTaskPool, Task, block_on},
+    time::TimeUpdateStrategy,
+    window::ExitSystems,
+};
+use gpu_allocator::MemoryLocation;
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+
+use super::{
+    buffer::Buffer,
+    readback::bytes_per_pixel,
+    render_context::RenderContext,
+    render_device::RenderDevice,
+    render_target::RenderTarget,
+    resource_state_tracker::ResourceStateTracker,
+    schedule::{Render, RenderSystems},
+    view::Views,
+};
+
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<Screenshot>()
+            .init_resource::<PendingScreenshots>()
+            .init_resource::<SaveTasks>()
+            .add_systems(PreUpdate, record_image_sequence)
+            .add_systems(
+                Render,
+                (
+                    save_screenshots.in_set(RenderSystems::Prepare),
+                    copy_screenshots.in_set(RenderSystems::Readback),
+                ),
+            )
+            .add_systems(Last, flush_screenshots.after(ExitSystems));
+    }
+}
+
+/// Requests the current frame to be written to `path`. The file format is picked from the
+/// extension, which must be either `png` or `exr`.
+#[derive(Message, Clone)]
+pub struct Screenshot {
+    pub path: PathBuf,
+    pub source: ScreenshotSource,
+    /// Camera whose image is captured for the ray traced and tonemapped sources. Defaults to the
+    /// camera with the lowest priority that renders into the primary target.
+    pub camera: Option<Entity>,
+}
+
+impl Screenshot {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            source: default(),
+            camera: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ScreenshotSource {
+    /// The linear HDR radiance of the ray traced image, before exposure and tonemapping. PNGs of
+    /// it are sRGB encoded with radiance above 1 clipped, so EXR keeps the full range.
+    RayTracing,
+    /// The tonemapped and sRGB encoded ray traced image, without any UI.
+    #[default]
+    Tonemapped,
+    /// The final image as presented, including UI.
+    RenderTarget,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ImageFileFormat {
+    #[default]
+    Png,
+    /// 32-bit float OpenEXR
+    Exr,
+}
+
+impl ImageFileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Exr => "exr",
+        }
+    }
+}
+
+/// Writes one numbered image per frame into `directory` while this resource exists.
+///
+/// Time advances by exactly `1 / frame_rate` seconds per frame during recording, so animations and
+/// camera paths are captured at a fixed timestep regardless of how long each frame takes.
+#[derive(Resource, Clone)]
+pub struct ImageSequence {
+    pub directory: PathBuf,
+    pub format: ImageFileFormat,
+    pub source: ScreenshotSource,
+    pub camera: Option<Entity>,
+    pub frame_rate: f64,
+    /// Stops recording after this many frames when set.
+    pub frame_count: Option<u32>,
+    /// Number of the next frame to be written.
+    pub frame: u32,
+}
+
+impl ImageSequence {
+    pub fn new(directory: impl Into<PathBuf>, frame_rate: f64) -> Self {
+        Self {
+            directory: directory.into(),
+            format: default(),
+            source: default(),
+            camera: None,
+            frame_rate,
+            frame_count: None,
+            frame: 0,
+        }
+    }
+
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        self.directory
+            .join(format!("frame-{frame:05}.{}", self.format.extension()))
+    }
+}
+
+fn record_image_sequence(
+    mut commands: Commands,
+    image_sequence: Option<ResMut<ImageSequence>>,
+    mut time_update_strategy: ResMut<TimeUpdateStrategy>,
+    mut screenshots: MessageWriter<Screenshot>,
+    mut previous_strategy: Local<Option<TimeUpdateStrategy>>,
+) {
+    let Some(mut image_sequence) = image_sequence else {
+        // Restores the strategy from before recording, which the app may have set itself
+        if let Some(previous_strategy) = previous_strategy.take() {
+            *time_update_strategy = previous_strategy;
+        }
+
+        return;
+    };
+
+    if image_sequence
+        .frame_count
+        .is_some_and(|frame_count| image_sequence.frame >= frame_count)
+    {
+        commands.remove_resource::<ImageSequence>();
+        return;
+    }
+
+    if previous_strategy.is_none() {
+        *previous_strategy = Some(time_update_strategy.clone());
+    }
+
+    *time_update_strategy = TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / image_sequence.frame_rate,
+    ));
+
+    screenshots.write(Screenshot {
+        path: image_sequence.frame_path(image_sequence.frame),
+        source: image_sequence.source,
+        camera: image_sequence.camera,
+    });
+
+    image_sequence.frame += 1;
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+struct PendingScreenshots(Vec<PendingScreenshot>);
+
+/// Screenshots being encoded and written in the background, which are waited for on exit.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct SaveTasks(Vec<Task<()>>);
+
+/// A screenshot that has been copied into a readback buffer, which is ready to be read once the
+/// frame it was recorded in has finished.
+struct PendingScreenshot {
+    render_device: RenderDevice,
+    path: PathBuf,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    buffer: Buffer,
+}
+
+impl PendingScreenshot {
+    fn to_image(&self) -> Result<DynamicImage> {
+        let vk::Extent2D { width, height } = self.extent;
+        let bytes = self.buffer.slice()?;
+
+        let image = match self.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => {
+                RgbaImage::from_raw(width, height, bytes.to_vec()).map(DynamicImage::ImageRgba8)
+            }
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+                let mut bytes = bytes.to_vec();
+                bytes.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+                RgbaImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
+            }
+            vk::Format::R32G32B32A32_SFLOAT => {
+                let pixels = bytemuck::cast_slice(bytes).to_vec();
+                Rgba32FImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba32F)
+            }
+            format => bail!("Unsupported screenshot format: {format:?}"),
+        };
+
+        image.ok_or_else(|| anyhow!("Screenshot buffer does not match its extent"))
+    }
+}
+
+impl Drop for PendingScreenshot {
+    fn drop(&mut self) {
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.buffer));
+    }
+}
+
+fn save_screenshots(
+    mut pending_screenshots: ResMut<PendingScreenshots>,
+    mut save_tasks: ResMut<SaveTasks>,
+) {
+    save_tasks.retain(|task| !task.is_finished());
+    spawn_save_tasks(&mut pending_screenshots, &mut save_tasks);
+}
+
+fn spawn_save_tasks(pending_screenshots: &mut PendingScreenshots, save_tasks: &mut SaveTasks) {
+    for pending_screenshot in pending_screenshots.drain(..) {
+        let path = pending_screenshot.path.clone();
+
+        let image = match pending_screenshot.to_image() {
+            Ok(image) => image,
+            Err(err) => {
+                error!("Failed to read screenshot {}: {err}", path.display());
+                continue;
+            }
+        };
+
+        save_tasks.push(IoTaskPool::get().spawn(async move {
+            match save_image(&image, &path) {
+                Ok(()) => info!("Saved screenshot to {}", path.display()),
+                Err(err) => error!("Failed to save screenshot {}: {err}", path.display()),
+            }
+        }));
+    }
+}
+
+/// Saves the screenshots of the last frame when the app exits, such as the final frame of an
+/// image sequence, which would otherwise only be saved in the next frame.
+fn flush_screenshots(
+    mut app_exit_events: MessageReader<AppExit>,
+    render_device: Res<RenderDevice>,
+    mut pending_screenshots: ResMut<PendingScreenshots>,
+    mut save_tasks: ResMut<SaveTasks>,
+) {
+    if app_exit_events.is_empty() {
+        return;
+    }
+
+    app_exit_events.clear();
+
+    // The readback buffers are only complete once the last frame has finished
+    render_device.wait_idle();
+    spawn_save_tasks(&mut pending_screenshots, &mut save_tasks);
+
+    for task in save_tasks.drain(..) {
+        block_on(task);
+    }
+}
+
+fn save_image(image: &DynamicImage, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match path.extension().and_then(OsStr::to_str) {
+        // Linear images are encoded like the tonemapped ones, so that they display correctly
+        Some("png") => match image {
+            DynamicImage::ImageRgba32F(linear) => {
+                let (width, height) = linear.dimensions();
+
+                let bytes = linear
+                    .pixels()
+                    .flat_map(|pixel| {
+                        let [r, g, b, a] = pixel.0;
+
+                        [
+                            linear_to_srgb(r),
+                            linear_to_srgb(g),
+                            linear_to_srgb(b),
+                            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                        ]
+                    })
+                    .collect();
+
+                RgbaImage::from_raw(width, height, bytes)
+                    .ok_or_else(|| anyhow!("Screenshot has an unexpected size"))?
+                    .save(path)?
+            }
+            _ => DynamicImage::ImageRgba8(image.to_rgba8()).save(path)?,
+        },
+        Some("exr") => DynamicImage::ImageRgba32F(image.to_rgba32f()).save(path)?,
+        _ => bail!("Unsupported screenshot file extension"),
+    }
+
+    Ok(())
+}
+
+/// Encodes a linear color channel with the sRGB transfer function, clipped to the 8-bit range.
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+
+    let encoded = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+
+    (encoded * 255.0).round() as u8
+}
+
+fn copy_screenshots(
+    render_device: Res<RenderDevice>,
+    render_context: Res<RenderContext>,
+    render_target: Res<RenderTarget>,
+    views: Option<Res<Views>>,
+    mut tracker: ResMut<ResourceStateTracker>,
+    mut pending_screenshots: ResMut<PendingScreenshots>,
+    mut screenshots: MessageReader<Screenshot>,
+) -> Result<(), BevyError> {
+    for screenshot in screenshots.read() {
+        let view = views.as_ref().and_then(|views| match screenshot.camera {
+            Some(camera) => views.get(&camera),
+            None => views.main().map(|(_, view)| view),
+        });
+
+        let (image, extent, format) = match screenshot.source {
+            ScreenshotSource::RayTracing => {
+                let Some(view) = view else {
+                    warn!("No ray traced image to take screenshot of");
+                    continue;
+                };
+
+                let radiance_image = &view.radiance_image;
+                (
+                    radiance_image.image,
+                    radiance_image.extent,
+                    radiance_image.format,
+                )
+            }
+            ScreenshotSource::Tonemapped => {
+                let Some(view) = view else {
+                    warn!("No tonemapped image to take screenshot of");
+                    continue;
+                };
+
+                let output_image = &view.output_image;
+                (output_image.image, output_image.extent, output_image.format)
+            }
+            ScreenshotSource::RenderTarget => (
+                render_target.image(),
+                render_target.extent(),
+                render_target.format(),
+            ),
+        };
+
+        let bytes_per_pixel = bytes_per_pixel(format)
+            .ok_or_else(|| anyhow!("Unsupported screenshot format: {format:?}"))?;
+
+        let buffer = render_device.create_buffer(
+            extent.width as u64 * extent.height as u64 * bytes_per_pixel,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+            Some("Screenshot Readback Buffer"),
+        )?;
+
+        render_device.cmd_copy_image_to_readback_buffer(
+            render_context.command_buffer,
+            &mut tracker,
+            image,
+            extent,
+            &buffer,
+        );
+
+        pending_screenshots.push(PendingScreenshot {
+            render_device: render_device.clone(),
+            path: screenshot.path.clone(),
+            extent,
+            format,
+            buffer,
+        });
+    }
+
+    Ok(())
+}
@@ -1,18 +1,126 @@
-use std::{ffi::CString, fs::File, io::Read, path::Path, process::Command};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::CString,
+    fs::{self, File},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Read,
+    path::Path,
+    sync::mpsc,
+    thread,
+};
 
 use anyhow::{Error, Ok, Result, anyhow};
 use bevy::{
-    asset::{AssetLoader, LoadContext, io::Reader},
+    asset::{AssetLoadFailedEvent, AssetLoader, AssetPath, LoadContext, io::Reader},
     prelude::*,
     tasks::ConditionalSendFuture,
 };
+use serde::{Deserialize, Serialize};
+
+use crate::reflection::ShaderReflection;
 
 pub struct ShaderPlugin;
 
 impl Plugin for ShaderPlugin {
     fn build(&self, app: &mut App) {
         app.init_asset::<Shader>()
-            .init_asset_loader::<ShaderLoader>();
+            .init_asset_loader::<ShaderLoader>()
+            .init_resource::<ShaderDiagnostics>()
+            .add_systems(Update, track_shader_diagnostics);
+    }
+}
+
+/// Errors of shaders that failed to load and pipelines that failed to build, kept until they are
+/// fixed. Pipelines that fail to rebuild keep using their last good version meanwhile.
+#[derive(Resource, Default)]
+pub struct ShaderDiagnostics {
+    /// Diagnostics by shader path or pipeline name.
+    errors: BTreeMap<String, Vec<ShaderDiagnostic>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderDiagnostic {
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl ShaderDiagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[ShaderDiagnostic])> {
+        self.errors
+            .iter()
+            .map(|(origin, diagnostics)| (origin.as_str(), diagnostics.as_slice()))
+    }
+
+    /// Records the error output of `origin`, replacing its previous errors.
+    pub fn insert(&mut self, origin: impl Into<String>, output: &str) {
+        self.errors
+            .insert(origin.into(), ShaderDiagnostic::parse(output));
+    }
+
+    pub fn remove(&mut self, origin: &str) {
+        self.errors.remove(origin);
+    }
+}
+
+impl ShaderDiagnostic {
+    /// Parses compiler output with lines like `file(line): error 123: message`. Output without
+    /// such lines, like pipeline layout mismatches, is kept as a single message.
+    fn parse(output: &str) -> Vec<Self> {
+        let diagnostics = output
+            .lines()
+            .filter_map(|line| {
+                let (location, message) = line.split_once("): ")?;
+                let (file, line) = location.rsplit_once('(')?;
+
+                if !message.starts_with("error") && !message.starts_with("warning") {
+                    return None;
+                }
+
+                // Loader errors prefix the first line with the asset path and loader name
+                let file = file.rsplit(": ").next().unwrap_or(file);
+
+                Some(Self {
+                    file: Some(file.to_owned()),
+                    line: Some(line.parse().ok()?),
+                    message: message.to_owned(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if diagnostics.is_empty() {
+            vec![Self {
+                file: None,
+                line: None,
+                message: output.trim().to_owned(),
+            }]
+        } else {
+            diagnostics
+        }
+    }
+}
+
+fn track_shader_diagnostics(
+    asset_server: Res<AssetServer>,
+    mut diagnostics: ResMut<ShaderDiagnostics>,
+    mut asset_events: MessageReader<AssetEvent<Shader>>,
+    mut load_failed_events: MessageReader<AssetLoadFailedEvent<Shader>>,
+) {
+    for asset_event in asset_events.read() {
+        if let AssetEvent::Added { id } | AssetEvent::Modified { id } = asset_event
+            && let Some(path) = asset_server.get_path(*id)
+        {
+            diagnostics.remove(&path.to_string());
+        }
+    }
+
+    for load_failed_event in load_failed_events.read() {
+        let path = load_failed_event.path.to_string();
+        diagnostics.insert(path, &load_failed_event.error.to_string());
     }
 }
 
@@ -20,52 +128,478 @@ impl Plugin for ShaderPlugin {
 pub struct Shader {
     pub code: Vec<u32>,
     pub entry_point: CString,
+    pub reflection: ShaderReflection,
 }
 
-#[derive(TypePath, Default)]
-pub struct ShaderLoader;
+/// Compiles a permutation of a shader, set with `AssetServer::load_with_settings`. Assets are
+/// identified by path alone, so loading a path that is already loaded returns the existing shader
+/// with its original settings, and a permutation that is needed alongside another one needs a
+/// file of its own.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct ShaderSettings {
+    /// Preprocessor macros as names and values, like `#define NAME VALUE` before the source.
+    pub defines: Vec<(String, String)>,
+    pub entry_point: String,
+}
+
+impl Default for ShaderSettings {
+    fn default() -> Self {
+        Self {
+            defines: Vec::new(),
+            entry_point: "main".to_owned(),
+        }
+    }
+}
+
+#[derive(TypePath)]
+pub struct ShaderLoader {
+    compiler: ShaderCompiler,
+}
+
+impl Default for ShaderLoader {
+    fn default() -> Self {
+        Self {
+            compiler: ShaderCompiler::spawn(),
+        }
+    }
+}
 
 impl AssetLoader for ShaderLoader {
     type Asset = Shader;
-    type Settings = ();
+    type Settings = ShaderSettings;
     type Error = Error;
 
     fn load(
         &self,
-        _reader: &mut dyn Reader,
-        _settings: &Self::Settings,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
         load_context: &mut LoadContext,
     ) -> impl ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
-            let input_path = Path::new("assets").join(load_context.path().path());
-            let output_path = input_path.with_extension("spv");
-            let mut command = Command::new("slangc");
+            let mut source = Vec::new();
+            reader.read_to_end(&mut source).await?;
 
-            command
-                .arg(&input_path)
-                .arg("-o")
-                .arg(&output_path)
-                .arg("-fvk-use-scalar-layout");
+            // The shader and the modules it imports are read through the asset system, so that
+            // editing any of them reloads this shader
+            let main_path = load_context.asset_path().clone_owned();
+            let mut sources = vec![(main_path.clone(), source)];
+            let mut visited = HashSet::from([main_path]);
+            let mut index = 0;
 
-            let output = command.output()?;
+            while let Some((path, source)) = sources.get(index) {
+                let text = std::str::from_utf8(source)
+                    .map_err(|e| anyhow!("Shader {path} is not valid UTF-8: {e}"))?;
 
-            if !output.stderr.is_empty() {
-                let stderr = std::str::from_utf8(&output.stderr)
-                    .map_err(|e| anyhow!("Failed to parse shader compiler output: {e}"))?;
+                let modules = imported_modules(text)
+                    .map_err(|e| anyhow!("{path}: {e}"))?
+                    .iter()
+                    .map(|(_, module)| path.resolve_embed(module))
+                    .collect::<Result<Vec<_>, _>>()?;
 
-                return Err(anyhow!("{stderr}"));
-            }
+                for module_path in modules {
+                    if !visited.insert(module_path.clone()) {
+                        continue;
+                    }
+
+                    // The compiler reports modules that are missing and actually needed, e.g. not
+                    // just imported in an inactive preprocessor branch
+                    let module_source = load_context
+                        .read_asset_bytes(module_path.clone())
+                        .await
+                        .inspect_err(|e| debug!("Skipping import {module_path}: {e}"))
+                        .ok();
 
-            let file = File::open(&output_path)
-                .map_err(|e| anyhow!("Failed to open compiled shader: {e}"))?;
+                    if let Some(module_source) = module_source {
+                        sources.push((module_path, module_source));
+                    }
+                }
 
-            let code =
-                read_spv(file).map_err(|e| anyhow!("Failed to read compiled shader: {e}"))?;
+                index += 1;
+            }
 
-            let entry_point = c"main".to_owned();
-            Ok(Shader { code, entry_point })
+            compile_permutation(&self.compiler, &sources, settings).await
         })
     }
+
+    fn extensions(&self) -> &[&str] {
+        &["slang"]
+    }
+}
+
+async fn compile_permutation(
+    compiler: &ShaderCompiler,
+    sources: &[(AssetPath<'static>, Vec<u8>)],
+    settings: &ShaderSettings,
+) -> Result<Shader> {
+    let cache_path = Path::new(crate::CACHE_DIR)
+        .join("shaders")
+        .join(format!("{:016x}.spv", cache_key(sources, settings)));
+
+    let cached = File::open(&cache_path)
+        .ok()
+        .and_then(|file| read_spv(file).ok());
+
+    let code = match cached {
+        Some(code) => code,
+        None => {
+            let code = compiler
+                .compile(source_modules(sources)?, settings.clone())
+                .await?;
+
+            if let Err(e) = write_cache(&cache_path, bytemuck::cast_slice(&code)) {
+                warn!("Failed to cache compiled shader {}: {e}", sources[0].0);
+            }
+
+            code
+        }
+    };
+
+    let entry_point = CString::new(settings.entry_point.clone())?;
+
+    let reflection = ShaderReflection::new(&code, &settings.entry_point)
+        .map_err(|e| anyhow!("Failed to reflect compiled shader: {e}"))?;
+
+    Ok(Shader {
+        code,
+        entry_point,
+        reflection,
+    })
+}
+
+/// Compiles shaders from memory on a thread that owns the Slang global session, which is
+/// expensive to create and must not be shared between threads. Every compilation gets a session
+/// of its own, since the modules and macros of a session can't change once loaded.
+struct ShaderCompiler {
+    requests: mpsc::Sender<CompileRequest>,
+}
+
+struct CompileRequest {
+    modules: Vec<SourceModule>,
+    settings: ShaderSettings,
+    response: async_channel::Sender<Result<Vec<u32>>>,
+}
+
+/// Source code of a Slang module, registered under the name that it is imported with.
+#[derive(PartialEq, Debug)]
+struct SourceModule {
+    name: String,
+    path: String,
+    source: String,
+}
+
+impl ShaderCompiler {
+    fn spawn() -> Self {
+        let (requests, receiver) = mpsc::channel::<CompileRequest>();
+
+        let result = thread::Builder::new()
+            .name("Shader Compiler".to_owned())
+            .spawn(move || {
+                let global_session = slang::GlobalSession::new();
+
+                if global_session.is_none() {
+                    error!("Failed to create shader compiler session");
+                }
+
+                // Runs until the loader is dropped
+                for request in receiver {
+                    let result = match &global_session {
+                        Some(global_session) => {
+                            compile(global_session, &request.modules, &request.settings)
+                        }
+                        None => Err(anyhow!("Shader compiler is not available")),
+                    };
+
+                    // The load may have been cancelled in the meantime
+                    let _ = request.response.send_blocking(result);
+                }
+            });
+
+        if let Err(e) = result {
+            error!("Failed to start shader compiler thread: {e}");
+        }
+
+        Self { requests }
+    }
+
+    async fn compile(
+        &self,
+        modules: Vec<SourceModule>,
+        settings: ShaderSettings,
+    ) -> Result<Vec<u32>> {
+        let (response, receiver) = async_channel::bounded(1);
+
+        self.requests
+            .send(CompileRequest {
+                modules,
+                settings,
+                response,
+            })
+            .map_err(|_| anyhow!("Shader compiler thread has stopped"))?;
+
+        receiver
+            .recv()
+            .await
+            .map_err(|_| anyhow!("Shader compiler thread has stopped"))?
+    }
+}
+
+/// Compiles the last of `modules` after loading the modules that it imports from their sources.
+fn compile(
+    global_session: &slang::GlobalSession,
+    modules: &[SourceModule],
+    settings: &ShaderSettings,
+) -> Result<Vec<u32>> {
+    let mut options = slang::CompilerOptions::default()
+        .glsl_force_scalar_layout(true)
+        .vulkan_use_entry_point_name(true)
+        .emit_spirv_directly(true);
+
+    for (name, value) in &settings.defines {
+        options = options.macro_define(name, value);
+    }
+
+    let targets = [slang::TargetDesc::default()
+        .format(slang::CompileTarget::Spirv)
+        .profile(global_session.find_profile("spirv_1_5"))];
+
+    let session_desc = slang::SessionDesc::default()
+        .targets(&targets)
+        .options(&options);
+
+    let session = global_session
+        .create_session(&session_desc)
+        .ok_or_else(|| anyhow!("Failed to create shader compiler session"))?;
+
+    let mut module = None;
+
+    // Errors carry the compiler diagnostics, with the asset paths of the modules
+    for source_module in modules {
+        module = Some(
+            session
+                .load_module_from_source_string(
+                    &source_module.name,
+                    &source_module.path,
+                    &source_module.source,
+                )
+                .map_err(|e| anyhow!("{e:?}"))?,
+        );
+    }
+
+    let module = module.ok_or_else(|| anyhow!("No shader to compile"))?;
+    let main_path = &modules[modules.len() - 1].path;
+
+    let entry_point = module
+        .find_entry_point_by_name(&settings.entry_point)
+        .ok_or_else(|| anyhow!("{main_path} has no entry point {}", settings.entry_point))?;
+
+    let program = session
+        .create_composite_component_type(&[
+            module.downcast().clone(),
+            entry_point.downcast().clone(),
+        ])
+        .map_err(|e| anyhow!("{e:?}"))?;
+
+    let code = program
+        .link()
+        .and_then(|program| program.entry_point_code(0, 0))
+        .map_err(|e| anyhow!("{e:?}"))?;
+
+    if !code.as_slice().len().is_multiple_of(4) {
+        anyhow::bail!("SPIR-V bytecode length must be divisible by 4");
+    }
+
+    Ok(bytemuck::pod_collect_to_vec(code.as_slice()))
+}
+
+/// Orders the gathered sources so that every module comes after the modules it imports, since
+/// modules compiled from memory can only import modules that are already loaded. The shader
+/// itself, the first of `sources`, comes last. A module is registered under the name that it is
+/// first imported with, so importing it under another name, like `import "common";` in one file
+/// and `import common;` in another, fails to compile.
+fn source_modules(sources: &[(AssetPath<'static>, Vec<u8>)]) -> Result<Vec<SourceModule>> {
+    fn visit(
+        sources: &[(AssetPath<'static>, Vec<u8>)],
+        index: usize,
+        name: String,
+        visited: &mut HashSet<usize>,
+        modules: &mut Vec<SourceModule>,
+    ) -> Result<()> {
+        if !visited.insert(index) {
+            return Ok(());
+        }
+
+        let (path, source) = &sources[index];
+        let source = std::str::from_utf8(source)
+            .map_err(|e| anyhow!("Shader {path} is not valid UTF-8: {e}"))?;
+
+        let imports = imported_modules(source).map_err(|e| anyhow!("{path}: {e}"))?;
+
+        for (import_name, import_path) in imports {
+            let import_path = path.resolve_embed(&import_path)?;
+
+            // Missing modules are reported by the compiler if they are actually needed
+            if let Some(import_index) = sources.iter().position(|(path, _)| *path == import_path) {
+                visit(sources, import_index, import_name, visited, modules)?;
+            }
+        }
+
+        modules.push(SourceModule {
+            name,
+            path: path.to_string(),
+            source: source.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    let name = sources[0]
+        .0
+        .path()
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut modules = Vec::new();
+    visit(sources, 0, name, &mut HashSet::new(), &mut modules)?;
+    Ok(modules)
+}
+
+/// Hash of everything that affects the compiled SPIR-V: the sources, their paths, the settings
+/// and the Slang installation that the compiler was built against.
+fn cache_key(sources: &[(AssetPath<'static>, Vec<u8>)], settings: &ShaderSettings) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    option_env!("SLANG_DIR").hash(&mut hasher);
+    option_env!("VULKAN_SDK").hash(&mut hasher);
+    settings.hash(&mut hasher);
+
+    for (path, source) in sources {
+        path.path().hash(&mut hasher);
+        source.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+/// Writes to a temporary file first, so that loads running in parallel never read a partially
+/// written file.
+fn write_cache(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Names and relative paths of the modules that Slang source code imports. `import a.b_c;` refers
+/// to `a/b-c.slang` and `import "a/b";` to `a/b.slang`. Comments and string literals are skipped,
+/// so this over-approximates only for imports in inactive preprocessor branches. Includes fail,
+/// since shaders are compiled from memory and the compiler can't read the included files.
+fn imported_modules(source: &str) -> Result<Vec<(String, String)>> {
+    let tokens = tokenize(source);
+    let mut modules = Vec::new();
+    let mut index = 0;
+
+    while let Some(token) = tokens.get(index) {
+        index += 1;
+
+        match token {
+            Token::Word("import") => {}
+            Token::Word("__include") | Token::Directive("include") => {
+                return Err(anyhow!(
+                    "Includes are not supported, import a module instead"
+                ));
+            }
+            _ => continue,
+        }
+
+        let (name, path) = match tokens.get(index) {
+            Some(Token::String(path)) => ((*path).to_owned(), (*path).to_owned()),
+            Some(Token::Word(_)) => {
+                let mut name = String::new();
+
+                while let Some(token) = tokens.get(index) {
+                    match token {
+                        Token::Word(word) => name.push_str(word),
+                        Token::Punct('.') => name.push('.'),
+                        _ => break,
+                    }
+
+                    index += 1;
+                }
+
+                let path = name.replace('.', "/").replace('_', "-");
+                (name, path)
+            }
+            _ => continue,
+        };
+
+        if Path::new(&path).extension().is_some() {
+            modules.push((name, path));
+        } else {
+            modules.push((name, format!("{path}.slang")));
+        }
+    }
+
+    Ok(modules)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Token<'a> {
+    Word(&'a str),
+    /// Preprocessor directive without the `#`.
+    Directive(&'a str),
+    /// Contents of a string literal without the quotes.
+    String(&'a str),
+    Punct(char),
+}
+
+/// Splits source code into words, strings and punctuation, dropping comments.
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.find('\n').map_or("", |end| &comment[end..]);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.find("*/").map_or("", |end| &comment[end + 2..]);
+        } else if let Some(string) = rest.strip_prefix('"') {
+            let mut escaped = false;
+
+            let end = string
+                .find(|c| {
+                    let is_end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    is_end
+                })
+                .unwrap_or(string.len());
+
+            tokens.push(Token::String(&string[..end]));
+            rest = string.get(end + 1..).unwrap_or_default();
+        } else if c == '_' || c.is_alphanumeric() || c == '#' {
+            let word = &rest[c.len_utf8()..];
+            let len = word
+                .find(|c: char| c != '_' && !c.is_alphanumeric())
+                .unwrap_or(word.len());
+
+            tokens.push(match c {
+                '#' => Token::Directive(&word[..len]),
+                _ => Token::Word(&rest[..c.len_utf8() + len]),
+            });
+
+            rest = &word[len..];
+        } else {
+            tokens.push(Token::Punct(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    tokens
 }
 
 fn read_spv(mut file: File) -> Result<Vec<u32>> {
@@ -80,3 +614,54 @@ fn read_spv(mut file: File) -> Result<Vec<u32>> {
     file.read_exact(bytemuck::cast_slice_mut(&mut code))?;
     Ok(code)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_imports() {
+        let source = r#"
+            import "common";
+            import utils.color_space; __exported import "parts/lighting.slang";
+            implementing scene;
+        "#;
+
+        assert_eq!(
+            imported_modules(source).unwrap(),
+            [
+                ("common".to_owned(), "common.slang".to_owned()),
+                (
+                    "utils.color_space".to_owned(),
+                    "utils/color-space.slang".to_owned()
+                ),
+                (
+                    "parts/lighting.slang".to_owned(),
+                    "parts/lighting.slang".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_imports_in_comments_and_strings() {
+        let source = r#"
+            // import old;
+            /* import "disabled";
+               #include "legacy.h" */
+            static const char *message = "import nothing;";
+            import kept; // import trailing;
+        "#;
+
+        assert_eq!(
+            imported_modules(source).unwrap(),
+            [("kept".to_owned(), "kept.slang".to_owned())]
+        );
+    }
+
+    #[test]
+    fn rejects_includes() {
+        assert!(imported_modules("#include \"macros.h\"").is_err());
+        assert!(imported_modules("__include parts;").is_err());
+    }
+}
@@ -5,7 +5,10 @@ use gpu_allocator::{
     vulkan::{Allocation, AllocationCreateDesc, AllocationScheme},
 };
 
-use super::RenderDevice;
+use super::{
+    RenderDevice,
+    resource_state_tracker::{ImageState, ResourceStateTracker},
+};
 
 #[derive(Default)]
 pub struct StorageImage {
@@ -23,9 +26,45 @@ impl RenderDevice {
         extent: vk::Extent2D,
         format: vk::Format,
         name: Option<&str>,
+    ) -> Result<StorageImage> {
+        self.create_image(
+            extent,
+            format,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+            Some(name.unwrap_or("Storage Image")),
+        )
+    }
+
+    pub fn create_image(
+        &self,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        name: Option<&str>,
+    ) -> Result<StorageImage> {
+        self.create_image_with_view_format(extent, format, format, usage, name)
+    }
+
+    /// Creates an image whose view uses `view_format`, which allows e.g. writing to an sRGB
+    /// image through a UNORM storage view. `usage` may include usages that only `view_format`
+    /// supports, such as storage for sRGB images.
+    pub fn create_image_with_view_format(
+        &self,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        view_format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        name: Option<&str>,
     ) -> Result<StorageImage> {
         unsafe {
+            let flags = if view_format != format {
+                vk::ImageCreateFlags::MUTABLE_FORMAT | vk::ImageCreateFlags::EXTENDED_USAGE
+            } else {
+                vk::ImageCreateFlags::empty()
+            };
+
             let image_create_info = vk::ImageCreateInfo::default()
+                .flags(flags)
                 .image_type(vk::ImageType::TYPE_2D)
                 .format(format)
                 .extent(extent.into())
@@ -33,12 +72,12 @@ impl RenderDevice {
                 .array_layers(1)
                 .samples(vk::SampleCountFlags::TYPE_1)
                 .tiling(vk::ImageTiling::OPTIMAL)
-                .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+                .usage(usage)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
             let image = self.device.create_image(&image_create_info, None)?;
             let requirements = self.device.get_image_memory_requirements(image);
-            let name = name.unwrap_or("Storage Image").to_owned();
+            let name = name.unwrap_or("Image").to_owned();
 
             let allocation = self.allocate(&AllocationCreateDesc {
                 name: &name,
@@ -54,7 +93,7 @@ impl RenderDevice {
             let image_view_create_info = vk::ImageViewCreateInfo::default()
                 .image(image)
                 .view_type(vk::ImageViewType::TYPE_2D)
-                .format(format)
+                .format(view_format)
                 .subresource_range(vk::ImageSubresourceRange {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     base_mip_level: 0,
@@ -78,6 +117,72 @@ impl RenderDevice {
         }
     }
 
+    /// Creates an additional view of `image`, e.g. to sample an sRGB image that is written
+    /// through a UNORM storage view. The view is restricted to `usage`, which `format` must
+    /// support even if the image has other usages.
+    pub fn create_image_view(
+        &self,
+        image: vk::Image,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<vk::ImageView> {
+        let mut image_view_usage_create_info = vk::ImageViewUsageCreateInfo::default().usage(usage);
+
+        let image_view_create_info = vk::ImageViewCreateInfo::default()
+            .push_next(&mut image_view_usage_create_info)
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+
+        unsafe {
+            Ok(self
+                .device
+                .create_image_view(&image_view_create_info, None)?)
+        }
+    }
+
+    /// Clears `image` to opaque black, e.g. so that images which nothing is rendered into are
+    /// still defined. The image must have been created with the transfer destination usage.
+    pub fn clear_image(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        tracker: &mut ResourceStateTracker,
+        image: vk::Image,
+    ) {
+        tracker
+            .transition_image(image, ImageState::transfer_dst())
+            .flush(self, command_buffer);
+
+        let clear_color = vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        };
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            self.device.cmd_clear_color_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &clear_color,
+                &[subresource_range],
+            );
+        }
+    }
+
     pub fn destroy_storage_image(&self, image: StorageImage) {
         unsafe {
             self.device.destroy_image_view(image.image_view, None);
@@ -86,3 +191,10 @@ impl RenderDevice {
         }
     }
 }
+
+pub fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
@@ -1,5 +1,5 @@
 use anyhow::{Result, anyhow};
-use ash::vk;
+use ash::{khr, vk};
 use bevy::prelude::*;
 use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 
@@ -14,7 +14,10 @@ pub struct SwapchainImage {
 #[derive(Resource)]
 pub struct Swapchain {
     pub render_device: RenderDevice,
+    pub surface_instance: khr::surface::Instance,
+    pub swapchain_device: khr::swapchain::Device,
     pub surface: vk::SurfaceKHR,
+    pub surface_format: vk::Format,
     pub surface_extent: vk::Extent2D,
     pub swapchain: vk::SwapchainKHR,
     pub swapchain_images: Vec<SwapchainImage>,
@@ -32,6 +35,13 @@ impl Swapchain {
         height: u32,
         old_swapchain: Option<&mut Self>,
     ) -> Result<Self> {
+        let (Some(surface_instance), Some(swapchain_device)) = (
+            render_device.surface_instance.clone(),
+            render_device.swapchain_device.clone(),
+        ) else {
+            return Err(anyhow!("Device was created without presentation support"));
+        };
+
         unsafe {
             let (old_swapchain, surface) = match old_swapchain {
                 Some(old_swapchain) => (
@@ -51,21 +61,27 @@ impl Swapchain {
                 }
             };
 
-            let surface_formats = render_device
-                .surface_instance
+            let surface_formats = surface_instance
                 .get_physical_device_surface_formats(render_device.physical_device, surface)?;
 
-            let surface_format = surface_formats
-                .iter()
-                .find(|format| {
-                    (format.format == vk::Format::B8G8R8A8_UNORM
-                        || format.format == vk::Format::R8G8B8A8_UNORM)
+            // Prefer sRGB formats so the hardware encodes on write, falling back to UNORM formats
+            // in which case the tonemapping pass encodes instead
+            let surface_format = [
+                vk::Format::B8G8R8A8_SRGB,
+                vk::Format::R8G8B8A8_SRGB,
+                vk::Format::B8G8R8A8_UNORM,
+                vk::Format::R8G8B8A8_UNORM,
+            ]
+            .iter()
+            .find_map(|&preferred_format| {
+                surface_formats.iter().find(|format| {
+                    format.format == preferred_format
                         && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
                 })
-                .ok_or_else(|| anyhow!("No suitable surface format found"))?;
+            })
+            .ok_or_else(|| anyhow!("No suitable surface format found"))?;
 
-            let surface_capabilities = render_device
-                .surface_instance
+            let surface_capabilities = surface_instance
                 .get_physical_device_surface_capabilities(render_device.physical_device, surface)?;
 
             let mut desired_image_count = surface_capabilities.min_image_count + 1;
@@ -79,8 +95,7 @@ impl Swapchain {
                 _ => surface_capabilities.current_extent,
             };
 
-            let present_mode = render_device
-                .surface_instance
+            let present_mode = surface_instance
                 .get_physical_device_surface_present_modes(render_device.physical_device, surface)?
                 .into_iter()
                 .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
@@ -94,7 +109,9 @@ impl Swapchain {
                 .image_extent(surface_extent)
                 .image_array_layers(1)
                 .image_usage(
-                    vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                    vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST
+                        | vk::ImageUsageFlags::COLOR_ATTACHMENT,
                 )
                 .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .queue_family_indices(std::slice::from_ref(&render_queue.queue_family_index))
@@ -104,12 +121,9 @@ impl Swapchain {
                 .clipped(true)
                 .old_swapchain(old_swapchain);
 
-            let swapchain = render_device
-                .swapchain_device
-                .create_swapchain(&swapchain_create_info, None)?;
+            let swapchain = swapchain_device.create_swapchain(&swapchain_create_info, None)?;
 
-            let swapchain_images = render_device
-                .swapchain_device
+            let swapchain_images = swapchain_device
                 .get_swapchain_images(swapchain)?
                 .into_iter()
                 .map(|image| {
@@ -148,7 +162,10 @@ impl Swapchain {
 
             Ok(Self {
                 render_device,
+                surface_instance,
+                swapchain_device,
                 surface,
+                surface_format: surface_format.format,
                 surface_extent,
                 swapchain,
                 swapchain_images,
@@ -160,7 +177,7 @@ impl Swapchain {
 
     pub fn acquire_next(&mut self, signal_semaphore: vk::Semaphore) -> Result<()> {
         unsafe {
-            let result = self.render_device.swapchain_device.acquire_next_image(
+            let result = self.swapchain_device.acquire_next_image(
                 self.swapchain,
                 u64::MAX,
                 signal_semaphore,
@@ -190,7 +207,6 @@ impl Swapchain {
                 .image_indices(std::slice::from_ref(&self.swapchain_image_index));
 
             let result = self
-                .render_device
                 .swapchain_device
                 .queue_present(render_queue.queue, &present_info);
 
@@ -222,13 +238,10 @@ impl Drop for Swapchain {
                     .destroy_image_view(swapchain_image.image_view, None);
             }
 
-            self.render_device
-                .swapchain_device
+            self.swapchain_device
                 .destroy_swapchain(self.swapchain, None);
 
-            self.render_device
-                .surface_instance
-                .destroy_surface(self.surface, None);
+            self.surface_instance.destroy_surface(self.surface, None);
         }
     }
 }
@@ -0,0 +1,207 @@
+use anyhow::{Result, anyhow};
+use ash::vk;
+use bevy::{
+    ecs::system::{SystemParamItem, lifetimeless::SRes},
+    prelude::*,
+};
+use bytemuck::{Pod, Zeroable};
+use gpu_allocator::MemoryLocation;
+
+use super::{
+    buffer::Buffer,
+    render_asset::{RenderAsset, RenderAssets, sync_render_assets},
+    render_device::RenderDevice,
+    render_queue::RenderQueue,
+    schedule::{Render, RenderSystems},
+};
+
+pub struct TexturePlugin;
+
+impl Plugin for TexturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Texture>()
+            .init_resource::<RenderAssets<GpuTexture>>()
+            .add_systems(
+                Render,
+                sync_render_assets::<GpuTexture>.in_set(RenderSystems::Prepare),
+            );
+    }
+}
+
+/// An sRGB image that shaders read texels from directly, with bilinear filtering and repeating
+/// UVs, see `sampleTexture` in `material.slang`.
+#[derive(Asset, TypePath, Clone, Default, Debug)]
+pub struct Texture {
+    pub size: UVec2,
+    /// Red, green, blue and alpha bytes of the texels, row by row from the top left.
+    pub data: Vec<u8>,
+}
+
+impl Texture {
+    /// Decodes a PNG or JPEG image.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let image = image::load_from_memory(bytes)?.into_rgba8();
+
+        Ok(Self {
+            size: UVec2::new(image.width(), image.height()),
+            data: image.into_raw(),
+        })
+    }
+}
+
+pub struct GpuTexture {
+    pub render_device: RenderDevice,
+    /// One texel per element in device local memory, read through its device address.
+    pub texel_buffer: Buffer<u32>,
+    pub size: UVec2,
+}
+
+impl RenderAsset for GpuTexture {
+    type SourceAsset = Texture;
+    type Param = (SRes<RenderDevice>, SRes<RenderQueue>);
+
+    fn prepare(
+        source_asset: &Self::SourceAsset,
+        (render_device, render_queue): &mut SystemParamItem<Self::Param>,
+        _previous_asset: Option<&Self>,
+    ) -> Result<Self> {
+        let texel_count = source_asset.size.element_product() as usize;
+
+        if texel_count == 0 {
+            return Err(anyhow!("Texture has no texels"));
+        }
+
+        if source_asset.data.len() != texel_count * 4 {
+            return Err(anyhow!(
+                "Texture of {}x{} texels has {} bytes instead of {}",
+                source_asset.size.x,
+                source_asset.size.y,
+                source_asset.data.len(),
+                texel_count * 4
+            ));
+        }
+
+        // Created into the texture, so that dropping it on an error destroys the buffer
+        let gpu_texture = Self {
+            render_device: render_device.clone(),
+            texel_buffer: render_device.create_buffer(
+                texel_count as u64,
+                vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS | vk::BufferUsageFlags::TRANSFER_DST,
+                MemoryLocation::GpuOnly,
+                Some("Texel Buffer"),
+            )?,
+            size: source_asset.size,
+        };
+
+        let mut staging_buffer: Buffer<u8> = render_device.create_buffer(
+            source_asset.data.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+            Some("Texel Staging Buffer"),
+        )?;
+
+        let result = staging_buffer
+            .slice_mut()
+            .map(|slice| slice.copy_from_slice(&source_asset.data))
+            .and_then(|()| gpu_texture.upload(render_queue, &staging_buffer));
+
+        render_device.destroy_buffer(staging_buffer);
+        result?;
+
+        Ok(gpu_texture)
+    }
+}
+
+impl GpuTexture {
+    /// Copies the texels from a staging buffer, waiting for the copy to finish.
+    fn upload(&self, render_queue: &RenderQueue, staging_buffer: &Buffer<u8>) -> Result<()> {
+        let device = &self.render_device.device;
+
+        unsafe {
+            let command_pool = device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .queue_family_index(render_queue.queue_family_index)
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                None,
+            )?;
+
+            let record_and_submit = || -> Result<()> {
+                let [command_buffer] = device
+                    .allocate_command_buffers(
+                        &vk::CommandBufferAllocateInfo::default()
+                            .command_pool(command_pool)
+                            .level(vk::CommandBufferLevel::PRIMARY)
+                            .command_buffer_count(1),
+                    )?
+                    .try_into()
+                    .map_err(|_| anyhow!("Failed to allocate texture upload command buffer"))?;
+
+                device.begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::default()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )?;
+
+                let region = vk::BufferCopy::default().size(staging_buffer.size);
+
+                device.cmd_copy_buffer(
+                    command_buffer,
+                    staging_buffer.buffer,
+                    self.texel_buffer.buffer,
+                    &[region],
+                );
+
+                let memory_barrier = vk::MemoryBarrier2::default()
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ);
+
+                device.cmd_pipeline_barrier2(
+                    command_buffer,
+                    &vk::DependencyInfo::default()
+                        .memory_barriers(std::slice::from_ref(&memory_barrier)),
+                );
+
+                device.end_command_buffer(command_buffer)?;
+
+                device.queue_submit(
+                    render_queue.queue,
+                    &[vk::SubmitInfo::default()
+                        .command_buffers(std::slice::from_ref(&command_buffer))],
+                    vk::Fence::null(),
+                )?;
+
+                render_queue.wait_idle();
+                Ok(())
+            };
+
+            let result = record_and_submit();
+            device.destroy_command_pool(command_pool, None);
+            result
+        }
+    }
+
+    pub fn texture_ref(&self) -> TextureRef {
+        TextureRef {
+            texels: self.texel_buffer.address,
+            size: self.size,
+        }
+    }
+}
+
+impl Drop for GpuTexture {
+    fn drop(&mut self) {
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.texel_buffer));
+    }
+}
+
+/// How shaders refer to a texture, `TextureRef` in `material.slang`. Textures that have not been
+/// uploaded yet have a size of zero, which samples as white.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable, Debug)]
+pub struct TextureRef {
+    pub texels: vk::DeviceAddress,
+    pub size: UVec2,
+}
@@ -1,17 +1,24 @@
+use std::collections::HashSet;
+
 use anyhow::{Result, anyhow};
 use ash::vk;
-use bevy::{math::Affine3A, prelude::*};
+use bevy::{ecs::system::SystemParam, math::Affine3A, prelude::*};
 use bytemuck::{Pod, Zeroable};
 use gpu_allocator::MemoryLocation;
 
+use crate::material::{AlphaMode, Material};
+
 use super::{
     blas::Blas,
     buffer::Buffer,
     mesh::GpuMesh,
+    procedural::{GpuProceduralGeometry, Procedural3d, ProceduralGeometry},
+    ray_tracing::{HitGroupShaders, RayTracingPipeline, RayTracingShaders},
     render_asset::{RenderAssets, sync_render_assets},
     render_device::RenderDevice,
     render_queue::RenderQueue,
     schedule::{Render, RenderSystems},
+    texture::{GpuTexture, Texture, TextureRef},
 };
 
 pub struct TlasPlugin;
@@ -22,7 +29,9 @@ impl Plugin for TlasPlugin {
             Render,
             build_tlas
                 .in_set(RenderSystems::Prepare)
-                .after(sync_render_assets::<GpuMesh>),
+                .after(sync_render_assets::<GpuMesh>)
+                .after(sync_render_assets::<GpuProceduralGeometry>)
+                .after(sync_render_assets::<GpuTexture>),
         );
     }
 }
@@ -33,35 +42,80 @@ fn build_tlas(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     gpu_meshes: Res<RenderAssets<GpuMesh>>,
+    gpu_procedurals: Res<RenderAssets<GpuProceduralGeometry>>,
+    gpu_textures: Res<RenderAssets<GpuTexture>>,
+    ray_tracing_shaders: Res<RayTracingShaders>,
     tlas: Option<ResMut<Tlas>>,
-    mut asset_events: MessageReader<AssetEvent<Mesh>>,
-    mesh3ds: Query<(&GlobalTransform, &Mesh3d)>,
-    changed_mesh3ds: Query<(), Changed<Mesh3d>>,
-    changed_transforms: Query<(), (Changed<GlobalTransform>, With<Mesh3d>)>,
-    removed_mesh3ds: RemovedComponents<Mesh3d>,
+    mut instance_changes: InstanceChanges,
+    mesh3ds: Query<(Entity, &GlobalTransform, &Mesh3d, Option<&Material>)>,
+    procedural3ds: Query<(Entity, &GlobalTransform, &Procedural3d, Option<&Material>)>,
+    mut warnings: Local<MaterialWarnings>,
 ) -> Result<(), BevyError> {
-    let build_tlas = tlas.is_none()
-        | !changed_mesh3ds.is_empty()
-        | !changed_transforms.is_empty()
-        | !removed_mesh3ds.is_empty()
-        | !asset_events.is_empty();
-
-    asset_events.clear();
+    let build_tlas = tlas.is_none() | instance_changes.any();
 
     if !build_tlas {
         return Ok(());
     }
 
-    let instances: Vec<_> = mesh3ds
-        .iter()
-        .filter_map(|(transform, Mesh3d(mesh_handle))| {
-            let mesh = gpu_meshes.get(&mesh_handle.id())?;
+    let mesh_instances =
+        mesh3ds
+            .iter()
+            .filter_map(|(entity, transform, Mesh3d(mesh_handle), material)| {
+                let mesh = gpu_meshes.get(&mesh_handle.id())?;
+
+                Some(BlasInstance {
+                    entity,
+                    geometry: InstanceGeometry::Mesh(mesh_handle.id()),
+                    mesh_index: mesh.mesh_index,
+                    blas: &mesh.blas,
+                    transform: transform.affine(),
+                    material: material.cloned().unwrap_or_default(),
+                    base_color_texture: None,
+                })
+            });
+
+    let procedural_instances = procedural3ds.iter().filter_map(
+        |(entity, transform, Procedural3d(geometry_handle), material)| {
+            let geometry = gpu_procedurals.get(&geometry_handle.id())?;
 
             Some(BlasInstance {
-                mesh_index: mesh.mesh_index,
-                blas: &mesh.blas,
+                entity,
+                geometry: InstanceGeometry::Procedural(geometry_handle.id()),
+                mesh_index: geometry.mesh_index,
+                blas: &geometry.blas,
                 transform: transform.affine(),
+                material: material.cloned().unwrap_or_default(),
+                base_color_texture: None,
             })
+        },
+    );
+
+    let instances: Vec<_> = mesh_instances
+        .chain(procedural_instances)
+        .filter_map(|mut instance| {
+            instance.material.hit_group =
+                select_hit_group(&ray_tracing_shaders, &instance, &mut warnings)?;
+
+            if let Some(callable) = instance.material.callable
+                && callable as usize >= ray_tracing_shaders.callables.len()
+            {
+                if warnings.callables.insert(callable) {
+                    warn!(
+                        "Callable {callable} does not exist, used by {} among others",
+                        instance.entity
+                    );
+                }
+
+                instance.material.callable = None;
+            }
+
+            instance.base_color_texture = instance
+                .material
+                .base_color_texture
+                .as_ref()
+                .and_then(|texture| gpu_textures.get(&texture.id()));
+
+            Some(instance)
         })
         .collect();
 
@@ -70,10 +124,105 @@ fn build_tlas(
     Ok(())
 }
 
+type Instanced = Or<(With<Mesh3d>, With<Procedural3d>)>;
+
+/// Changes to the instanced geometry that the TLAS is rebuilt on.
+#[derive(SystemParam)]
+struct InstanceChanges<'w, 's> {
+    mesh_events: MessageReader<'w, 's, AssetEvent<Mesh>>,
+    procedural_events: MessageReader<'w, 's, AssetEvent<ProceduralGeometry>>,
+    texture_events: MessageReader<'w, 's, AssetEvent<Texture>>,
+    changed_mesh3ds: Query<'w, 's, (), Changed<Mesh3d>>,
+    changed_procedural3ds: Query<'w, 's, (), Changed<Procedural3d>>,
+    changed_transforms: Query<'w, 's, (), (Changed<GlobalTransform>, Instanced)>,
+    changed_materials: Query<'w, 's, (), (Changed<Material>, Instanced)>,
+    removed_mesh3ds: RemovedComponents<'w, 's, Mesh3d>,
+    removed_procedural3ds: RemovedComponents<'w, 's, Procedural3d>,
+    removed_materials: RemovedComponents<'w, 's, Material>,
+}
+
+impl InstanceChanges<'_, '_> {
+    fn any(&mut self) -> bool {
+        let any = !self.changed_mesh3ds.is_empty()
+            | !self.changed_procedural3ds.is_empty()
+            | !self.changed_transforms.is_empty()
+            | !self.changed_materials.is_empty()
+            | !self.removed_mesh3ds.is_empty()
+            | !self.removed_procedural3ds.is_empty()
+            | !self.removed_materials.is_empty()
+            | !self.mesh_events.is_empty()
+            | !self.procedural_events.is_empty()
+            | !self.texture_events.is_empty();
+
+        self.mesh_events.clear();
+        self.procedural_events.clear();
+        self.texture_events.clear();
+        any
+    }
+}
+
+/// Invalid material settings that have been warned about, which are only reported once rather than
+/// on every TLAS rebuild.
+#[derive(Default)]
+struct MaterialWarnings {
+    /// Hit groups that cannot shade triangles (`false`) or procedural geometry (`true`).
+    hit_groups: HashSet<(u32, bool)>,
+    callables: HashSet<u32>,
+}
+
+/// Returns the material's hit group if it can shade the instance's kind of geometry, or else the
+/// first one that can, since triangles and procedural geometry need different hit groups.
+fn select_hit_group(
+    shaders: &RayTracingShaders,
+    instance: &BlasInstance,
+    warnings: &mut MaterialWarnings,
+) -> Option<u32> {
+    let is_procedural = matches!(instance.geometry, InstanceGeometry::Procedural(_));
+    let is_compatible =
+        |hit_group: &HitGroupShaders| hit_group.intersection.is_some() == is_procedural;
+    let hit_group = instance.material.hit_group;
+
+    if shaders
+        .hit_groups
+        .get(hit_group as usize)
+        .is_some_and(is_compatible)
+    {
+        return Some(hit_group);
+    }
+
+    let fallback = shaders.hit_groups.iter().position(is_compatible);
+
+    if warnings.hit_groups.insert((hit_group, is_procedural)) {
+        let geometry = if is_procedural {
+            "procedural geometry"
+        } else {
+            "meshes"
+        };
+
+        match fallback {
+            Some(fallback) => warn!(
+                "Hit group {hit_group} cannot shade {geometry} such as {}, falling back to {fallback}",
+                instance.entity
+            ),
+            None => warn!(
+                "No hit group can shade {geometry} such as {}",
+                instance.entity
+            ),
+        }
+    }
+
+    fallback.map(|fallback| fallback as u32)
+}
+
 pub struct BlasInstance<'a> {
+    pub entity: Entity,
+    pub geometry: InstanceGeometry,
     pub mesh_index: u32,
     pub blas: &'a Blas,
     pub transform: Affine3A,
+    pub material: Material,
+    /// The material's texture, if it has been uploaded.
+    pub base_color_texture: Option<&'a GpuTexture>,
 }
 
 #[repr(C)]
@@ -99,12 +248,21 @@ impl From<&BlasInstance<'_>> for AccelerationStructureInstance {
             ],
         };
 
+        // Any-hit shaders only run for non-opaque materials, since they cost traversal time
+        let opacity = match instance.material.alpha_mode {
+            AlphaMode::Opaque => vk::GeometryInstanceFlagsKHR::FORCE_OPAQUE,
+            _ => vk::GeometryInstanceFlagsKHR::FORCE_NO_OPAQUE,
+        };
+
+        let flags = vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE | opacity;
+
         Self(vk::AccelerationStructureInstanceKHR {
             transform,
             instance_custom_index_and_mask: vk::Packed24_8::new(instance.mesh_index, 0xFF),
+            // Every hit group has one record per ray type, see `RayTracingPipeline::RAY_TYPE_COUNT`
             instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
-                0,
-                vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as _,
+                instance.material.hit_group * RayTracingPipeline::RAY_TYPE_COUNT,
+                flags.as_raw() as _,
             ),
             acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
                 device_handle: instance.blas.device_address,
@@ -118,12 +276,54 @@ pub struct Tlas {
     render_device: RenderDevice,
     buffer: Buffer,
     acceleration_structure: vk::AccelerationStructureKHR,
+    instances: Vec<TlasInstance>,
+    instance_info_buffer: Buffer<InstanceInfo>,
 }
 
 impl Tlas {
     pub fn acceleration_structure(&self) -> &vk::AccelerationStructureKHR {
         &self.acceleration_structure
     }
+
+    /// Instances in the order they were built, so that `InstanceIndex()` in shaders indexes into
+    /// this slice.
+    pub fn instances(&self) -> &[TlasInstance] {
+        &self.instances
+    }
+
+    /// Per instance shading data, indexed by `InstanceIndex()` in shaders.
+    pub fn instance_info_buffer(&self) -> &Buffer<InstanceInfo> {
+        &self.instance_info_buffer
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Pod, Zeroable)]
+pub struct InstanceInfo {
+    /// Base color texture of the instance's material, which has a size of zero for none.
+    pub base_color_texture: TextureRef,
+    /// Linear base color of the instance's material.
+    pub base_color: Vec3,
+    pub alpha: f32,
+    /// `AlphaMode` of the material, see `ALPHA_MODE_*` in `material.slang`.
+    pub alpha_mode: u32,
+    pub alpha_cutoff: f32,
+    /// Callable shader of the material, or `u32::MAX` for none.
+    pub callable: u32,
+    /// Rounds the size up to the alignment of the texture address.
+    pub _padding: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TlasInstance {
+    pub entity: Entity,
+    pub geometry: InstanceGeometry,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InstanceGeometry {
+    Mesh(AssetId<Mesh>),
+    Procedural(AssetId<ProceduralGeometry>),
 }
 
 impl RenderDevice {
@@ -268,10 +468,52 @@ impl RenderDevice {
             self.destroy_buffer(scratch_buffer);
             self.destroy_buffer(instance_buffer);
 
+            // Storage buffers cannot be empty, so there is always at least one slot
+            let mut instance_info_buffer = self.create_buffer::<InstanceInfo>(
+                instance_count.max(1) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                MemoryLocation::CpuToGpu,
+                Some("TLAS Instance Info Buffer"),
+            )?;
+
+            instance_info_buffer
+                .slice_mut()?
+                .iter_mut()
+                .zip(instances)
+                .for_each(|(slot, instance)| {
+                    let base_color = instance.material.base_color.to_linear();
+                    let alpha_mode = instance.material.alpha_mode;
+
+                    *slot = InstanceInfo {
+                        base_color_texture: instance
+                            .base_color_texture
+                            .map_or(TextureRef::default(), GpuTexture::texture_ref),
+                        base_color: base_color.to_vec3(),
+                        alpha: base_color.alpha,
+                        alpha_mode: alpha_mode.index(),
+                        alpha_cutoff: match alpha_mode {
+                            AlphaMode::Mask { cutoff } => cutoff,
+                            _ => 0.0,
+                        },
+                        callable: instance.material.callable.unwrap_or(u32::MAX),
+                        _padding: 0,
+                    };
+                });
+
+            let instances = instances
+                .iter()
+                .map(|instance| TlasInstance {
+                    entity: instance.entity,
+                    geometry: instance.geometry,
+                })
+                .collect();
+
             Ok(Tlas {
                 render_device: self.clone(),
                 acceleration_structure,
                 buffer,
+                instances,
+                instance_info_buffer,
             })
         }
     }
@@ -286,6 +528,9 @@ impl Drop for Tlas {
 
             self.render_device
                 .destroy_buffer(std::mem::take(&mut self.buffer));
+
+            self.render_device
+                .destroy_buffer(std::mem::take(&mut self.instance_info_buffer));
         }
     }
 }
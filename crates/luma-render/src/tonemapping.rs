@@ -0,0 +1,550 @@
+use anyhow::{Result, anyhow};
+use ash::vk;
+use bevy::prelude::*;
+use bytemuck::{Pod, Zeroable};
+
+use super::{
+    RenderDevice,
+    camera::{Camera, CameraTarget},
+    ray_tracing::{DebugView, RayTracingPipeline, RayTracingSettings},
+    render_context::RenderContext,
+    render_target::RenderTarget,
+    resource_state_tracker::{ImageState, ResourceStateTracker},
+    schedule::{Render, RenderSystems},
+    shader::{Shader, ShaderDiagnostics},
+    storage_image::is_srgb,
+    view::{View, Views},
+};
+
+pub struct TonemappingPlugin;
+
+impl Plugin for TonemappingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Tonemapping>()
+            .add_systems(Startup, load_shader)
+            .add_systems(
+                Render,
+                (
+                    create_or_update_tonemapping_pipeline.in_set(RenderSystems::Prepare),
+                    execute_tonemapping_pipeline.in_set(RenderSystems::QueuePostProcess),
+                ),
+            );
+    }
+}
+
+/// Settings of the post pass that maps the HDR ray traced image to the display.
+#[derive(Resource, Clone)]
+pub struct Tonemapping {
+    pub tonemapper: Tonemapper,
+    /// Exposure compensation in stops, applied before tonemapping and on top of the physical
+    /// exposure of the camera.
+    pub exposure: f32,
+}
+
+impl Default for Tonemapping {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::AgX,
+            exposure: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Tonemapper {
+    /// Clamps values outside of the displayable range.
+    None,
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and output transforms.
+    Aces,
+    #[default]
+    AgX,
+}
+
+impl Tonemapper {
+    pub const ALL: [Self; 4] = [Self::None, Self::Reinhard, Self::Aces, Self::AgX];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Reinhard => "Reinhard",
+            Self::Aces => "ACES",
+            Self::AgX => "AgX",
+        }
+    }
+
+    fn index(&self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Reinhard => 1,
+            Self::Aces => 2,
+            Self::AgX => 3,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct TonemappingShaderHandle(Handle<Shader>);
+
+fn load_shader(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(TonemappingShaderHandle(
+        asset_server.load("shaders/tonemapping.slang"),
+    ));
+}
+
+fn create_or_update_tonemapping_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    shader_handle: Res<TonemappingShaderHandle>,
+    assets: Res<Assets<Shader>>,
+    mut asset_events: MessageReader<AssetEvent<Shader>>,
+    mut shader_diagnostics: ResMut<ShaderDiagnostics>,
+    mut is_built: Local<bool>,
+) {
+    let Some(shader) = assets.get(&shader_handle.0) else {
+        return;
+    };
+
+    let is_shader_modified = asset_events.read().any(|asset_event| {
+        matches!(asset_event, AssetEvent::Modified { id } if *id == shader_handle.0.id())
+    });
+
+    // A pipeline that failed to build is only retried once the shader changes
+    if *is_built && !is_shader_modified {
+        return;
+    }
+
+    *is_built = true;
+
+    // The last good pipeline stays in use until the error is fixed
+    match TonemappingPipeline::new(render_device.clone(), shader) {
+        Ok(pipeline) => {
+            shader_diagnostics.remove(TonemappingPipeline::NAME);
+            commands.insert_resource(pipeline);
+        }
+        Err(err) => {
+            error!("Failed to build tonemapping pipeline: {err}");
+            shader_diagnostics.insert(TonemappingPipeline::NAME, &err.to_string());
+        }
+    }
+}
+
+fn execute_tonemapping_pipeline(
+    render_target: Res<RenderTarget>,
+    render_context: Res<RenderContext>,
+    mut resource_state_tracker: ResMut<ResourceStateTracker>,
+    ray_tracing_pipeline: Option<Res<RayTracingPipeline>>,
+    tonemapping_pipeline: Option<Res<TonemappingPipeline>>,
+    tonemapping: Res<Tonemapping>,
+    settings: Res<RayTracingSettings>,
+    views: Res<Views>,
+    cameras: Query<&Camera>,
+) -> Result<(), BevyError> {
+    // Regions not covered by any viewport stay black
+    render_context.render_device.clear_image(
+        render_context.command_buffer,
+        &mut resource_state_tracker,
+        render_target.image(),
+    );
+
+    // Only views that have been traced this frame hold a valid image, so until both pipelines
+    // have been built the output images are cleared rather than left undefined
+    let (Some(_), Some(tonemapping_pipeline)) = (ray_tracing_pipeline, tonemapping_pipeline) else {
+        for view in views.values() {
+            render_context.render_device.clear_image(
+                render_context.command_buffer,
+                &mut resource_state_tracker,
+                view.output_image.image,
+            );
+        }
+
+        return Ok(());
+    };
+
+    tonemapping_pipeline.reset_descriptor_sets()?;
+
+    // Debug views write colors meant to be displayed as they are
+    let passthrough = settings.debug_view != DebugView::None;
+
+    for (entity, view) in views.sorted() {
+        tonemapping_pipeline.tonemap(
+            render_context.command_buffer,
+            &mut resource_state_tracker,
+            view,
+            &tonemapping,
+            cameras.get(entity).ok(),
+            passthrough,
+        )?;
+
+        // Views with an image target are left in their output image
+        if view.target == CameraTarget::Primary {
+            tonemapping_pipeline.blit(
+                render_context.command_buffer,
+                &mut resource_state_tracker,
+                view,
+                render_target.image(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Compute pass that applies exposure, tonemapping and the letterbox overlay to the ray traced
+/// image of each view and writes sRGB encoded values into its output image, which is then
+/// blitted into the view's viewport of the render target.
+#[derive(Resource)]
+pub struct TonemappingPipeline {
+    pub render_device: RenderDevice,
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    /// Holds one descriptor set per view tonemapped in the current frame.
+    pub descriptor_pool: vk::DescriptorPool,
+}
+
+impl TonemappingPipeline {
+    /// Origin of build errors in `ShaderDiagnostics`.
+    pub const NAME: &str = "Tonemapping pipeline";
+
+    const WORKGROUP_SIZE: u32 = 8;
+
+    /// The shader always writes sRGB encoded values through a UNORM view. The output image is
+    /// created with an sRGB format when the render target has one, so that blitting decodes and
+    /// re-encodes the values, and with a UNORM format otherwise, so that they are copied as is.
+    pub fn output_format(render_target_format: vk::Format) -> vk::Format {
+        if is_srgb(render_target_format) {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        }
+    }
+
+    pub fn new(render_device: RenderDevice, shader: &Shader) -> Result<Self> {
+        // Dropping this if a later step fails destroys the objects created so far
+        let mut tonemapping_pipeline = Self {
+            render_device: render_device.clone(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+        };
+
+        unsafe {
+            let descriptor_set_layout_bindings = [
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            ];
+
+            let descriptor_set_layout_create_info = vk::DescriptorSetLayoutCreateInfo::default()
+                .bindings(&descriptor_set_layout_bindings);
+
+            tonemapping_pipeline.descriptor_set_layout = render_device
+                .device
+                .create_descriptor_set_layout(&descriptor_set_layout_create_info, None)?;
+
+            let push_constant_range = vk::PushConstantRange::default()
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .offset(0)
+                .size(size_of::<PushConstants>() as u32);
+
+            let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(std::slice::from_ref(
+                    &tonemapping_pipeline.descriptor_set_layout,
+                ))
+                .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+
+            tonemapping_pipeline.pipeline_layout = render_device
+                .device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)?;
+
+            let shader_module = render_device.device.create_shader_module(
+                &vk::ShaderModuleCreateInfo::default().code(&shader.code),
+                None,
+            )?;
+
+            let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(shader_module)
+                .name(&shader.entry_point);
+
+            let pipeline_create_info = vk::ComputePipelineCreateInfo::default()
+                .stage(shader_stage)
+                .layout(tonemapping_pipeline.pipeline_layout);
+
+            let pipelines = render_device.device.create_compute_pipelines(
+                render_device.pipeline_cache,
+                &[pipeline_create_info],
+                None,
+            );
+
+            render_device
+                .device
+                .destroy_shader_module(shader_module, None);
+
+            tonemapping_pipeline.pipeline = pipelines
+                .map_err(|(_, result)| {
+                    anyhow!("Failed to create tonemapping pipeline: {result:?}")
+                })?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Failed to create tonemapping pipeline"))?;
+
+            let pool_size = vk::DescriptorPoolSize::default()
+                .ty(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(
+                    descriptor_set_layout_bindings.len() as u32 * RayTracingPipeline::MAX_VIEWS,
+                );
+
+            let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::default()
+                .max_sets(RayTracingPipeline::MAX_VIEWS)
+                .pool_sizes(std::slice::from_ref(&pool_size));
+
+            tonemapping_pipeline.descriptor_pool = render_device
+                .device
+                .create_descriptor_pool(&descriptor_pool_create_info, None)?;
+        }
+
+        Ok(tonemapping_pipeline)
+    }
+
+    /// Frees the descriptor sets allocated by `tonemap`, which must no longer be in use.
+    pub fn reset_descriptor_sets(&self) -> Result<()> {
+        unsafe {
+            self.render_device.device.reset_descriptor_pool(
+                self.descriptor_pool,
+                vk::DescriptorPoolResetFlags::empty(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn tonemap(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        tracker: &mut ResourceStateTracker,
+        view: &View,
+        tonemapping: &Tonemapping,
+        camera: Option<&Camera>,
+        passthrough: bool,
+    ) -> Result<()> {
+        let input_image_info = vk::DescriptorImageInfo::default()
+            .image_view(view.radiance_image.image_view)
+            .image_layout(vk::ImageLayout::GENERAL);
+
+        let output_image_info = vk::DescriptorImageInfo::default()
+            .image_view(view.output_image.image_view)
+            .image_layout(vk::ImageLayout::GENERAL);
+
+        let descriptor_set = unsafe {
+            let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(std::slice::from_ref(&self.descriptor_set_layout));
+
+            let [descriptor_set] = self
+                .render_device
+                .device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)?
+                .try_into()
+                .map_err(|_| anyhow!("Failed to allocate exactly one descriptor set"))?;
+
+            self.render_device.device.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(std::slice::from_ref(&input_image_info)),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(std::slice::from_ref(&output_image_info)),
+                ],
+                &[],
+            );
+
+            descriptor_set
+        };
+
+        tracker
+            .transition_image(
+                view.radiance_image.image,
+                ImageState {
+                    layout: vk::ImageLayout::GENERAL,
+                    access: vk::AccessFlags2::SHADER_STORAGE_READ,
+                    stages: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                },
+            )
+            .transition_image(
+                view.output_image.image,
+                ImageState {
+                    layout: vk::ImageLayout::GENERAL,
+                    access: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                    stages: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                },
+            )
+            .flush(&self.render_device, command_buffer);
+
+        let vk::Extent2D { width, height } = view.output_image.extent;
+        let aspect_ratio = width as f32 / height as f32;
+        let mut exposure = tonemapping.exposure.exp2();
+        let mut sensor_frame = Vec2::ONE;
+        let mut letterbox = 0.0;
+
+        if let Some(camera) = camera {
+            if camera.physical_exposure {
+                exposure *= camera.exposure();
+            }
+
+            sensor_frame = camera.sensor_frame(aspect_ratio);
+            letterbox = camera.letterbox;
+        }
+
+        let push_constants = PushConstants {
+            exposure,
+            tonemapper: tonemapping.tonemapper.index(),
+            sensor_frame,
+            letterbox,
+            passthrough: passthrough.into(),
+        };
+
+        unsafe {
+            self.render_device.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+
+            self.render_device.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+
+            self.render_device.device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&push_constants),
+            );
+
+            self.render_device.device.cmd_dispatch(
+                command_buffer,
+                width.div_ceil(Self::WORKGROUP_SIZE),
+                height.div_ceil(Self::WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Blits the output image of `view` into its viewport of the render target.
+    pub fn blit(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        tracker: &mut ResourceStateTracker,
+        view: &View,
+        target_image: vk::Image,
+    ) {
+        let vk::Rect2D { offset, extent } = view.viewport;
+
+        if extent.width == 0 || extent.height == 0 {
+            return;
+        }
+
+        tracker
+            .transition_image(view.output_image.image, ImageState::transfer_src())
+            .transition_image(target_image, ImageState::transfer_dst())
+            .flush(&self.render_device, command_buffer);
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let x = view.output_image.extent.width as i32;
+        let y = view.output_image.extent.height as i32;
+
+        let src_offsets = [
+            vk::Offset3D { x: 0, y: 0, z: 0 },
+            vk::Offset3D { x, y, z: 1 },
+        ];
+
+        let dst_offsets = [
+            vk::Offset3D {
+                x: offset.x,
+                y: offset.y,
+                z: 0,
+            },
+            vk::Offset3D {
+                x: offset.x + extent.width as i32,
+                y: offset.y + extent.height as i32,
+                z: 1,
+            },
+        ];
+
+        let image_blit = vk::ImageBlit::default()
+            .src_subresource(subresource)
+            .src_offsets(src_offsets)
+            .dst_subresource(subresource)
+            .dst_offsets(dst_offsets);
+
+        unsafe {
+            self.render_device.device.cmd_blit_image(
+                command_buffer,
+                view.output_image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                target_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[image_blit],
+                vk::Filter::LINEAR,
+            );
+        }
+    }
+}
+
+impl Drop for TonemappingPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.render_device
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.render_device
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.render_device
+                .device
+                .destroy_pipeline(self.pipeline, None);
+            self.render_device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod)]
+struct PushConstants {
+    exposure: f32,
+    tonemapper: u32,
+    sensor_frame: Vec2,
+    letterbox: f32,
+    passthrough: u32,
+}
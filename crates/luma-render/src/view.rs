@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use ash::vk;
+use bevy::prelude::*;
+use gpu_allocator::MemoryLocation;
+
+use crate::camera::{Camera, CameraTarget};
+
+use super::{
+    RenderDevice,
+    buffer::Buffer,
+    ray_tracing::{RayTracingPipeline, RayTracingSettings},
+    render_target::RenderTarget,
+    resource_state_tracker::ResourceStateTracker,
+    schedule::{Render, RenderSystems},
+    storage_image::StorageImage,
+    tonemapping::TonemappingPipeline,
+};
+
+pub struct ViewPlugin;
+
+impl Plugin for ViewPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Views>()
+            .add_systems(Render, prepare_views.in_set(RenderSystems::Prepare));
+    }
+}
+
+/// Images of the active cameras, keyed by camera entity.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Views {
+    #[deref]
+    views: HashMap<Entity, View>,
+    /// Views that were replaced or removed in the current frame. They may still be in use by the
+    /// previous frame, so they are only destroyed once the next frame has waited for it.
+    retired: Vec<View>,
+}
+
+impl Views {
+    /// Views in the order they are composed in, which is by ascending camera priority.
+    pub fn sorted(&self) -> Vec<(Entity, &View)> {
+        let mut views = self
+            .iter()
+            .map(|(&entity, view)| (entity, view))
+            .collect::<Vec<_>>();
+
+        views.sort_by_key(|(entity, view)| (view.priority, *entity));
+        views
+    }
+
+    /// The view with the lowest priority rendering into the primary target, which is usually the
+    /// main camera, or any view if no camera renders into the primary target.
+    pub fn main(&self) -> Option<(Entity, &View)> {
+        let views = self.sorted();
+
+        views
+            .iter()
+            .find(|(_, view)| view.target == CameraTarget::Primary)
+            .or(views.first())
+            .copied()
+    }
+}
+
+/// Images a camera renders into. The ray tracing pipeline writes the radiance image, and the
+/// tonemapping pipeline writes the output image, which is then composed into the primary target
+/// or used directly as the camera's image target.
+pub struct View {
+    pub render_device: RenderDevice,
+    pub target: CameraTarget,
+    /// Region of the primary target the output image is blitted into, which may be empty.
+    pub viewport: vk::Rect2D,
+    pub priority: i32,
+    /// Linear HDR radiance
+    pub radiance_image: StorageImage,
+    /// Tonemapped and sRGB encoded image, written through a UNORM storage view.
+    pub output_image: StorageImage,
+    /// View of the output image in its own format, for sampling it e.g. from egui.
+    pub output_image_view: vk::ImageView,
+    /// Camera and frame data of the ray tracing shaders, written by `trace_rays`.
+    pub uniform_buffer: Buffer,
+    /// Number of frames averaged in the radiance image, which restarts from zero whenever the
+    /// camera or scene changes.
+    pub sample_count: u32,
+}
+
+impl View {
+    pub fn new(
+        render_device: RenderDevice,
+        extent: vk::Extent2D,
+        output_format: vk::Format,
+    ) -> Result<Self> {
+        // Cleared when there is nothing to trace, so that the tonemapped image is defined
+        let radiance_image = render_device.create_image(
+            extent,
+            RayTracingPipeline::FORMAT,
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            Some("View Radiance Image"),
+        )?;
+
+        // Cleared until the pipelines have been built, so that it never shows stale contents
+        let output_image = render_device.create_image_with_view_format(
+            extent,
+            output_format,
+            vk::Format::R8G8B8A8_UNORM,
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::SAMPLED,
+            Some("View Output Image"),
+        )?;
+
+        let output_image_view = render_device.create_image_view(
+            output_image.image,
+            output_format,
+            vk::ImageUsageFlags::SAMPLED,
+        )?;
+
+        let uniform_buffer = render_device.create_buffer(
+            RayTracingPipeline::VIEW_UNIFORMS_SIZE,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryLocation::CpuToGpu,
+            Some("View Uniform Buffer"),
+        )?;
+
+        Ok(Self {
+            render_device,
+            target: default(),
+            viewport: vk::Rect2D::default(),
+            priority: 0,
+            radiance_image,
+            output_image,
+            output_image_view,
+            uniform_buffer,
+            sample_count: 0,
+        })
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.radiance_image.extent
+    }
+
+    fn untrack(&self, tracker: &mut ResourceStateTracker) {
+        tracker
+            .untrack_image(self.radiance_image.image)
+            .untrack_image(self.output_image.image);
+    }
+}
+
+impl Drop for View {
+    fn drop(&mut self) {
+        unsafe {
+            self.render_device
+                .device
+                .destroy_image_view(self.output_image_view, None);
+        }
+
+        self.render_device
+            .destroy_storage_image(std::mem::take(&mut self.radiance_image));
+        self.render_device
+            .destroy_storage_image(std::mem::take(&mut self.output_image));
+        self.render_device
+            .destroy_buffer(std::mem::take(&mut self.uniform_buffer));
+    }
+}
+
+pub(crate) fn prepare_views(
+    render_device: Res<RenderDevice>,
+    render_target: Res<RenderTarget>,
+    settings: Res<RayTracingSettings>,
+    mut views: ResMut<Views>,
+    mut tracker: ResMut<ResourceStateTracker>,
+    cameras: Query<(Entity, &Camera)>,
+    mut warned_max_views: Local<bool>,
+) -> Result<(), BevyError> {
+    // The previous frame has finished before the render schedule runs, and it was the last one
+    // that could use the views retired in it
+    views.retired.clear();
+
+    let mut active_cameras = cameras
+        .iter()
+        .filter(|(_, camera)| camera.is_active)
+        .collect::<Vec<_>>();
+
+    active_cameras.sort_by_key(|(entity, camera)| (camera.priority, *entity));
+
+    let max_views = RayTracingPipeline::MAX_VIEWS as usize;
+
+    if active_cameras.len() > max_views && !*warned_max_views {
+        warn!(
+            "{} cameras are active, but at most {max_views} views are supported. Only the ones \
+             with the lowest priority are rendered",
+            active_cameras.len()
+        );
+    }
+
+    *warned_max_views = active_cameras.len() > max_views;
+    active_cameras.truncate(max_views);
+
+    let Views { views, retired } = &mut *views;
+
+    for entity in views.keys().copied().collect::<Vec<_>>() {
+        if !active_cameras.iter().any(|&(active, _)| active == entity)
+            && let Some(view) = views.remove(&entity)
+        {
+            view.untrack(&mut tracker);
+            retired.push(view);
+        }
+    }
+
+    for (entity, camera) in active_cameras {
+        let (viewport, output_format) = match camera.target {
+            CameraTarget::Primary => (
+                viewport_rect(camera.viewport, render_target.extent()),
+                TonemappingPipeline::output_format(render_target.format()),
+            ),
+            CameraTarget::Image { width, height } | CameraTarget::Egui { width, height } => (
+                vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent: vk::Extent2D {
+                        width: width.max(1),
+                        height: height.max(1),
+                    },
+                },
+                vk::Format::R8G8B8A8_SRGB,
+            ),
+        };
+
+        let scaling = camera.resolution_scaling * settings.resolution_scaling;
+
+        let extent = vk::Extent2D {
+            width: ((viewport.extent.width as f32 * scaling) as u32).max(1),
+            height: ((viewport.extent.height as f32 * scaling) as u32).max(1),
+        };
+
+        let is_outdated = views.get(&entity).is_none_or(|view| {
+            view.extent() != extent || view.output_image.format != output_format
+        });
+
+        if is_outdated {
+            let view = View::new(render_device.clone(), extent, output_format)?;
+
+            if let Some(old_view) = views.insert(entity, view) {
+                old_view.untrack(&mut tracker);
+                retired.push(old_view);
+            }
+        }
+
+        if let Some(view) = views.get_mut(&entity) {
+            view.target = camera.target;
+            view.viewport = viewport;
+            view.priority = camera.priority;
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a normalized viewport to pixels of a target with the given extent.
+fn viewport_rect(viewport: Rect, extent: vk::Extent2D) -> vk::Rect2D {
+    let size = Vec2::new(extent.width as f32, extent.height as f32);
+    let min = (viewport.min.clamp(Vec2::ZERO, Vec2::ONE) * size).round();
+    let max = (viewport.max.clamp(Vec2::ZERO, Vec2::ONE) * size).round();
+
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: min.x as i32,
+            y: min.y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (max.x - min.x) as u32,
+            height: (max.y - min.y) as u32,
+        },
+    }
+}
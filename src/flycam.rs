@@ -1,38 +1,223 @@
 // Based on bevy_flycam
 
 use bevy::{
-    input::mouse::MouseMotion,
+    input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit},
     prelude::*,
     window::{CursorGrabMode, CursorOptions, PrimaryWindow},
 };
-
-// TODO: Make configurable
-const MOVE_SENSITIVITY: f32 = 10.0;
-const LOOK_SENSITIVITY: f32 = 0.0001;
-
-const GAMEPAD_JOYSTICK_DEADZONE: f32 = 0.1;
-const GAMEPAD_TRIGGER_DEADZONE: f32 = 0.01;
-const GAMEPAD_MOVE_SENSITIVITY: f32 = 5.0;
-const GAMEPAD_LOOK_SENSITIVITY: f32 = 100.0;
+use luma_render::egui_renderer::{egui_wants_keyboard_input, egui_wants_pointer_input};
 
 pub struct FlycamPlugin;
 
 impl Plugin for FlycamPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (update_transform, update_transform_gamepad))
-            .add_systems(Update, update_cursor_grab);
+        // Keys typed into the UI must not move the camera or release the cursor, and dragging
+        // windows or gizmo handles must not rotate it
+        app.init_resource::<FlycamSettings>()
+            .add_systems(
+                Update,
+                (
+                    update_transform.run_if(not(egui_wants_keyboard_input)),
+                    update_look.run_if(not(egui_wants_pointer_input)),
+                    update_transform_gamepad,
+                    update_orbit.run_if(not(egui_wants_pointer_input)),
+                ),
+            )
+            .add_systems(
+                Update,
+                (update_cursor_grab, toggle_mode).run_if(not(egui_wants_keyboard_input)),
+            );
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct FlycamSettings {
+    pub bindings: FlycamBindings,
+    /// Movement speed in metres per second
+    pub move_speed: f32,
+    /// Rotation in degrees per pixel of mouse motion, relative to the smaller window dimension
+    pub look_sensitivity: f32,
+    /// Rate at which the velocity approaches the move speed while moving
+    pub acceleration: f32,
+    /// Rate at which the velocity decays once no movement key is held
+    pub damping: f32,
+    /// Factor the move speed is multiplied with per line scrolled
+    pub scroll_speed_factor: f32,
+    pub invert_y: bool,
+    /// Rotation in radians per pixel of mouse motion while orbiting
+    pub orbit_sensitivity: f32,
+    /// Translation per pixel of mouse motion while panning, relative to the orbit distance
+    pub pan_sensitivity: f32,
+    /// Factor the orbit distance is multiplied with per line scrolled
+    pub dolly_factor: f32,
+    /// Distance in pixels the mouse has to move with a button held before orbiting or panning
+    /// starts, so that clicks still select entities
+    pub drag_threshold: f32,
+    pub gamepad: GamepadSettings,
+}
+
+impl Default for FlycamSettings {
+    fn default() -> Self {
+        Self {
+            bindings: default(),
+            move_speed: 10.0,
+            look_sensitivity: 0.0001,
+            acceleration: 20.0,
+            damping: 10.0,
+            scroll_speed_factor: 1.2,
+            invert_y: false,
+            orbit_sensitivity: 0.005,
+            pan_sensitivity: 0.001,
+            dolly_factor: 0.9,
+            drag_threshold: 6.0,
+            gamepad: default(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FlycamBindings {
+    pub forward: KeyCode,
+    pub back: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub up: KeyCode,
+    pub down: KeyCode,
+    /// Grabs or releases the cursor in flycam mode.
+    pub toggle_grab: KeyCode,
+    /// Switches between flycam and orbit mode.
+    pub toggle_mode: KeyCode,
+    pub orbit: MouseButton,
+    pub pan: MouseButton,
+}
+
+impl Default for FlycamBindings {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::KeyW,
+            back: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            up: KeyCode::Space,
+            down: KeyCode::ShiftLeft,
+            toggle_grab: KeyCode::Escape,
+            toggle_mode: KeyCode::Tab,
+            orbit: MouseButton::Left,
+            pan: MouseButton::Middle,
+        }
     }
 }
 
+#[derive(Clone)]
+pub struct GamepadSettings {
+    pub joystick_deadzone: f32,
+    pub trigger_deadzone: f32,
+    /// Movement speed in metres per second at full deflection
+    pub move_speed: f32,
+    /// Rotation in degrees per second at full deflection
+    pub look_speed: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            joystick_deadzone: 0.1,
+            trigger_deadzone: 0.01,
+            move_speed: 5.0,
+            look_speed: 100.0,
+        }
+    }
+}
+
+#[derive(Component, Default)]
+pub struct Flycam {
+    pub velocity: Vec3,
+}
+
+/// Orbits, pans and dollies around a focus point, for inspecting single assets.
 #[derive(Component)]
-pub struct Flycam;
+pub struct OrbitCam {
+    pub focus: Vec3,
+    pub distance: f32,
+}
+
+impl OrbitCam {
+    /// Orbit around the point `distance` in front of `transform`, keeping the view unchanged.
+    pub fn in_front_of(transform: &Transform, distance: f32) -> Self {
+        Self {
+            focus: transform.translation + transform.forward() * distance,
+            distance,
+        }
+    }
+}
 
 fn update_transform(
-    window: Query<&Window, With<PrimaryWindow>>,
     cursor_options: Query<&CursorOptions, With<PrimaryWindow>>,
     keys: Res<ButtonInput<KeyCode>>,
+    scroll: Res<AccumulatedMouseScroll>,
     time: Res<Time>,
-    mut messages: MessageReader<MouseMotion>,
+    mut settings: ResMut<FlycamSettings>,
+    mut query: Query<(&mut Flycam, &mut Transform)>,
+) {
+    let Ok(cursor_options) = cursor_options.single() else {
+        return;
+    };
+
+    let Ok((mut flycam, mut transform)) = query.single_mut() else {
+        return;
+    };
+
+    let is_grabbed = cursor_options.grab_mode == CursorGrabMode::Confined;
+    let mut direction = Vec3::ZERO;
+
+    if is_grabbed {
+        let bindings = &settings.bindings;
+        let forward = transform.forward().as_vec3();
+        let right = transform.right().as_vec3();
+
+        for (key, key_direction) in [
+            (bindings.forward, forward),
+            (bindings.back, -forward),
+            (bindings.left, -right),
+            (bindings.right, right),
+            (bindings.up, Vec3::Y),
+            (bindings.down, Vec3::NEG_Y),
+        ] {
+            if keys.pressed(key) {
+                direction += key_direction;
+            }
+        }
+
+        let lines = scroll_lines(&scroll);
+
+        if lines != 0.0 {
+            settings.move_speed *= settings.scroll_speed_factor.powf(lines);
+        }
+    }
+
+    let dt = time.delta_secs();
+
+    flycam.velocity = if direction != Vec3::ZERO {
+        let target_velocity = direction.normalize() * settings.move_speed;
+        let t = 1.0 - (-settings.acceleration * dt).exp();
+        flycam.velocity.lerp(target_velocity, t)
+    } else {
+        flycam.velocity * (-settings.damping * dt).exp()
+    };
+
+    // Come to a full stop eventually, so the transform stops changing while the camera is at rest
+    if flycam.velocity.length_squared() < 1e-6 {
+        flycam.velocity = Vec3::ZERO;
+    } else {
+        transform.translation += flycam.velocity * dt;
+    }
+}
+
+fn update_look(
+    window: Query<&Window, With<PrimaryWindow>>,
+    cursor_options: Query<&CursorOptions, With<PrimaryWindow>>,
+    motion: Res<AccumulatedMouseMotion>,
+    settings: Res<FlycamSettings>,
     mut query: Query<&mut Transform, With<Flycam>>,
 ) {
     let Ok(window) = window.single() else {
@@ -47,60 +232,39 @@ fn update_transform(
         return;
     };
 
-    if cursor_options.grab_mode != CursorGrabMode::Confined {
+    if cursor_options.grab_mode != CursorGrabMode::Confined || motion.delta == Vec2::ZERO {
         return;
     }
 
-    let mut velocity = Vec3::ZERO;
-    let forward = -transform.local_z().as_vec3();
-    let right = transform.local_x().as_vec3();
-
-    for key in keys.get_pressed() {
-        match key {
-            KeyCode::KeyW => {
-                velocity += forward;
-            }
-            KeyCode::KeyS => {
-                velocity -= forward;
-            }
-            KeyCode::KeyA => {
-                velocity -= right;
-            }
-            KeyCode::KeyD => {
-                velocity += right;
-            }
-            KeyCode::Space => {
-                velocity += Vec3::Y;
-            }
-            KeyCode::ShiftLeft => {
-                velocity -= Vec3::Y;
-            }
-            _ => {}
-        }
-    }
-
-    velocity = velocity.normalize_or_zero();
-    transform.translation += velocity * time.delta_secs() * MOVE_SENSITIVITY;
-
-    for message in messages.read() {
-        let (mut yaw, mut pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
-        let window_scale = window.height().min(window.width());
-        pitch -= (LOOK_SENSITIVITY * message.delta.y * window_scale).to_radians();
-        yaw -= (LOOK_SENSITIVITY * message.delta.x * window_scale).to_radians();
-        pitch = pitch.clamp(-1.54, 1.54);
-        transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
-    }
+    let y_sign = if settings.invert_y { -1.0 } else { 1.0 };
+    let (mut yaw, mut pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+    let window_scale = window.height().min(window.width());
+    let delta_y = motion.delta.y * y_sign;
+    pitch -= (settings.look_sensitivity * delta_y * window_scale).to_radians();
+    yaw -= (settings.look_sensitivity * motion.delta.x * window_scale).to_radians();
+    pitch = pitch.clamp(-1.54, 1.54);
+    transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
 }
 
 fn update_transform_gamepad(
     gamepads: Query<&Gamepad>,
     time: Res<Time>,
+    settings: Res<FlycamSettings>,
     mut query: Query<&mut Transform, With<Flycam>>,
 ) {
     let Ok(mut transform) = query.single_mut() else {
         return;
     };
 
+    let GamepadSettings {
+        joystick_deadzone,
+        trigger_deadzone,
+        move_speed,
+        look_speed,
+    } = settings.gamepad;
+
+    let y_sign = if settings.invert_y { -1.0 } else { 1.0 };
+
     for gamepad in gamepads.iter() {
         let mut velocity = Vec3::ZERO;
         let forward = -transform.local_z().as_vec3();
@@ -110,11 +274,11 @@ fn update_transform_gamepad(
             gamepad.get(GamepadAxis::LeftStickX),
             gamepad.get(GamepadAxis::LeftStickY),
         ) {
-            if x.abs() > GAMEPAD_JOYSTICK_DEADZONE {
+            if x.abs() > joystick_deadzone {
                 velocity += right * x;
             }
 
-            if y.abs() > GAMEPAD_JOYSTICK_DEADZONE {
+            if y.abs() > joystick_deadzone {
                 velocity += forward * y;
             }
         }
@@ -123,46 +287,146 @@ fn update_transform_gamepad(
             gamepad.get(GamepadButton::RightTrigger2),
             gamepad.get(GamepadButton::LeftTrigger2),
         ) {
-            if right.abs() > GAMEPAD_TRIGGER_DEADZONE {
+            if right.abs() > trigger_deadzone {
                 velocity += Vec3::Y * right;
             }
 
-            if left.abs() > GAMEPAD_TRIGGER_DEADZONE {
+            if left.abs() > trigger_deadzone {
                 velocity -= Vec3::Y * left;
             }
         }
 
-        transform.translation += velocity * time.delta_secs() * GAMEPAD_MOVE_SENSITIVITY;
+        if velocity != Vec3::ZERO {
+            transform.translation += velocity * time.delta_secs() * move_speed;
+        }
 
-        let (mut yaw, mut pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+        let mut look = Vec2::ZERO;
 
         if let (Some(x), Some(y)) = (
             gamepad.get(GamepadAxis::RightStickX),
             gamepad.get(GamepadAxis::RightStickY),
         ) {
-            if x.abs() > GAMEPAD_JOYSTICK_DEADZONE {
-                yaw -= (GAMEPAD_LOOK_SENSITIVITY * x * time.delta_secs()).to_radians();
+            if x.abs() > joystick_deadzone {
+                look.x = x;
             }
 
-            if y.abs() > GAMEPAD_JOYSTICK_DEADZONE {
-                pitch += (GAMEPAD_LOOK_SENSITIVITY * y * time.delta_secs()).to_radians();
+            if y.abs() > joystick_deadzone {
+                look.y = y;
             }
         }
 
+        if look != Vec2::ZERO {
+            let (mut yaw, mut pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+            yaw -= (look_speed * look.x * time.delta_secs()).to_radians();
+            pitch += (look_speed * look.y * y_sign * time.delta_secs()).to_radians();
+            pitch = pitch.clamp(-1.54, 1.54);
+            transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
+        }
+    }
+}
+
+fn update_orbit(
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    motion: Res<AccumulatedMouseMotion>,
+    scroll: Res<AccumulatedMouseScroll>,
+    settings: Res<FlycamSettings>,
+    mut drag_distance: Local<Option<f32>>,
+    mut query: Query<(&mut OrbitCam, &mut Transform)>,
+) {
+    let Ok((mut orbit_cam, mut transform)) = query.single_mut() else {
+        return;
+    };
+
+    let is_pressed = mouse_buttons.pressed(settings.bindings.orbit)
+        || mouse_buttons.pressed(settings.bindings.pan);
+
+    // Motion below the threshold is ignored, since it may belong to a click
+    *drag_distance = is_pressed.then(|| drag_distance.unwrap_or(0.0) + motion.delta.length());
+
+    let is_dragging = drag_distance.is_some_and(|distance| distance > settings.drag_threshold);
+    let is_moving = is_dragging && motion.delta != Vec2::ZERO;
+
+    let lines = scroll_lines(&scroll);
+
+    // Leave the transform untouched without input, so it only changes while the camera moves
+    if !is_moving && lines == 0.0 && !orbit_cam.is_added() {
+        return;
+    }
+
+    let (mut yaw, mut pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+    let y_sign = if settings.invert_y { -1.0 } else { 1.0 };
+
+    if is_dragging && mouse_buttons.pressed(settings.bindings.orbit) {
+        yaw -= motion.delta.x * settings.orbit_sensitivity;
+        pitch -= motion.delta.y * y_sign * settings.orbit_sensitivity;
         pitch = pitch.clamp(-1.54, 1.54);
-        transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
+    }
+
+    let rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.0);
+
+    if is_dragging && mouse_buttons.pressed(settings.bindings.pan) {
+        let scale = orbit_cam.distance * settings.pan_sensitivity;
+        let pan = rotation * Vec3::new(-motion.delta.x, motion.delta.y, 0.0) * scale;
+        orbit_cam.focus += pan;
+    }
+
+    if lines != 0.0 {
+        orbit_cam.distance = (orbit_cam.distance * settings.dolly_factor.powf(lines)).max(0.01);
+    }
+
+    transform.rotation = rotation;
+    transform.translation = orbit_cam.focus + rotation * Vec3::Z * orbit_cam.distance;
+}
+
+/// Switches the camera between flycam and orbit mode, orbiting around the point in front of it.
+fn toggle_mode(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<FlycamSettings>,
+    mut cursor_options: Query<&mut CursorOptions, With<PrimaryWindow>>,
+    flycams: Query<(Entity, &Transform), With<Flycam>>,
+    orbit_cams: Query<Entity, With<OrbitCam>>,
+) {
+    if !keys.just_pressed(settings.bindings.toggle_mode) {
+        return;
+    }
+
+    for (entity, transform) in &flycams {
+        commands
+            .entity(entity)
+            .remove::<Flycam>()
+            .insert(OrbitCam::in_front_of(transform, 10.0));
+
+        // Orbit controls need a visible cursor
+        if let Ok(mut cursor_options) = cursor_options.single_mut() {
+            cursor_options.grab_mode = CursorGrabMode::None;
+            cursor_options.visible = true;
+        }
+    }
+
+    for entity in &orbit_cams {
+        commands
+            .entity(entity)
+            .remove::<OrbitCam>()
+            .insert(Flycam::default());
     }
 }
 
 fn update_cursor_grab(
     mut cursor_options: Query<&mut CursorOptions, With<PrimaryWindow>>,
     keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<FlycamSettings>,
+    flycams: Query<(), With<Flycam>>,
 ) {
     let Ok(mut cursor_options) = cursor_options.single_mut() else {
         return;
     };
 
-    if keys.just_pressed(KeyCode::Escape) {
+    if flycams.is_empty() {
+        return;
+    }
+
+    if keys.just_pressed(settings.bindings.toggle_grab) {
         match cursor_options.grab_mode {
             CursorGrabMode::Confined => {
                 cursor_options.grab_mode = CursorGrabMode::None;
@@ -176,3 +440,10 @@ fn update_cursor_grab(
         }
     }
 }
+
+fn scroll_lines(scroll: &AccumulatedMouseScroll) -> f32 {
+    match scroll.unit {
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / 100.0,
+    }
+}
@@ -0,0 +1,512 @@
+use bevy::{ecs::system::SystemParam, math::Affine3A, prelude::*};
+use luma_render::{
+    camera::Camera,
+    egui_renderer::{EguiContext, EguiPass, EguiPassSystems},
+};
+
+use crate::selection::Selection;
+
+pub struct GizmoPlugin;
+
+impl Plugin for GizmoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GizmoSettings>().add_systems(
+            EguiPass,
+            render_gizmo_settings.in_set(EguiPassSystems::Render),
+        );
+    }
+}
+
+/// Size of the gizmo handles on screen in points.
+const GIZMO_SIZE: f32 = 80.0;
+
+/// Distance in points within which the pointer grabs a handle.
+const GRAB_DISTANCE: f32 = 8.0;
+
+const AXIS_COLORS: [egui::Color32; 3] = [
+    egui::Color32::from_rgb(220, 60, 60),
+    egui::Color32::from_rgb(60, 200, 60),
+    egui::Color32::from_rgb(60, 110, 230),
+];
+
+const ACTIVE_COLOR: egui::Color32 = egui::Color32::from_rgb(250, 210, 60);
+
+#[derive(Resource)]
+pub struct GizmoSettings {
+    pub mode: GizmoMode,
+    /// Space of the translation and rotation axes. Scaling always happens along local axes.
+    pub space: GizmoSpace,
+    /// Rounds edits to the steps below, holding Ctrl inverts this while dragging.
+    pub snap: bool,
+    pub translation_step: f32,
+    /// Rotation step in degrees
+    pub rotation_step: f32,
+    pub scale_step: f32,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> Self {
+        Self {
+            mode: default(),
+            space: default(),
+            snap: false,
+            translation_step: 0.5,
+            rotation_step: 15.0,
+            scale_step: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [Self; 3] = [Self::Translate, Self::Rotate, Self::Scale];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Translate => "Translate",
+            Self::Rotate => "Rotate",
+            Self::Scale => "Scale",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum GizmoSpace {
+    #[default]
+    World,
+    Local,
+}
+
+impl GizmoSpace {
+    pub const ALL: [Self; 2] = [Self::World, Self::Local];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::World => "World",
+            Self::Local => "Local",
+        }
+    }
+}
+
+/// State captured when a handle is grabbed, which edits are applied relative to.
+struct GizmoDrag {
+    axis: usize,
+    direction: Vec3,
+    origin: Vec3,
+    /// Basis of the plane perpendicular to `direction`, for measuring rotation angles.
+    basis: (Vec3, Vec3),
+    /// Distance along the axis or angle around it at the grab position.
+    start_value: f32,
+    start_world: Transform,
+    start_local: Transform,
+    /// Transform from the parent's space to world space.
+    parent: Affine3A,
+}
+
+/// Draws the translate, rotate and scale handles of the selected entity over the viewport, and
+/// edits its `Transform` while a handle is dragged.
+#[derive(SystemParam)]
+pub struct Gizmo<'w, 's> {
+    settings: Res<'w, GizmoSettings>,
+    selection: Res<'w, Selection>,
+    transforms: Query<'w, 's, (&'static mut Transform, &'static GlobalTransform)>,
+    drag: Local<'s, Option<GizmoDrag>>,
+}
+
+impl Gizmo<'_, '_> {
+    /// Shows the gizmo over `rect`, which displays the image of `camera`.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        rect: egui::Rect,
+        camera_entity: Entity,
+        camera: &Camera,
+    ) {
+        let Some(entity) = self
+            .selection
+            .entity
+            .filter(|&entity| entity != camera_entity)
+        else {
+            *self.drag = None;
+            return;
+        };
+
+        let Ok([(camera_transform, _), (mut transform, global_transform)]) =
+            self.transforms.get_many_mut([camera_entity, entity])
+        else {
+            *self.drag = None;
+            return;
+        };
+
+        let projector = Projector {
+            rect,
+            camera,
+            transform: *camera_transform,
+        };
+
+        let world = global_transform.compute_transform();
+        let origin = world.translation;
+        let mode = self.settings.mode;
+
+        let Some(length) = projector.points_to_world(origin) else {
+            return;
+        };
+
+        let length = length * GIZMO_SIZE;
+
+        let directions =
+            [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| match (mode, self.settings.space) {
+                (GizmoMode::Scale, _) | (_, GizmoSpace::Local) => world.rotation * axis,
+                (_, GizmoSpace::World) => axis,
+            });
+
+        let handles = directions.map(|direction| {
+            let points = match mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    vec![origin, origin + direction * length]
+                }
+                GizmoMode::Rotate => {
+                    let (u, v) = plane_basis(direction);
+
+                    (0..=48)
+                        .map(|i| {
+                            let angle = i as f32 / 48.0 * std::f32::consts::TAU;
+                            origin + (u * angle.cos() + v * angle.sin()) * length
+                        })
+                        .collect()
+                }
+            };
+
+            points
+                .into_iter()
+                .map(|point| projector.to_screen(point))
+                .collect::<Option<Vec<_>>>()
+        });
+
+        let handle_at = |pointer: egui::Pos2| {
+            handles
+                .iter()
+                .enumerate()
+                .filter_map(|(axis, handle)| {
+                    let distance = polyline_distance(handle.as_deref()?, pointer);
+                    (distance < GRAB_DISTANCE).then_some((axis, distance))
+                })
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(axis, _)| axis)
+        };
+
+        let hovered = ui.ctx().pointer_hover_pos().and_then(handle_at);
+
+        // The pointer can leave the handle before it moved far enough to count as a drag
+        let (pressed, press_origin) =
+            ui.input(|input| (input.pointer.primary_down(), input.pointer.press_origin()));
+        let grabbed = press_origin.filter(|_| pressed).and_then(handle_at);
+
+        let active = self
+            .drag
+            .as_ref()
+            .map(|drag| drag.axis)
+            .or(grabbed)
+            .or(hovered);
+
+        // The gizmo only takes the pointer over its handles, so clicks elsewhere still pick
+        if active.is_some() {
+            let response = ui.interact(rect, ui.id().with("Gizmo"), egui::Sense::click_and_drag());
+
+            if response.drag_started()
+                && let Some(axis) = grabbed
+            {
+                let direction = directions[axis];
+                let basis = plane_basis(direction);
+
+                let start_value = press_origin
+                    .and_then(|position| projector.ray(position))
+                    .and_then(|ray| axis_value(mode, origin, direction, basis, ray));
+
+                if let Some(start_value) = start_value {
+                    *self.drag = Some(GizmoDrag {
+                        axis,
+                        direction,
+                        origin,
+                        basis,
+                        start_value,
+                        start_world: world,
+                        start_local: *transform,
+                        parent: global_transform.affine() * transform.compute_affine().inverse(),
+                    });
+                }
+            }
+
+            if let Some(drag) = self.drag.as_ref()
+                && response.dragged()
+            {
+                let snap = self.settings.snap != ui.input(|input| input.modifiers.ctrl);
+
+                let edited = response
+                    .interact_pointer_pos()
+                    .and_then(|position| projector.ray(position))
+                    .and_then(|ray| axis_value(mode, drag.origin, drag.direction, drag.basis, ray))
+                    .map(|value| apply(&self.settings, drag, value, snap));
+
+                if let Some(edited) = edited {
+                    transform.set_if_neq(edited);
+                }
+            }
+
+            if response.drag_stopped() {
+                *self.drag = None;
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        let active = self
+            .drag
+            .as_ref()
+            .map(|drag| drag.axis)
+            .or(grabbed)
+            .or(hovered);
+
+        for (axis, handle) in handles.iter().enumerate() {
+            let Some(points) = handle else {
+                continue;
+            };
+
+            let color = if active == Some(axis) {
+                ACTIVE_COLOR
+            } else {
+                AXIS_COLORS[axis]
+            };
+
+            painter.add(egui::Shape::line(
+                points.clone(),
+                egui::Stroke::new(2.5, color),
+            ));
+
+            let end = points[points.len() - 1];
+
+            match mode {
+                GizmoMode::Translate => {
+                    painter.circle_filled(end, 5.0, color);
+                }
+                GizmoMode::Scale => {
+                    let square = egui::Rect::from_center_size(end, egui::Vec2::splat(9.0));
+                    painter.rect_filled(square, 0.0, color);
+                }
+                GizmoMode::Rotate => {}
+            }
+        }
+    }
+}
+
+/// Maps between world space and the viewport rectangle showing a camera's image.
+struct Projector<'a> {
+    rect: egui::Rect,
+    camera: &'a Camera,
+    transform: Transform,
+}
+
+impl Projector<'_> {
+    fn aspect_ratio(&self) -> f32 {
+        self.rect.width() / self.rect.height()
+    }
+
+    fn to_screen(&self, point: Vec3) -> Option<egui::Pos2> {
+        let position =
+            self.camera
+                .world_to_viewport(point, self.aspect_ratio(), &self.transform)?;
+
+        Some(self.rect.min + egui::vec2(position.x, position.y) * self.rect.size())
+    }
+
+    fn ray(&self, position: egui::Pos2) -> Option<Ray3d> {
+        let position = (position - self.rect.min) / self.rect.size();
+
+        self.camera.viewport_to_ray(
+            Vec2::new(position.x, position.y),
+            self.aspect_ratio(),
+            &self.transform,
+        )
+    }
+
+    /// Size of one point on screen in world units at the distance of `point`.
+    fn points_to_world(&self, point: Vec3) -> Option<f32> {
+        let start = self.to_screen(point)?;
+        let right = self.transform.right().as_vec3();
+        let end = self.to_screen(point + right)?;
+        let distance = start.distance(end);
+
+        (distance > f32::EPSILON).then(|| 1.0 / distance)
+    }
+}
+
+/// Local transform after dragging the grabbed handle to `value`.
+fn apply(settings: &GizmoSettings, drag: &GizmoDrag, value: f32, snap: bool) -> Transform {
+    let mut local = drag.start_local;
+
+    match settings.mode {
+        GizmoMode::Translate => {
+            let mut offset = value - drag.start_value;
+
+            if snap {
+                offset = round_to_step(offset, settings.translation_step);
+            }
+
+            let translation = drag.start_world.translation + drag.direction * offset;
+            local.translation = drag.parent.inverse().transform_point3(translation);
+        }
+        GizmoMode::Rotate => {
+            let mut angle = wrap_angle(value - drag.start_value);
+
+            if snap {
+                angle = round_to_step(angle.to_degrees(), settings.rotation_step).to_radians();
+            }
+
+            let rotation = Quat::from_axis_angle(drag.direction, angle) * drag.start_world.rotation;
+            let (_, parent_rotation, _) = drag.parent.to_scale_rotation_translation();
+            local.rotation = (parent_rotation.inverse() * rotation).normalize();
+        }
+        GizmoMode::Scale => {
+            if drag.start_value.abs() < f32::EPSILON {
+                return local;
+            }
+
+            let axis = drag.axis;
+            let mut scale = drag.start_local.scale[axis] * value / drag.start_value;
+
+            if snap {
+                scale = round_to_step(scale, settings.scale_step);
+            }
+
+            // Keep the scale from collapsing to zero, which would leave a degenerate BLAS
+            local.scale[axis] = if scale.abs() < 1e-3 {
+                1e-3_f32.copysign(scale)
+            } else {
+                scale
+            };
+        }
+    }
+
+    local
+}
+
+/// Distance along the axis for translation and scaling, or angle around it for rotation, of the
+/// point on the axis or in its plane that `ray` points at.
+fn axis_value(
+    mode: GizmoMode,
+    origin: Vec3,
+    direction: Vec3,
+    (u, v): (Vec3, Vec3),
+    ray: Ray3d,
+) -> Option<f32> {
+    match mode {
+        GizmoMode::Translate | GizmoMode::Scale => {
+            // Closest point on the axis line to the ray
+            let w = origin - ray.origin;
+            let b = direction.dot(*ray.direction);
+            let denominator = 1.0 - b * b;
+
+            // The axis is useless when it points straight at the camera
+            if denominator < 1e-4 {
+                return None;
+            }
+
+            Some((b * ray.direction.dot(w) - direction.dot(w)) / denominator)
+        }
+        GizmoMode::Rotate => {
+            let plane = InfinitePlane3d::new(direction);
+            let distance = ray.intersect_plane(origin, plane)?;
+            let offset = ray.get_point(distance) - origin;
+            Some(offset.dot(v).atan2(offset.dot(u)))
+        }
+    }
+}
+
+fn plane_basis(direction: Vec3) -> (Vec3, Vec3) {
+    let u = direction.any_orthonormal_vector();
+    (u, direction.cross(u))
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    use std::f32::consts::{PI, TAU};
+
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+fn round_to_step(value: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+fn polyline_distance(points: &[egui::Pos2], pointer: egui::Pos2) -> f32 {
+    points
+        .windows(2)
+        .map(|segment| {
+            let (a, b) = (segment[0], segment[1]);
+            let ab = b - a;
+            let t = ((pointer - a).dot(ab) / ab.length_sq().max(f32::EPSILON)).clamp(0.0, 1.0);
+            pointer.distance(a + ab * t)
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn render_gizmo_settings(ctx: Res<EguiContext>, mut settings: ResMut<GizmoSettings>) {
+    egui::Window::new("Gizmo")
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .show(&ctx, |ui| {
+            ui.horizontal(|ui| {
+                for mode in GizmoMode::ALL {
+                    ui.selectable_value(&mut settings.mode, mode, mode.name());
+                }
+            });
+
+            ui.horizontal(|ui| {
+                for space in GizmoSpace::ALL {
+                    ui.selectable_value(&mut settings.space, space, space.name());
+                }
+            });
+
+            ui.checkbox(&mut settings.snap, "Snap");
+
+            ui.add_enabled_ui(settings.snap, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Translation:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.translation_step)
+                            .speed(0.01)
+                            .range(0.0..=100.0),
+                    );
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Rotation:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.rotation_step)
+                            .speed(1.0)
+                            .range(0.0..=180.0)
+                            .suffix("°"),
+                    );
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Scale:");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.scale_step)
+                            .speed(0.01)
+                            .range(0.0..=10.0),
+                    );
+                });
+            });
+        });
+}
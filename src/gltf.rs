@@ -1,9 +1,13 @@
-use anyhow::{Error, Result};
+use anyhow::{Error, Result, anyhow};
 use bevy::{
     asset::{AssetLoader, LoadContext, RenderAssetUsages, io::Reader},
-    mesh::{Indices, PrimitiveTopology},
+    mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
     prelude::*,
 };
+use luma_render::{
+    material::{AlphaMode, Material},
+    texture::Texture,
+};
 
 pub struct GltfPlugin;
 
@@ -13,10 +17,36 @@ impl Plugin for GltfPlugin {
     }
 }
 
-#[expect(unused)]
 #[derive(Asset, TypePath)]
 pub struct Gltf {
-    pub meshes: Vec<Handle<Mesh>>,
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<Material>,
+    pub nodes: Vec<GltfNode>,
+    /// Root nodes of the default scene, or of the first scene if there is no default.
+    pub roots: Vec<usize>,
+}
+
+pub struct GltfMesh {
+    /// Triangle primitives of the mesh, other primitive modes are skipped.
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+pub struct GltfPrimitive {
+    pub mesh: Handle<Mesh>,
+    /// Index into `Gltf::materials`, or `None` for the default material.
+    pub material: Option<usize>,
+    /// Local bounds of the primitive's vertex positions.
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub transform: Transform,
+    /// Index into `Gltf::meshes`.
+    pub mesh: Option<usize>,
+    /// Indices into `Gltf::nodes`.
+    pub children: Vec<usize>,
 }
 
 #[derive(Default, TypePath)]
@@ -38,13 +68,38 @@ impl AssetLoader for GltfLoader {
 
         let gltf = gltf::Gltf::from_slice(&bytes)?;
         let blob = gltf.blob.as_deref();
+
+        // External buffers are resolved relative to the glTF file, embedded ones are decoded
+        let mut buffers = Vec::new();
+
+        for buffer in gltf.document.buffers() {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => None,
+                gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                    Some(decode_data_uri(uri)?)
+                }
+                gltf::buffer::Source::Uri(uri) => {
+                    let path = load_context.asset_path().resolve_embed(uri)?;
+                    Some(load_context.read_asset_bytes(path).await?)
+                }
+            };
+
+            buffers.push(data);
+        }
+
         let mut meshes = Vec::new();
 
         for (mesh_index, gltf_mesh) in gltf.document.meshes().enumerate() {
+            let mut primitives = Vec::new();
+
             for (primitive_index, primitive) in gltf_mesh.primitives().enumerate() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+
                 let reader = primitive.reader(|buffer| match buffer.source() {
                     gltf::buffer::Source::Bin => blob,
-                    gltf::buffer::Source::Uri(_) => None,
+                    gltf::buffer::Source::Uri(_) => buffers[buffer.index()].as_deref(),
                 });
 
                 let mut mesh = Mesh::new(
@@ -52,31 +107,190 @@ impl AssetLoader for GltfLoader {
                     RenderAssetUsages::default(),
                 );
 
-                if let Some(positions) = reader.read_positions() {
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.collect::<Vec<_>>());
-                }
+                let Some(positions) = reader.read_positions() else {
+                    continue;
+                };
 
-                if let Some(normals) = reader.read_normals() {
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals.collect::<Vec<_>>());
-                }
+                let positions = positions.collect::<Vec<_>>();
+                let vertex_count = positions.len();
+                mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
 
-                if let Some(uvs) = reader.read_tex_coords(0) {
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs.into_f32().collect::<Vec<_>>());
+                // The renderer requires indices, normals and UVs, so missing ones are filled in
+                match reader.read_indices() {
+                    Some(indices) => {
+                        mesh.insert_indices(Indices::U32(indices.into_u32().collect()))
+                    }
+                    None => mesh.insert_indices(Indices::U32((0..vertex_count as u32).collect())),
                 }
 
-                if let Some(indices) = reader.read_indices() {
-                    mesh.insert_indices(Indices::U32(indices.into_u32().collect()));
+                match reader.read_normals() {
+                    Some(normals) => {
+                        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals.collect::<Vec<_>>())
+                    }
+                    None => mesh.compute_normals(),
                 }
 
+                let uvs = match reader.read_tex_coords(0) {
+                    Some(uvs) => uvs.into_f32().collect::<Vec<_>>(),
+                    None => vec![[0.0; 2]; vertex_count],
+                };
+
+                mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(uvs));
+
+                let bounds = primitive.bounding_box();
                 let label = format!("Mesh{mesh_index}/Primitive{primitive_index}");
-                meshes.push(load_context.add_labeled_asset(label, mesh));
+
+                primitives.push(GltfPrimitive {
+                    mesh: load_context.add_labeled_asset(label, mesh),
+                    material: primitive.material().index(),
+                    min: Vec3::from(bounds.min),
+                    max: Vec3::from(bounds.max),
+                });
+            }
+
+            meshes.push(GltfMesh { primitives });
+        }
+
+        // Only base color textures are rendered, so other images are not decoded
+        let mut textures = Vec::new();
+
+        for texture in gltf.document.textures() {
+            let is_base_color = gltf.document.materials().any(|material| {
+                material
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                    .is_some_and(|info| info.texture().index() == texture.index())
+            });
+
+            if !is_base_color {
+                textures.push(None);
+                continue;
+            }
+
+            // A texture that cannot be loaded leaves its materials untextured
+            let image = texture.source();
+            let result = read_image(&image, blob, &buffers, load_context)
+                .await
+                .and_then(|bytes| Texture::decode(&bytes));
+
+            match result {
+                Ok(decoded) => {
+                    let label = format!("Texture{}", texture.index());
+                    textures.push(Some(load_context.add_labeled_asset(label, decoded)));
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to load image {} of {}: {err}",
+                        image.index(),
+                        load_context.asset_path()
+                    );
+                    textures.push(None);
+                }
             }
         }
 
-        Ok(Gltf { meshes })
+        let materials = gltf
+            .document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                let [red, green, blue, alpha] = pbr.base_color_factor();
+
+                // Textures are sampled with the first UV set, which is the only one loaded
+                let base_color_texture = pbr
+                    .base_color_texture()
+                    .and_then(|info| textures[info.texture().index()].clone());
+
+                let alpha_mode = match material.alpha_mode() {
+                    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    gltf::material::AlphaMode::Mask => AlphaMode::Mask {
+                        cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                    },
+                    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                };
+
+                Material {
+                    base_color: Color::linear_rgba(red, green, blue, alpha),
+                    base_color_texture,
+                    alpha_mode,
+                    ..default()
+                }
+            })
+            .collect();
+
+        let nodes = gltf
+            .document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+
+                GltfNode {
+                    name: node.name().map(str::to_owned),
+                    transform: Transform {
+                        translation: Vec3::from(translation),
+                        rotation: Quat::from_array(rotation),
+                        scale: Vec3::from(scale),
+                    },
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    children: node.children().map(|child| child.index()).collect(),
+                }
+            })
+            .collect();
+
+        let roots = gltf
+            .document
+            .default_scene()
+            .or_else(|| gltf.document.scenes().next())
+            .map(|scene| scene.nodes().map(|node| node.index()).collect())
+            .unwrap_or_default();
+
+        Ok(Gltf {
+            meshes,
+            materials,
+            nodes,
+            roots,
+        })
     }
 
     fn extensions(&self) -> &[&str] {
         &["gltf", "glb"]
     }
 }
+
+/// Reads the encoded bytes of an image, which is either embedded in a buffer view or a data URI, or
+/// a file relative to the glTF file.
+async fn read_image(
+    image: &gltf::Image<'_>,
+    blob: Option<&[u8]>,
+    buffers: &[Option<Vec<u8>>],
+    load_context: &mut LoadContext<'_>,
+) -> Result<Vec<u8>> {
+    match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = match view.buffer().source() {
+                gltf::buffer::Source::Bin => blob,
+                gltf::buffer::Source::Uri(_) => buffers[view.buffer().index()].as_deref(),
+            };
+
+            buffer
+                .and_then(|buffer| buffer.get(view.offset()..view.offset() + view.length()))
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| anyhow!("Buffer view {} is out of bounds", view.index()))
+        }
+        gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => decode_data_uri(uri),
+        gltf::image::Source::Uri { uri, .. } => {
+            let path = load_context.asset_path().resolve_embed(uri)?;
+            Ok(load_context.read_asset_bytes(path).await?)
+        }
+    }
+}
+
+/// Decodes the base64 payload of a `data:` URI, the way `gltf::import` does for embedded buffers.
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>> {
+    let encoded = match uri.split_once(";base64,") {
+        Some((_, encoded)) => encoded,
+        None => uri.trim_start_matches("data:"),
+    };
+
+    base64::decode(encoded).map_err(|err| anyhow!("Invalid base64 data URI: {err}"))
+}
@@ -0,0 +1,516 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use egui::collapsing_header::CollapsingState;
+use luma_render::{
+    camera::{Camera, FisheyeModel, Projection, SensorFit},
+    egui_renderer::{EguiContext, EguiPass, EguiPassSystems},
+    light::DirectionalLight,
+    material::{AlphaMode, Material},
+    picking::PickedGeometry,
+    procedural::Procedural3d,
+    ray_tracing::{DebugView, RayTracingSettings, RayTracingShaders},
+};
+
+use crate::selection::Selection;
+
+pub struct InspectorPlugin;
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            EguiPass,
+            (render_outliner, render_inspector, render_settings).in_set(EguiPassSystems::Render),
+        );
+    }
+}
+
+/// Entities listed in the outliner. Entities with children are included so that the meshes of a
+/// hierarchy show up below their parents.
+type Outlined = Or<(
+    With<Mesh3d>,
+    With<Procedural3d>,
+    With<Camera>,
+    With<DirectionalLight>,
+    With<Children>,
+)>;
+
+type OutlinerItem = (
+    Entity,
+    Option<&'static Name>,
+    Has<Camera>,
+    Has<DirectionalLight>,
+    Option<&'static Children>,
+);
+
+fn render_outliner(
+    ctx: Res<EguiContext>,
+    mut selection: ResMut<Selection>,
+    entities: Query<OutlinerItem, Outlined>,
+    parents: Query<&ChildOf>,
+) {
+    egui::Window::new("Outliner")
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .show(&ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    let mut roots = entities
+                        .iter()
+                        .filter(|(entity, ..)| {
+                            parents
+                                .get(*entity)
+                                .ok()
+                                .is_none_or(|child_of| !entities.contains(child_of.parent()))
+                        })
+                        .map(|(entity, ..)| entity)
+                        .collect::<Vec<_>>();
+
+                    roots.sort();
+
+                    for entity in roots {
+                        outliner_node(ui, entity, &entities, &mut selection);
+                    }
+                });
+        });
+}
+
+fn outliner_node(
+    ui: &mut egui::Ui,
+    entity: Entity,
+    entities: &Query<OutlinerItem, Outlined>,
+    selection: &mut Selection,
+) {
+    let Ok((_, name, is_camera, is_light, children)) = entities.get(entity) else {
+        return;
+    };
+
+    let label = match name {
+        Some(name) => name.to_string(),
+        None if is_camera => format!("Camera {entity}"),
+        None if is_light => format!("Light {entity}"),
+        None => format!("Entity {entity}"),
+    };
+
+    let is_selected = selection.entity == Some(entity);
+
+    let children = children
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|&child| entities.contains(child))
+        .collect::<Vec<_>>();
+
+    if children.is_empty() {
+        if ui.selectable_label(is_selected, label).clicked() {
+            selection.select(entity);
+        }
+
+        return;
+    }
+
+    let id = ui.make_persistent_id(entity);
+
+    CollapsingState::load_with_default_open(ui.ctx(), id, false)
+        .show_header(ui, |ui| {
+            if ui.selectable_label(is_selected, label).clicked() {
+                selection.select(entity);
+            }
+        })
+        .body(|ui| {
+            for child in children {
+                outliner_node(ui, child, entities, selection);
+            }
+        });
+}
+
+#[allow(clippy::type_complexity)]
+fn render_inspector(
+    mut commands: Commands,
+    ctx: Res<EguiContext>,
+    selection: Res<Selection>,
+    ray_tracing_shaders: Res<RayTracingShaders>,
+    mut entities: Query<(
+        Option<&Name>,
+        Option<&mut Transform>,
+        Option<&mut Camera>,
+        Option<&mut DirectionalLight>,
+        Option<&mut Material>,
+        Has<Mesh3d>,
+        Has<Procedural3d>,
+    )>,
+) {
+    let Some(entity) = selection.entity else {
+        return;
+    };
+
+    let Ok((name, transform, camera, light, material, has_mesh, has_procedural)) =
+        entities.get_mut(entity)
+    else {
+        return;
+    };
+
+    // Widgets edit copies, which are only written back when they differ, so that untouched
+    // components are not marked as changed and accumulation carries on
+    egui::Window::new("Inspector")
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .show(&ctx, |ui| {
+            match name {
+                Some(name) => ui.heading(name.as_str()),
+                None => ui.heading(format!("Entity {entity}")),
+            };
+
+            if let Some(hit) = selection.hit.filter(|hit| hit.entity == entity) {
+                match hit.geometry {
+                    PickedGeometry::Triangle { triangle, .. } => {
+                        ui.label(format!("Triangle: {triangle}"))
+                    }
+                    PickedGeometry::Procedural { primitive, .. } => {
+                        ui.label(format!("Primitive: {primitive}"))
+                    }
+                };
+                ui.label(format!(
+                    "Position: {:.2} {:.2} {:.2}",
+                    hit.position.x, hit.position.y, hit.position.z
+                ));
+                ui.label(format!("Distance: {:.2} m", hit.distance));
+            }
+
+            if let Some(mut transform) = transform {
+                ui.separator();
+
+                let mut edited = *transform;
+                transform_ui(ui, &mut edited);
+                transform.set_if_neq(edited);
+            }
+
+            if let Some(mut camera) = camera {
+                ui.separator();
+
+                let mut edited = camera.clone();
+                camera_ui(ui, &mut edited);
+                camera.set_if_neq(edited);
+            }
+
+            if let Some(mut light) = light {
+                ui.separator();
+
+                let mut edited = light.clone();
+                light_ui(ui, &mut edited);
+                light.set_if_neq(edited);
+            }
+
+            if has_mesh || has_procedural {
+                ui.separator();
+
+                match material {
+                    Some(mut material) => {
+                        let mut edited = material.clone();
+                        material_ui(ui, &mut edited, &ray_tracing_shaders);
+                        material.set_if_neq(edited);
+                    }
+                    None => {
+                        let mut edited = Material::default();
+
+                        if material_ui(ui, &mut edited, &ray_tracing_shaders) {
+                            commands.entity(entity).insert(edited);
+                        }
+                    }
+                }
+            }
+        });
+}
+
+fn transform_ui(ui: &mut egui::Ui, transform: &mut Transform) {
+    ui.label("Translation:");
+    ui.horizontal(|ui| {
+        for value in transform.translation.as_mut() {
+            ui.add(egui::DragValue::new(value).speed(0.1).fixed_decimals(2));
+        }
+    });
+
+    // Rotation is only rebuilt from the angles when they are edited, since the conversion does
+    // not round trip exactly
+    let (yaw, pitch, roll) = transform.rotation.to_euler(EulerRot::YXZ);
+    let mut angles = [pitch, yaw, roll].map(f32::to_degrees);
+    let mut is_rotated = false;
+
+    ui.label("Rotation:");
+    ui.horizontal(|ui| {
+        for value in &mut angles {
+            is_rotated |= ui
+                .add(
+                    egui::DragValue::new(value)
+                        .speed(1.0)
+                        .fixed_decimals(1)
+                        .suffix("°"),
+                )
+                .changed();
+        }
+    });
+
+    if is_rotated {
+        let [pitch, yaw, roll] = angles.map(f32::to_radians);
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
+    }
+
+    ui.label("Scale:");
+    ui.horizontal(|ui| {
+        for value in transform.scale.as_mut() {
+            ui.add(egui::DragValue::new(value).speed(0.01).fixed_decimals(2));
+        }
+    });
+}
+
+fn camera_ui(ui: &mut egui::Ui, camera: &mut Camera) {
+    egui::ComboBox::from_label("Projection")
+        .selected_text(camera.projection.name())
+        .show_ui(ui, |ui| {
+            for projection in projections() {
+                let selected = camera.projection.name() == projection.name();
+
+                if ui.selectable_label(selected, projection.name()).clicked() {
+                    camera.projection = projection;
+                }
+            }
+        });
+
+    match &mut camera.projection {
+        Projection::Orthographic { height } => {
+            ui.label("Height:");
+            ui.add(
+                egui::Slider::new(height, 1.0..=100.0)
+                    .logarithmic(true)
+                    .custom_formatter(|value, _| format!("{:.1} m", value)),
+            );
+        }
+        Projection::Fisheye { fov, .. } => {
+            ui.label("Field of view:");
+            ui.add(
+                egui::Slider::new(fov, 0.5..=2.0 * PI)
+                    .custom_formatter(|value, _| format!("{:.0}°", value.to_degrees())),
+            );
+        }
+        _ => {}
+    }
+
+    ui.label("Focal length:");
+    ui.add(
+        egui::Slider::new(&mut camera.focal_length, 10.0..=70.0)
+            .step_by(1.0)
+            .custom_formatter(|value, _| format!("{:.0} mm", value)),
+    );
+
+    egui::ComboBox::from_label("Sensor fit")
+        .selected_text(camera.sensor_fit.name())
+        .show_ui(ui, |ui| {
+            for sensor_fit in SensorFit::ALL {
+                ui.selectable_value(&mut camera.sensor_fit, sensor_fit, sensor_fit.name());
+            }
+        });
+
+    ui.label("Letterbox:");
+    ui.add(egui::Slider::new(&mut camera.letterbox, 0.0..=1.0));
+
+    ui.label("Aperture:");
+    ui.add(
+        egui::Slider::new(&mut camera.aperture, 1.4..=22.0)
+            .logarithmic(true)
+            .custom_formatter(|value, _| format!("f/{:.1}", value)),
+    );
+
+    ui.label("Shutter speed:");
+    ui.add(
+        egui::Slider::new(&mut camera.shutter_speed, 1.0 / 4000.0..=1.0)
+            .logarithmic(true)
+            .custom_formatter(|value, _| format!("1/{:.0} s", 1.0 / value)),
+    );
+
+    ui.label("ISO:");
+    ui.add(
+        egui::Slider::new(&mut camera.iso, 50.0..=12800.0)
+            .logarithmic(true)
+            .custom_formatter(|value, _| format!("{:.0}", value)),
+    );
+
+    ui.checkbox(&mut camera.physical_exposure, "Physical exposure");
+    ui.checkbox(&mut camera.depth_of_field, "Depth of field");
+
+    ui.add_enabled_ui(camera.depth_of_field, |ui| {
+        ui.label("Focus distance:");
+        ui.add(
+            egui::Slider::new(&mut camera.focus_distance, 0.1..=100.0)
+                .logarithmic(true)
+                .custom_formatter(|value, _| format!("{:.2} m", value)),
+        );
+
+        ui.label("Aperture blades:");
+        ui.add(egui::Slider::new(&mut camera.bokeh.blades, 0..=12));
+    });
+}
+
+fn light_ui(ui: &mut egui::Ui, light: &mut DirectionalLight) {
+    let srgba = light.color.to_srgba();
+    let mut rgb = [srgba.red, srgba.green, srgba.blue];
+
+    let is_color_changed = ui
+        .horizontal(|ui| {
+            ui.label("Light color:");
+            ui.color_edit_button_rgb(&mut rgb).changed()
+        })
+        .inner;
+
+    // Only replaced when edited, like the base color of materials
+    if is_color_changed {
+        light.color = Color::srgb(rgb[0], rgb[1], rgb[2]);
+    }
+
+    ui.checkbox(&mut light.shadows, "Shadows");
+}
+
+/// Returns whether the material was edited.
+fn material_ui(
+    ui: &mut egui::Ui,
+    material: &mut Material,
+    ray_tracing_shaders: &RayTracingShaders,
+) -> bool {
+    let original = material.clone();
+    let srgba = material.base_color.to_srgba();
+    let mut rgba = [srgba.red, srgba.green, srgba.blue, srgba.alpha];
+
+    let is_color_changed = ui
+        .horizontal(|ui| {
+            ui.label("Base color:");
+            ui.color_edit_button_rgba_unmultiplied(&mut rgba).changed()
+        })
+        .inner;
+
+    // The color is only replaced when edited, since the sRGB conversion does not round trip
+    // exactly for colors in other spaces
+    if is_color_changed {
+        material.base_color = Color::srgba(rgba[0], rgba[1], rgba[2], rgba[3]);
+    }
+
+    if material.base_color_texture.is_some() {
+        ui.horizontal(|ui| {
+            ui.label("Base color texture");
+
+            if ui.button("Remove").clicked() {
+                material.base_color_texture = None;
+            }
+        });
+    }
+
+    egui::ComboBox::from_label("Alpha mode")
+        .selected_text(material.alpha_mode.name())
+        .show_ui(ui, |ui| {
+            for alpha_mode in alpha_modes() {
+                let selected = material.alpha_mode.name() == alpha_mode.name();
+
+                if ui.selectable_label(selected, alpha_mode.name()).clicked() {
+                    material.alpha_mode = alpha_mode;
+                }
+            }
+        });
+
+    if let AlphaMode::Mask { cutoff } = &mut material.alpha_mode {
+        ui.label("Alpha cutoff:");
+        ui.add(egui::Slider::new(cutoff, 0.0..=1.0));
+    }
+
+    let hit_group_name = |index: usize| {
+        ray_tracing_shaders
+            .hit_groups
+            .get(index)
+            .map_or("Missing", |hit_group| hit_group.closest_hit.as_ref())
+            .to_owned()
+    };
+
+    egui::ComboBox::from_label("Hit group")
+        .selected_text(hit_group_name(material.hit_group as usize))
+        .show_ui(ui, |ui| {
+            for index in 0..ray_tracing_shaders.hit_groups.len() {
+                ui.selectable_value(&mut material.hit_group, index as u32, hit_group_name(index));
+            }
+        });
+
+    let callable_name = |callable: Option<u32>| match callable {
+        Some(index) => ray_tracing_shaders
+            .callables
+            .get(index as usize)
+            .map_or("Missing", |callable| callable.as_ref())
+            .to_owned(),
+        None => "None".to_owned(),
+    };
+
+    egui::ComboBox::from_label("Callable")
+        .selected_text(callable_name(material.callable))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut material.callable, None, callable_name(None));
+
+            for index in 0..ray_tracing_shaders.callables.len() as u32 {
+                ui.selectable_value(
+                    &mut material.callable,
+                    Some(index),
+                    callable_name(Some(index)),
+                );
+            }
+        });
+
+    *material != original
+}
+
+fn render_settings(ctx: Res<EguiContext>, mut settings: ResMut<RayTracingSettings>) {
+    egui::Window::new("Ray Tracing")
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .show(&ctx, |ui| {
+            let mut edited = settings.clone();
+
+            ui.label("Resolution scaling:");
+            ui.add(
+                egui::Slider::new(&mut edited.resolution_scaling, 0.25..=2.0)
+                    .step_by(0.05)
+                    .custom_formatter(|value, _| format!("{:.0}%", value * 100.0)),
+            );
+
+            ui.checkbox(&mut edited.accumulate, "Accumulate");
+
+            egui::ComboBox::from_label("Debug View")
+                .selected_text(edited.debug_view.name())
+                .show_ui(ui, |ui| {
+                    for debug_view in DebugView::ALL {
+                        ui.selectable_value(&mut edited.debug_view, debug_view, debug_view.name());
+                    }
+                });
+
+            settings.set_if_neq(edited);
+        });
+}
+
+fn alpha_modes() -> [AlphaMode; 3] {
+    [
+        AlphaMode::Opaque,
+        AlphaMode::Mask { cutoff: 0.5 },
+        AlphaMode::Blend,
+    ]
+}
+
+fn projections() -> [Projection; 7] {
+    let fisheye = |model| Projection::Fisheye { model, fov: PI };
+
+    [
+        Projection::Perspective,
+        Projection::Orthographic { height: 20.0 },
+        Projection::Equirectangular,
+        Projection::Cubemap,
+        fisheye(FisheyeModel::Equidistant),
+        fisheye(FisheyeModel::Equisolid),
+        fisheye(FisheyeModel::Stereographic),
+    ]
+}
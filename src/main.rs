@@ -1,61 +1,231 @@
 mod flycam;
+mod gizmo;
 mod gltf;
+mod inspector;
 mod panic;
+mod scene;
+mod selection;
+mod shader_diagnostics;
 
+use std::{
+    ffi::OsString,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow, bail};
 use bevy::{
+    app::ScheduleRunnerPlugin,
+    asset::UnapprovedPathMode,
     diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
     prelude::*,
+    window::ExitCondition,
+    winit::WinitPlugin,
 };
 use luma_render::{
     RendererPlugin,
-    camera::Camera,
-    egui_renderer::{EguiContext, EguiPass, EguiPassSystems, EguiPlugin},
-    ray_tracing::{RayTracingPlugin, RayTracingShaders},
+    camera::{Camera, CameraTarget},
+    egui_renderer::{
+        EguiContext, EguiPass, EguiPassSystems, EguiPlugin, EguiTexture, egui_wants_keyboard_input,
+    },
+    light::DirectionalLight,
+    material::Material,
+    picking::Pick,
+    procedural::{Procedural3d, ProceduralGeometry, ProceduralPrimitive},
+    ray_tracing::{HitGroupShaders, RayTracingPlugin, RayTracingShaders},
+    render_target::Headless,
+    screenshot::{ImageFileFormat, ImageSequence, Screenshot, ScreenshotPlugin, ScreenshotSource},
+    tonemapping::{Tonemapper, Tonemapping},
 };
 
 use crate::{
     flycam::{Flycam, FlycamPlugin},
-    gltf::GltfPlugin,
+    gizmo::{Gizmo, GizmoPlugin},
+    inspector::InspectorPlugin,
+    scene::{LoadScene, ScenePlugin, SceneRoot, open_file_dialog},
+    selection::SelectionPlugin,
+    shader_diagnostics::ShaderDiagnosticsPlugin,
 };
 
+/// Number of frames accumulated before the screenshot of a headless run is taken.
+const HEADLESS_FRAMES: u32 = 64;
+
 fn main() -> AppExit {
     panic::init_hook();
 
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Luma".to_owned(),
-                ..default()
-            }),
-            ..default()
-        }))
+    let args = match Args::parse(std::env::args_os().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!("Usage: luma [--headless] [--output PATH] [SCENE]");
+            return AppExit::error();
+        }
+    };
+
+    let asset_plugin = AssetPlugin {
+        // Scenes are opened from anywhere on disk
+        unapproved_path_mode: UnapprovedPathMode::Allow,
+        ..default()
+    };
+
+    let mut app = App::new();
+
+    if args.headless {
+        // Without winit nothing drives the frames or closes the app, so do both here
+        app.add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .set(asset_plugin)
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+        ))
+        .add_systems(Update, capture_headless);
+    } else {
+        app.add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: "Luma".to_owned(),
+                        ..default()
+                    }),
+                    ..default()
+                })
+                .set(asset_plugin),
+        );
+    }
+
+    app.insert_resource(args.clone())
         .add_plugins(FrameTimeDiagnosticsPlugin::default())
-        .add_plugins(RendererPlugin)
+        .add_plugins(RendererPlugin {
+            headless: args.headless.then(Headless::default),
+        })
         .add_plugins(RayTracingPlugin {
             shaders: RayTracingShaders {
                 raygen: "shaders/raygen.slang".into(),
                 miss: "shaders/miss.slang".into(),
-                closest_hit: "shaders/closest-hit.slang".into(),
+                shadow_miss: "shaders/shadow-miss.slang".into(),
+                hit_groups: vec![
+                    HitGroupShaders {
+                        closest_hit: "shaders/closest-hit.slang".into(),
+                        any_hit: Some("shaders/any-hit.slang".into()),
+                        shadow_any_hit: Some("shaders/shadow-any-hit.slang".into()),
+                        intersection: None,
+                    },
+                    HitGroupShaders {
+                        closest_hit: "shaders/shape-closest-hit.slang".into(),
+                        any_hit: None,
+                        shadow_any_hit: None,
+                        intersection: Some("shaders/shape-intersection.slang".into()),
+                    },
+                ],
+                callables: vec!["shaders/toon.slang".into()],
+                defines: Vec::new(),
+                specialization_constants: Vec::new(),
             },
             settings: default(),
         })
+        .add_plugins(ScreenshotPlugin)
         .add_plugins(EguiPlugin)
         .add_plugins(FlycamPlugin)
-        .add_plugins(GltfPlugin)
+        .add_plugins(ScenePlugin)
+        .add_plugins(SelectionPlugin)
+        .add_plugins(InspectorPlugin)
+        .add_plugins(GizmoPlugin)
+        .add_plugins(ShaderDiagnosticsPlugin)
         .add_systems(Startup, setup)
+        .add_systems(
+            Update,
+            take_screenshot.run_if(not(egui_wants_keyboard_input)),
+        )
         .add_systems(EguiPass, render_ui.in_set(EguiPassSystems::Render))
         .run()
 }
 
-fn setup(mut commands: Commands, mut assets: ResMut<Assets<Mesh>>) {
+/// Command line arguments, `luma [--headless] [--output PATH] [SCENE]`.
+#[derive(Resource, Clone, Default, Debug)]
+struct Args {
+    /// glTF file opened instead of the demo scene.
+    scene: Option<PathBuf>,
+    /// Renders without a window, saves a screenshot after `HEADLESS_FRAMES` frames and exits.
+    headless: bool,
+    /// Path of the headless screenshot, a timestamped PNG in `screenshots` by default.
+    output: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--headless") => parsed.headless = true,
+                Some("--output") => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing path after --output"))?;
+
+                    parsed.output = Some(path.into());
+                }
+                Some(option) if option.starts_with("--") => bail!("Unknown option {option}"),
+                _ => parsed.scene = Some(arg.into()),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Camera shown in the viewport panel, among any other cameras the scene may have.
+#[derive(Component)]
+struct ViewportCamera;
+
+fn setup(
+    mut commands: Commands,
+    args: Res<Args>,
+    mut assets: ResMut<Assets<Mesh>>,
+    mut procedural_geometries: ResMut<Assets<ProceduralGeometry>>,
+    mut load_scene: MessageWriter<LoadScene>,
+) {
+    // Rendered into the viewport panel, which resizes the target to fit
     commands.spawn((
-        Camera::default(),
-        Flycam,
+        Name::new("Camera"),
+        Camera {
+            target: CameraTarget::Egui {
+                width: 1280,
+                height: 720,
+            },
+            ..default()
+        },
+        ViewportCamera,
+        Flycam::default(),
         Transform::from_xyz(0.0, 10.0, 20.0).looking_at(Vec3::ZERO, Vec3::Y),
     ));
 
+    // Kept when scenes are loaded, which only replace the entities below the scene root
+    commands.spawn((
+        Name::new("Sun"),
+        DirectionalLight {
+            shadows: true,
+            ..default()
+        },
+        Transform::default().looking_to(-Vec3::new(0.4, 1.0, 0.3), Vec3::Y),
+    ));
+
+    if let Some(path) = &args.scene {
+        load_scene.write(LoadScene(path.clone()));
+        return;
+    }
+
     let cube = assets.add(Cuboid::new(1.0, 1.0, 1.0).mesh().build());
     let sphere = assets.add(Sphere::new(0.5).mesh().build());
+    let root = commands
+        .spawn((Name::new("Demo"), Transform::IDENTITY, SceneRoot))
+        .id();
 
     for z in -5..=5 {
         let z = -z as f32 * 2.0;
@@ -63,16 +233,145 @@ fn setup(mut commands: Commands, mut assets: ResMut<Assets<Mesh>>) {
         for x in -5..=5 {
             let x = x as f32 * 2.0;
 
-            commands.spawn((Transform::from_xyz(x, 0.0, z), Mesh3d(cube.clone())));
-            commands.spawn((Transform::from_xyz(x, 2.0, z), Mesh3d(sphere.clone())));
+            commands.spawn((
+                Name::new(format!("Cube ({x}, {z})")),
+                Transform::from_xyz(x, 0.0, z),
+                Mesh3d(cube.clone()),
+                ChildOf(root),
+            ));
+
+            commands.spawn((
+                Name::new(format!("Sphere ({x}, {z})")),
+                Transform::from_xyz(x, 2.0, z),
+                Mesh3d(sphere.clone()),
+                Material {
+                    base_color: Color::hsl((x + z) * 9.0 + 180.0, 0.6, 0.6),
+                    // Every other sphere is evaluated by the toon callable
+                    callable: ((x + z) as i32 % 4 == 0).then_some(0),
+                    ..default()
+                },
+                ChildOf(root),
+            ));
         }
     }
+
+    // A helix of analytic spheres, traced with the intersection shader of the second hit group
+    let point_cloud = ProceduralGeometry::spheres((0..2000).map(|i| {
+        let t = i as f32 / 2000.0;
+        let angle = t * std::f32::consts::TAU * 8.0;
+        let center = Vec3::new(angle.cos() * 3.0, t * 6.0, angle.sin() * 3.0);
+        (center, 0.05 + t * 0.1)
+    }));
+
+    commands.spawn((
+        Name::new("Point Cloud"),
+        Transform::from_xyz(0.0, 4.0, 0.0),
+        Procedural3d(procedural_geometries.add(point_cloud)),
+        Material {
+            base_color: Color::srgb(1.0, 0.8, 0.4),
+            hit_group: 1,
+            ..default()
+        },
+        ChildOf(root),
+    ));
+
+    // A trefoil knot made of a curve, above the helix
+    let knot = ProceduralGeometry::curve(
+        (0..=600).map(|i| {
+            let t = i as f32 / 600.0 * std::f32::consts::TAU;
+            Vec3::new(
+                t.sin() + 2.0 * (2.0 * t).sin(),
+                -(3.0 * t).sin(),
+                t.cos() - 2.0 * (2.0 * t).cos(),
+            ) * 0.5
+        }),
+        0.1,
+    );
+
+    commands.spawn((
+        Name::new("Knot"),
+        Transform::from_xyz(0.0, 12.0, 0.0),
+        Procedural3d(procedural_geometries.add(knot)),
+        Material {
+            base_color: Color::srgb(0.4, 0.8, 1.0),
+            hit_group: 1,
+            ..default()
+        },
+        ChildOf(root),
+    ));
+
+    // Signed distance fields, sphere traced by the same intersection shader
+    let sdfs = ProceduralGeometry {
+        primitives: vec![
+            ProceduralPrimitive::torus(Vec3::new(-1.5, 0.0, 0.0), 0.6, 0.2),
+            ProceduralPrimitive::rounded_box(Vec3::new(1.5, 0.0, 0.0), Vec3::splat(0.5), 0.15),
+        ],
+    };
+
+    commands.spawn((
+        Name::new("SDFs"),
+        Transform::from_xyz(0.0, 4.0, 0.0),
+        Procedural3d(procedural_geometries.add(sdfs)),
+        Material {
+            base_color: Color::srgb(0.8, 0.5, 1.0),
+            hit_group: 1,
+            ..default()
+        },
+        ChildOf(root),
+    ));
+}
+
+/// Takes the screenshot of a headless run and exits once it has been written.
+fn capture_headless(
+    args: Res<Args>,
+    mut screenshots: MessageWriter<Screenshot>,
+    mut app_exit: MessageWriter<AppExit>,
+    mut frame: Local<u32>,
+) {
+    *frame += 1;
+
+    if *frame == HEADLESS_FRAMES {
+        let path = args
+            .output
+            .clone()
+            .unwrap_or_else(|| screenshot_path(ImageFileFormat::Png));
+
+        screenshots.write(Screenshot::new(path));
+    }
+
+    // The screenshot is copied in the next frame at the latest, and saved in the one after
+    if *frame == HEADLESS_FRAMES + 2 {
+        app_exit.write(AppExit::Success);
+    }
 }
 
+fn take_screenshot(keys: Res<ButtonInput<KeyCode>>, mut screenshots: MessageWriter<Screenshot>) {
+    if keys.just_pressed(KeyCode::F12) {
+        screenshots.write(Screenshot::new(screenshot_path(ImageFileFormat::Png)));
+    }
+}
+
+fn screenshot_path(format: ImageFileFormat) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    PathBuf::from("screenshots").join(format!("{timestamp}.{}", format.extension()))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn render_ui(
+    mut commands: Commands,
     ctx: Res<EguiContext>,
     diagnostics: Res<DiagnosticsStore>,
-    mut camera: Query<&mut Camera>,
+    image_sequence: Option<Res<ImageSequence>>,
+    mut screenshots: MessageWriter<Screenshot>,
+    mut tonemapping: ResMut<Tonemapping>,
+    mut picks: MessageWriter<Pick>,
+    mut load_scene: MessageWriter<LoadScene>,
+    mut camera: Query<(Entity, &mut Camera, Option<&EguiTexture>), With<ViewportCamera>>,
+    mut gizmo: Gizmo,
 ) {
     egui::Window::new("Stats")
         .collapsible(false)
@@ -90,18 +389,121 @@ fn render_ui(
             }
         });
 
-    if let Ok(mut camera) = camera.single_mut() {
-        egui::Window::new("Camera")
-            .collapsible(false)
-            .resizable(false)
-            .movable(false)
+    // Panels have to be added before the central panel showing the viewport
+    egui::TopBottomPanel::top("Menu").show(&ctx, |ui| {
+        egui::MenuBar::new().ui(ui, |ui| {
+            ui.menu_button("File", |ui| {
+                if ui.button("Open...").clicked()
+                    && let Some(path) = open_file_dialog()
+                {
+                    load_scene.write(LoadScene(path));
+                }
+            });
+        });
+    });
+
+    if let Ok((entity, mut camera, texture)) = camera.single_mut() {
+        egui::CentralPanel::default()
+            .frame(egui::Frame::NONE)
             .show(&ctx, |ui| {
-                ui.label("Focal length:");
-                ui.add(
-                    egui::Slider::new(&mut camera.focal_length, 10.0..=70.0)
-                        .step_by(1.0)
-                        .custom_formatter(|value, _| format!("{:.0} mm", value)),
-                );
+                let size = ui.available_size();
+                let pixels_per_point = ui.ctx().pixels_per_point();
+
+                let target = CameraTarget::Egui {
+                    width: (size.x * pixels_per_point).round().max(1.0) as u32,
+                    height: (size.y * pixels_per_point).round().max(1.0) as u32,
+                };
+
+                if camera.target != target {
+                    camera.target = target;
+                }
+
+                let Some(texture) = texture else {
+                    return;
+                };
+
+                let response =
+                    ui.add(egui::Image::new((**texture, size)).sense(egui::Sense::click()));
+
+                if let Some(pointer) = response
+                    .interact_pointer_pos()
+                    .filter(|_| response.clicked())
+                {
+                    let position = (pointer - response.rect.min) / response.rect.size();
+
+                    picks.write(Pick {
+                        camera: entity,
+                        position: Vec2::new(position.x, position.y),
+                    });
+                }
+
+                gizmo.show(ui, response.rect, entity, &camera);
             });
     }
+
+    egui::Window::new("Tonemapping")
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .show(&ctx, |ui| {
+            egui::ComboBox::from_label("Tonemapper")
+                .selected_text(tonemapping.tonemapper.name())
+                .show_ui(ui, |ui| {
+                    for tonemapper in Tonemapper::ALL {
+                        ui.selectable_value(
+                            &mut tonemapping.tonemapper,
+                            tonemapper,
+                            tonemapper.name(),
+                        );
+                    }
+                });
+
+            ui.label("Exposure:");
+            ui.add(
+                egui::Slider::new(&mut tonemapping.exposure, -10.0..=10.0)
+                    .step_by(0.1)
+                    .custom_formatter(|value, _| format!("{:+.1} EV", value)),
+            );
+        });
+
+    egui::Window::new("Capture")
+        .collapsible(false)
+        .resizable(false)
+        .movable(false)
+        .show(&ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Save PNG").clicked() {
+                    screenshots.write(Screenshot::new(screenshot_path(ImageFileFormat::Png)));
+                }
+
+                if ui.button("Save EXR").clicked() {
+                    screenshots.write(Screenshot {
+                        path: screenshot_path(ImageFileFormat::Exr),
+                        source: ScreenshotSource::RayTracing,
+                        camera: None,
+                    });
+                }
+            });
+
+            match image_sequence {
+                Some(image_sequence) => {
+                    ui.label(format!("Recording frame {}", image_sequence.frame));
+
+                    if ui.button("Stop recording").clicked() {
+                        commands.remove_resource::<ImageSequence>();
+                    }
+                }
+                None => {
+                    if ui.button("Record sequence").clicked() {
+                        let timestamp = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs();
+
+                        let directory = PathBuf::from("sequences").join(timestamp.to_string());
+                        commands.insert_resource(ImageSequence::new(directory, 30.0));
+                    }
+                }
+            }
+        });
 }
@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+
+use bevy::{asset::LoadState, math::Affine3A, prelude::*, window::FileDragAndDrop};
+use luma_render::camera::Camera;
+use native_dialog::DialogBuilder;
+
+use crate::{
+    flycam::{Flycam, OrbitCam},
+    gltf::{Gltf, GltfPlugin},
+    selection::Selection,
+};
+
+pub struct ScenePlugin;
+
+impl Plugin for ScenePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(GltfPlugin)
+            .add_message::<LoadScene>()
+            .init_resource::<PendingScene>()
+            .add_systems(
+                Update,
+                (load_dropped_files, load_scene, spawn_scene).chain(),
+            );
+    }
+}
+
+/// Replaces the current scene with the glTF file at the given path once it has loaded. Relative
+/// paths are resolved against the working directory rather than the assets directory.
+#[derive(Message, Clone, Debug)]
+pub struct LoadScene(pub PathBuf);
+
+/// Marks the root entities of the current scene, which are despawned along with their
+/// descendants when another scene is loaded.
+#[derive(Component)]
+pub struct SceneRoot;
+
+#[derive(Resource, Default)]
+struct PendingScene(Option<Handle<Gltf>>);
+
+/// Asks the user for a glTF file to open, blocking until the dialog is closed.
+pub fn open_file_dialog() -> Option<PathBuf> {
+    DialogBuilder::file()
+        .set_title("Open Scene")
+        .add_filter("glTF", ["gltf", "glb"])
+        .open_single_file()
+        .show()
+        .inspect_err(|err| error!("Failed to show file dialog: {err}"))
+        .ok()
+        .flatten()
+}
+
+fn load_dropped_files(
+    mut drag_and_drop: MessageReader<FileDragAndDrop>,
+    mut load_scene: MessageWriter<LoadScene>,
+) {
+    for message in drag_and_drop.read() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = message {
+            load_scene.write(LoadScene(path_buf.clone()));
+        }
+    }
+}
+
+fn load_scene(
+    asset_server: Res<AssetServer>,
+    mut pending_scene: ResMut<PendingScene>,
+    mut load_scene: MessageReader<LoadScene>,
+) {
+    // Only the most recent request is loaded, earlier ones are superseded
+    if let Some(LoadScene(path)) = load_scene.read().last() {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        info!("Loading scene {}", path.display());
+        pending_scene.0 = Some(asset_server.load(path));
+    }
+}
+
+fn spawn_scene(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    gltfs: Res<Assets<Gltf>>,
+    mut pending_scene: ResMut<PendingScene>,
+    mut selection: ResMut<Selection>,
+    scene_roots: Query<Entity, With<SceneRoot>>,
+    mut cameras: Query<(
+        &mut Transform,
+        &mut Camera,
+        Option<&mut Flycam>,
+        Option<&mut OrbitCam>,
+    )>,
+) {
+    let Some(handle) = &pending_scene.0 else {
+        return;
+    };
+
+    if let Some(LoadState::Failed(err)) = asset_server.get_load_state(handle) {
+        error!("Failed to load scene: {err}");
+        pending_scene.0 = None;
+        return;
+    }
+
+    let Some(gltf) = gltfs.get(handle) else {
+        return;
+    };
+
+    for entity in &scene_roots {
+        commands.entity(entity).despawn();
+    }
+
+    selection.entity = None;
+    selection.hit = None;
+
+    let mut bounds = None;
+
+    for &node in &gltf.roots {
+        let entity = spawn_node(&mut commands, gltf, node, Affine3A::IDENTITY, &mut bounds);
+        commands.entity(entity).insert(SceneRoot);
+    }
+
+    if let Some((min, max)) = bounds {
+        for (mut transform, mut camera, flycam, orbit_cam) in &mut cameras {
+            frame_bounds(&mut transform, &mut camera, flycam, orbit_cam, min, max);
+        }
+    }
+
+    pending_scene.0 = None;
+}
+
+/// Spawns a node and its descendants, growing `bounds` by the world space bounds of its meshes.
+fn spawn_node(
+    commands: &mut Commands,
+    gltf: &Gltf,
+    index: usize,
+    parent_transform: Affine3A,
+    bounds: &mut Option<(Vec3, Vec3)>,
+) -> Entity {
+    let node = &gltf.nodes[index];
+    let transform = parent_transform * node.transform.compute_affine();
+    let name = node.name.clone().unwrap_or_else(|| format!("Node {index}"));
+    let entity = commands.spawn((Name::new(name), node.transform)).id();
+
+    let primitives = node
+        .mesh
+        .and_then(|mesh| gltf.meshes.get(mesh))
+        .map(|mesh| mesh.primitives.as_slice())
+        .unwrap_or_default();
+
+    for (primitive_index, primitive) in primitives.iter().enumerate() {
+        // Single primitives live on the node itself, multiple ones on a child entity each
+        let primitive_entity = if primitives.len() == 1 {
+            entity
+        } else {
+            commands
+                .spawn((
+                    Name::new(format!("Primitive {primitive_index}")),
+                    Transform::IDENTITY,
+                    ChildOf(entity),
+                ))
+                .id()
+        };
+
+        let mut primitive_commands = commands.entity(primitive_entity);
+        primitive_commands.insert(Mesh3d(primitive.mesh.clone()));
+
+        if let Some(material) = primitive
+            .material
+            .and_then(|material| gltf.materials.get(material))
+        {
+            primitive_commands.insert(material.clone());
+        }
+
+        for corner in 0..8 {
+            let select = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+            let corner =
+                transform.transform_point3(Vec3::select(select, primitive.max, primitive.min));
+
+            *bounds = Some(match *bounds {
+                Some((min, max)) => (min.min(corner), max.max(corner)),
+                None => (corner, corner),
+            });
+        }
+    }
+
+    for &child in &node.children {
+        let child = spawn_node(commands, gltf, child, transform, bounds);
+        commands.entity(child).insert(ChildOf(entity));
+    }
+
+    entity
+}
+
+/// Moves the camera back along its view direction until the bounding sphere of `min` and `max`
+/// fits into its vertical field of view, and focuses on the centre.
+fn frame_bounds(
+    transform: &mut Transform,
+    camera: &mut Camera,
+    flycam: Option<Mut<Flycam>>,
+    orbit_cam: Option<Mut<OrbitCam>>,
+    min: Vec3,
+    max: Vec3,
+) {
+    let center = (min + max) * 0.5;
+    let radius = ((max - min).length() * 0.5).max(0.01);
+    let half_fov = camera.sensor.vertical_fov(camera.focal_length) * 0.5;
+    let distance = radius / half_fov.sin();
+
+    transform.translation = center - transform.forward() * distance;
+    camera.focus_distance = distance;
+
+    if let Some(mut flycam) = flycam {
+        flycam.velocity = Vec3::ZERO;
+    }
+
+    if let Some(mut orbit_cam) = orbit_cam {
+        orbit_cam.focus = center;
+        orbit_cam.distance = distance;
+    }
+}
@@ -0,0 +1,34 @@
+use bevy::prelude::*;
+use luma_render::picking::{PickHit, PickResult};
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_systems(Update, update_selection);
+    }
+}
+
+/// The entity that was last clicked in the viewport or outliner.
+#[derive(Resource, Default)]
+pub struct Selection {
+    pub entity: Option<Entity>,
+    /// The surface under the cursor when the entity was picked in the viewport.
+    pub hit: Option<PickHit>,
+}
+
+impl Selection {
+    pub fn select(&mut self, entity: Entity) {
+        self.entity = Some(entity);
+        self.hit = None;
+    }
+}
+
+fn update_selection(mut selection: ResMut<Selection>, mut pick_results: MessageReader<PickResult>) {
+    // Only the latest click matters, clicking into empty space clears the selection
+    if let Some(pick_result) = pick_results.read().last() {
+        selection.entity = pick_result.hit.map(|hit| hit.entity);
+        selection.hit = pick_result.hit;
+    }
+}
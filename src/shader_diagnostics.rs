@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+use luma_render::{
+    egui_renderer::{EguiContext, EguiPass, EguiPassSystems},
+    shader::ShaderDiagnostics,
+};
+
+pub struct ShaderDiagnosticsPlugin;
+
+impl Plugin for ShaderDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            EguiPass,
+            render_shader_diagnostics.in_set(EguiPassSystems::Render),
+        );
+    }
+}
+
+/// Shows the errors of broken shaders on top of the viewport until they are fixed.
+fn render_shader_diagnostics(ctx: Res<EguiContext>, diagnostics: Res<ShaderDiagnostics>) {
+    if diagnostics.is_empty() {
+        return;
+    }
+
+    egui::Area::new("Shader Diagnostics".into())
+        .anchor(egui::Align2::LEFT_BOTTOM, [8.0, -8.0])
+        .order(egui::Order::Foreground)
+        .interactable(false)
+        .show(&ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                let color = ui.visuals().error_fg_color;
+
+                for (origin, diagnostics) in diagnostics.iter() {
+                    ui.label(egui::RichText::new(origin).strong().color(color));
+
+                    for diagnostic in diagnostics {
+                        let location = match (&diagnostic.file, diagnostic.line) {
+                            (Some(file), Some(line)) => format!("{file}:{line}: "),
+                            (Some(file), None) => format!("{file}: "),
+                            _ => String::new(),
+                        };
+
+                        ui.label(
+                            egui::RichText::new(format!("{location}{}", diagnostic.message))
+                                .monospace(),
+                        );
+                    }
+                }
+            });
+        });
+}
@@ -0,0 +1,23 @@
+//! Smoke test of the headless mode, which renders the demo scene without a window.
+
+use std::process::Command;
+
+#[test]
+#[ignore = "requires a Vulkan device with ray tracing support"]
+fn renders_demo_scene_to_screenshot() {
+    let output = std::env::temp_dir().join("luma-headless-smoke-test.png");
+    let _ = std::fs::remove_file(&output);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_luma"))
+        .arg("--headless")
+        .arg("--output")
+        .arg(&output)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("Failed to run luma");
+
+    assert!(status.success(), "luma exited with {status}");
+
+    let metadata = std::fs::metadata(&output).expect("Screenshot was not written");
+    assert!(metadata.len() > 0, "Screenshot is empty");
+}